@@ -0,0 +1,56 @@
+//! Recall and query latency of `HnswVectorStore` against exact search in `InMemoryVectorStore`.
+//!
+//! Run with `cargo bench --bench hnsw [-- <vectors>]`; defaults to 100k random 64-dim vectors.
+
+use std::{collections::HashSet, time::{Duration, Instant}};
+
+use forge::vector::{ByteHistogramEmbedder, HnswVectorStore, InMemoryVectorStore, VectorStore};
+
+const DIM: usize = 64;
+const K: usize = 10;
+const QUERIES: usize = 200;
+
+fn random_vectors(n: usize, mut seed: u64) -> Vec<Vec<f32>> {
+    let mut next = move || {
+        seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+    };
+    (0..n).map(|_| (0..DIM).map(|_| next()).collect()).collect()
+}
+
+#[tokio::main]
+async fn main() {
+    let n = std::env::args().skip(1).find_map(|a| a.parse().ok()).unwrap_or(100_000);
+    let data = random_vectors(n, 1);
+    let queries = random_vectors(QUERIES, 2);
+
+    let mut exact = InMemoryVectorStore::new(DIM);
+    let start = Instant::now();
+    for (i, v) in data.iter().enumerate() { exact.upsert(i.to_string(), v.clone()).await.expect("upsert"); }
+    println!("in-memory build: {:>10.2?} ({n} vectors)", start.elapsed());
+
+    let mut hnsw = HnswVectorStore::new(ByteHistogramEmbedder::new(DIM), 16, 200);
+    let start = Instant::now();
+    for (i, v) in data.into_iter().enumerate() { hnsw.upsert(i.to_string(), v).await.expect("upsert"); }
+    println!("hnsw build:      {:>10.2?}", start.elapsed());
+
+    let mut truth = Vec::with_capacity(QUERIES);
+    let start = Instant::now();
+    for q in &queries { truth.push(exact.search(q, K).await.into_iter().map(|(id, _)| id).collect::<HashSet<_>>()); }
+    println!("in-memory query: {:>10.2?}/query", start.elapsed() / QUERIES as u32);
+
+    for ef in [64, 128, 256] {
+        hnsw = hnsw.with_ef_search(ef);
+        let (mut elapsed, mut hits) = (Duration::ZERO, 0);
+        for (q, truth) in queries.iter().zip(&truth) {
+            let start = Instant::now();
+            let found = hnsw.search(q, K).await;
+            elapsed += start.elapsed();
+            hits += found.iter().filter(|(id, _)| truth.contains(id)).count();
+        }
+        println!("hnsw ef={ef:<4} query: {:>10.2?}/query, recall@{K} {:.3}", elapsed / QUERIES as u32, hits as f32 / (QUERIES * K) as f32);
+    }
+}
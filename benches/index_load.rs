@@ -0,0 +1,55 @@
+//! Load time of a saved `ForgeIndex` as JSON, gzip-compressed JSON and bincode.
+//!
+//! Run with `cargo bench --bench index_load [-- <files>]`; defaults to an index of 2000
+//! generated markdown files, built with the stub LLM's heuristic extraction.
+
+use std::{fs, path::Path, time::{Duration, Instant}};
+
+use forge::{llm::LLMEngine, rag::{ForgeIndex, Indexer}};
+
+const RUNS: u32 = 5;
+
+fn write_corpus(dir: &Path, files: usize) {
+    for i in 0..files {
+        let text = format!(
+            "# Note {i}\n\nAda Lovelace{a} met Charles Babbage{b} in London{c}. They discussed the Analytical Engine{a} at length.\n\n\
+             Grace Hopper{b} later wrote the first compiler{c}, and Alan Turing{a} described computable numbers.\n",
+            a = i % 97,
+            b = i % 89,
+            c = i % 83,
+        );
+        fs::write(dir.join(format!("note{i}.md")), text).expect("write corpus file");
+    }
+}
+
+fn time_load(path: &Path) -> (Duration, ForgeIndex) {
+    let mut index = ForgeIndex::load(path).expect("load index");
+    let start = Instant::now();
+    for _ in 0..RUNS { index = ForgeIndex::load(path).expect("load index"); }
+    (start.elapsed() / RUNS, index)
+}
+
+#[tokio::main]
+async fn main() {
+    let files = std::env::args().skip(1).find_map(|a| a.parse().ok()).unwrap_or(2000);
+    let dir = tempfile::tempdir().expect("temp dir");
+    let corpus = dir.path().join("corpus");
+    fs::create_dir(&corpus).expect("corpus dir");
+    write_corpus(&corpus, files);
+
+    let start = Instant::now();
+    let (index, _) = Indexer::new(LLMEngine::new()).index_directory(&corpus).await.expect("index corpus");
+    println!("indexed {} chunks, {} nodes, {} edges in {:.2?}", index.chunks.len(), index.graph.nodes.len(), index.graph.edges.len(), start.elapsed());
+
+    let expected = serde_json::to_value(&index).expect("serialize index");
+    for name in ["index.json", "index.json.gz", "index.bin"] {
+        let path = dir.path().join(name);
+        let start = Instant::now();
+        index.save(&path).expect("save index");
+        let saved = start.elapsed();
+        let (load, loaded) = time_load(&path);
+        assert_eq!(serde_json::to_value(&loaded).expect("serialize index"), expected, "{name} changed the index");
+        let size = fs::metadata(&path).expect("index metadata").len();
+        println!("{name:<14} {:>8} KiB  save {:>10.2?}  load {:>10.2?}", size / 1024, saved, load);
+    }
+}
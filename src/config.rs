@@ -7,7 +7,47 @@ pub struct Config {
     pub device: Option<String>,
     pub chunk_tokens: Option<usize>,
     pub chunk_overlap: Option<usize>,
+    /// `words` or `sentences`.
+    pub chunk_mode: Option<String>,
     pub tokenizer_json: Option<PathBuf>,
+    /// Split CSV rows / JSONL records that exceed `chunk_tokens` into several chunks.
+    pub split_large_rows: Option<bool>,
+    /// PDF pages with fewer extracted characters are OCR'd (`ocr` feature).
+    pub ocr_min_chars: Option<usize>,
+    /// Files parsed and extracted concurrently by `forge index`.
+    pub index_jobs: Option<usize>,
+    /// Most extraction LLM calls in flight at once during `forge index`.
+    pub max_concurrency: Option<usize>,
+    /// Gitignore-style globs left out by `forge index`, on top of `.gitignore` files.
+    pub exclude: Option<Vec<String>>,
+    /// When set, `forge index` only indexes files matching one of these globs.
+    pub include: Option<Vec<String>>,
+    /// Extracted entities with a lower confidence (0 to 1) are left out of the graph.
+    pub min_confidence: Option<f32>,
+    /// Chunks retrieved per query.
+    pub top_k: Option<usize>,
+    /// Dimension of the byte-histogram embeddings used for new vector stores.
+    pub embedding_dim: Option<usize>,
+    /// `label_propagation` or `louvain`.
+    pub community_alg: Option<String>,
+    /// Share of the vector ranking in hybrid retrieval: 0 is BM25 only, 1 is vector search only.
+    pub hybrid_weight: Option<f32>,
+    /// Model context window the answer prompt must fit in; unset includes all retrieved context.
+    pub max_context_tokens: Option<usize>,
+    /// `[prompts]` table overriding individual extraction/answer templates.
+    pub prompts: Option<crate::prompts::Prompts>,
+    /// `[rel_types]` table mapping canonical relationship types to the synonyms folded into them.
+    pub rel_types: Option<std::collections::BTreeMap<String, Vec<String>>>,
+    /// Directory for cached LLM responses (default `.forge-cache`; `--no-cache` disables).
+    pub cache_dir: Option<PathBuf>,
+    /// Also cache responses sampled with a nonzero temperature.
+    pub cache_sampled: Option<bool>,
+    /// `[retry]` table: backoff for HTTP model backends and model downloads.
+    pub retry: Option<crate::llm::RetryPolicy>,
+    /// Seconds one LLM call may take (default 120); 0 disables the limit.
+    pub llm_timeout_secs: Option<u64>,
+    /// Chat format for prompts: `plain`, `chatml`, `llama3` or `mistral`; detected from the model when unset.
+    pub chat_template: Option<String>,
 }
 
 impl Config {
@@ -16,4 +56,157 @@ impl Config {
         let cfg: Config = toml::from_str(&data)?;
         Ok(cfg)
     }
+
+    /// Checks values that would otherwise surface as empty chunks or late backend failures,
+    /// reporting every problem at once.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        if self.chunk_tokens == Some(0) {
+            problems.push("chunk_tokens must be greater than 0".to_string());
+        }
+        if let (Some(tokens), Some(overlap)) = (self.chunk_tokens, self.chunk_overlap) {
+            if overlap >= tokens {
+                problems.push(format!("chunk_overlap ({}) must be smaller than chunk_tokens ({})", overlap, tokens));
+            }
+        }
+        if let Some(Err(err)) = self.device.as_deref().map(str::parse::<crate::llm::DeviceSpec>) {
+            problems.push(err.to_string());
+        }
+        if let Some(weight) = self.hybrid_weight {
+            if !(0.0..=1.0).contains(&weight) {
+                problems.push(format!("hybrid_weight ({}) must be between 0 and 1", weight));
+            }
+        }
+        if let Some(min) = self.min_confidence {
+            if !(0.0..=1.0).contains(&min) {
+                problems.push(format!("min_confidence ({}) must be between 0 and 1", min));
+            }
+        }
+        if self.max_concurrency == Some(0) {
+            problems.push("max_concurrency must be at least 1".to_string());
+        }
+        if self.retry.is_some_and(|r| r.max_attempts == 0) {
+            problems.push("retry.max_attempts must be at least 1".to_string());
+        }
+        for (key, path) in [("model_path", &self.model_path), ("tokenizer_json", &self.tokenizer_json)] {
+            if let Some(path) = path {
+                if !path.exists() {
+                    problems.push(format!("{} {} does not exist", key, path.display()));
+                }
+            }
+        }
+        if !problems.is_empty() {
+            anyhow::bail!("invalid configuration:\n  - {}", problems.join("\n  - "));
+        }
+        Ok(())
+    }
+
+    /// Reads `FORGE_*` overrides (`FORGE_MODEL_PATH`, `FORGE_DEVICE`, `FORGE_CHUNK_TOKENS`, ...), one per field.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Config {
+            model_path: env_var("FORGE_MODEL_PATH").map(PathBuf::from),
+            device: env_var("FORGE_DEVICE"),
+            chunk_tokens: env_parse("FORGE_CHUNK_TOKENS")?,
+            chunk_overlap: env_parse("FORGE_CHUNK_OVERLAP")?,
+            chunk_mode: env_var("FORGE_CHUNK_MODE"),
+            tokenizer_json: env_var("FORGE_TOKENIZER_JSON").map(PathBuf::from),
+            split_large_rows: env_parse("FORGE_SPLIT_LARGE_ROWS")?,
+            ocr_min_chars: env_parse("FORGE_OCR_MIN_CHARS")?,
+            index_jobs: env_parse("FORGE_INDEX_JOBS")?,
+            max_concurrency: env_parse("FORGE_MAX_CONCURRENCY")?,
+            exclude: env_list("FORGE_EXCLUDE"),
+            include: env_list("FORGE_INCLUDE"),
+            min_confidence: env_parse("FORGE_MIN_CONFIDENCE")?,
+            top_k: env_parse("FORGE_TOP_K")?,
+            embedding_dim: env_parse("FORGE_EMBEDDING_DIM")?,
+            community_alg: env_var("FORGE_COMMUNITY_ALG"),
+            hybrid_weight: env_parse("FORGE_HYBRID_WEIGHT")?,
+            max_context_tokens: env_parse("FORGE_MAX_CONTEXT_TOKENS")?,
+            prompts: None,
+            rel_types: None,
+            cache_dir: env_var("FORGE_CACHE_DIR").map(PathBuf::from),
+            cache_sampled: env_parse("FORGE_CACHE_SAMPLED")?,
+            retry: None,
+            llm_timeout_secs: env_parse("FORGE_LLM_TIMEOUT_SECS")?,
+            chat_template: env_var("FORGE_CHAT_TEMPLATE"),
+        })
+    }
+
+    /// Fills every unset field from `fallback`, so `a.or(b)` gives `a` precedence over `b`.
+    pub fn or(self, fallback: Config) -> Self {
+        Config {
+            model_path: self.model_path.or(fallback.model_path),
+            device: self.device.or(fallback.device),
+            chunk_tokens: self.chunk_tokens.or(fallback.chunk_tokens),
+            chunk_overlap: self.chunk_overlap.or(fallback.chunk_overlap),
+            chunk_mode: self.chunk_mode.or(fallback.chunk_mode),
+            tokenizer_json: self.tokenizer_json.or(fallback.tokenizer_json),
+            split_large_rows: self.split_large_rows.or(fallback.split_large_rows),
+            ocr_min_chars: self.ocr_min_chars.or(fallback.ocr_min_chars),
+            index_jobs: self.index_jobs.or(fallback.index_jobs),
+            max_concurrency: self.max_concurrency.or(fallback.max_concurrency),
+            exclude: self.exclude.or(fallback.exclude),
+            include: self.include.or(fallback.include),
+            min_confidence: self.min_confidence.or(fallback.min_confidence),
+            top_k: self.top_k.or(fallback.top_k),
+            embedding_dim: self.embedding_dim.or(fallback.embedding_dim),
+            community_alg: self.community_alg.or(fallback.community_alg),
+            hybrid_weight: self.hybrid_weight.or(fallback.hybrid_weight),
+            max_context_tokens: self.max_context_tokens.or(fallback.max_context_tokens),
+            prompts: self.prompts.or(fallback.prompts),
+            rel_types: self.rel_types.or(fallback.rel_types),
+            cache_dir: self.cache_dir.or(fallback.cache_dir),
+            cache_sampled: self.cache_sampled.or(fallback.cache_sampled),
+            retry: self.retry.or(fallback.retry),
+            llm_timeout_secs: self.llm_timeout_secs.or(fallback.llm_timeout_secs),
+            chat_template: self.chat_template.or(fallback.chat_template),
+        }
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    env_var(name)
+        .map(|v| v.trim().parse().map_err(|e| anyhow::anyhow!("invalid {}={:?}: {}", name, v, e)))
+        .transpose()
+}
+
+/// A comma-separated list, e.g. `FORGE_EXCLUDE=target,*.log`.
+fn env_list(name: &str) -> Option<Vec<String>> {
+    env_var(name).map(|v| v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::RetryPolicy;
+
+    fn problem(cfg: Config) -> String { cfg.validate().unwrap_err().to_string() }
+
+    #[test]
+    fn each_invalid_value_is_reported() {
+        assert!(Config::default().validate().is_ok());
+        assert!(problem(Config { chunk_tokens: Some(0), ..Default::default() }).contains("chunk_tokens must be greater than 0"));
+        assert!(problem(Config { chunk_tokens: Some(100), chunk_overlap: Some(100), ..Default::default() }).contains("chunk_overlap (100) must be smaller than chunk_tokens (100)"));
+        assert!(problem(Config { device: Some("tpu".into()), ..Default::default() }).contains("tpu"));
+        assert!(problem(Config { hybrid_weight: Some(1.5), ..Default::default() }).contains("hybrid_weight (1.5) must be between 0 and 1"));
+        assert!(problem(Config { min_confidence: Some(-0.1), ..Default::default() }).contains("min_confidence (-0.1) must be between 0 and 1"));
+        assert!(problem(Config { max_concurrency: Some(0), ..Default::default() }).contains("max_concurrency must be at least 1"));
+        assert!(problem(Config { retry: Some(RetryPolicy { max_attempts: 0, ..Default::default() }), ..Default::default() }).contains("retry.max_attempts must be at least 1"));
+        assert!(problem(Config { model_path: Some("no/such/model.gguf".into()), ..Default::default() }).contains("model_path no/such/model.gguf does not exist"));
+        assert!(problem(Config { tokenizer_json: Some("no/such/tokenizer.json".into()), ..Default::default() }).contains("tokenizer_json no/such/tokenizer.json does not exist"));
+    }
+
+    #[test]
+    fn every_problem_is_listed_at_once() {
+        let err = problem(Config { chunk_tokens: Some(0), max_concurrency: Some(0), hybrid_weight: Some(2.0), ..Default::default() });
+        assert!(err.starts_with("invalid configuration:\n  - "));
+        assert_eq!(err.matches("\n  - ").count(), 3);
+    }
 }
@@ -1,27 +1,211 @@
-use std::{fs, path::{Path, PathBuf}};
+use std::{collections::HashSet, fs, path::{Path, PathBuf}, sync::Arc};
 
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
 
-use crate::Result;
+use crate::{ids::{IdStrategy, Standard}, vector::Metadata, Result};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Chunk {
     pub id: String,
     pub text: String,
     pub token_estimate: usize,
     pub source_path: Option<PathBuf>,
+    /// Markdown heading path of the section this chunk came from, e.g. `Guide > Install`.
+    #[serde(default)]
+    pub heading: Option<String>,
+    /// ISO 639-3 code of the chunk's detected language (`lang-detect` feature).
+    #[serde(default)]
+    pub language: Option<String>,
+    /// 1-based PDF page the chunk starts on.
+    #[serde(default)]
+    pub page: Option<usize>,
+}
+
+impl Chunk {
+    /// What the vector store records alongside the chunk's vector: whichever of `source_path`,
+    /// `heading`, `language` and `page` are set.
+    pub fn metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new();
+        if let Some(path) = &self.source_path { metadata.insert("source_path".into(), path.to_string_lossy().into_owned()); }
+        if let Some(heading) = &self.heading { metadata.insert("heading".into(), heading.clone()); }
+        if let Some(language) = &self.language { metadata.insert("language".into(), language.clone()); }
+        if let Some(page) = self.page { metadata.insert("page".into(), page.to_string()); }
+        metadata
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct DocumentProcessor;
 
+/// A file whose content, sniffed with the `sniff` feature, contradicts its extension; it's
+/// parsed as `sniffed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatMismatch {
+    pub path: PathBuf,
+    pub extension: String,
+    pub sniffed: &'static str,
+}
+
+impl std::fmt::Display for FormatMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} looks like {} despite its .{} extension; parsing it as {}", self.path.display(), self.sniffed, self.extension, self.sniffed)
+    }
+}
+
+/// How running text is cut into chunks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkMode {
+    /// Fixed word windows (`chunk_text`).
+    #[default]
+    Words,
+    /// Whole sentences packed up to the budget (`chunk_sentences`).
+    Sentences,
+}
+
+impl std::str::FromStr for ChunkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "words" => Ok(ChunkMode::Words),
+            "sentences" => Ok(ChunkMode::Sentences),
+            other => anyhow::bail!("unknown chunk mode '{}' (expected words or sentences)", other),
+        }
+    }
+}
+
+/// File extensions `DocumentProcessor::parse_path` handles.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["txt", "text", "md", "markdown", "pdf", "docx", "html", "htm", "csv", "jsonl", "ndjson"];
+
+/// Knobs for `DocumentProcessor::parse_path_opts`.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Word budget per chunk.
+    pub chunk_tokens: usize,
+    /// Words repeated at the start of the next chunk; 0 disables it. Sentence chunks repeat the
+    /// whole trailing sentences that fit, and Markdown only overlaps the pieces of a split section.
+    pub overlap: usize,
+    pub chunk_mode: ChunkMode,
+    /// Split CSV rows / JSONL records longer than `chunk_tokens` into several chunks.
+    pub split_rows: bool,
+    /// PDF pages with fewer extracted characters than this are OCR'd (`ocr` feature).
+    pub ocr_min_chars: usize,
+    /// Names the chunks; ids repeated within a document get a `-{n}` suffix.
+    pub ids: Arc<dyn IdStrategy>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self { Self { chunk_tokens: 512, overlap: 0, chunk_mode: ChunkMode::Words, split_rows: false, ocr_min_chars: 32, ids: Arc::new(Standard) } }
+}
+
 impl DocumentProcessor {
-    pub fn parse_path(path: &Path) -> Result<Vec<Chunk>> {
+    pub fn parse_path(path: &Path) -> Result<Vec<Chunk>> { Self::parse_path_opts(path, &ParseOptions::default()) }
+
+    /// `parse_path` with a custom chunk size and word overlap.
+    pub fn parse_path_with(path: &Path, chunk_tokens: usize, overlap: usize) -> Result<Vec<Chunk>> {
+        Self::parse_path_opts(path, &ParseOptions { chunk_tokens, overlap, ..ParseOptions::default() })
+    }
+
+    pub fn parse_path_opts(path: &Path, opts: &ParseOptions) -> Result<Vec<Chunk>> {
+        Ok(Self::parse_path_reporting(path, opts)?.0)
+    }
+
+    /// `parse_path_opts`, also returning the problems that didn't stop the parse, such as PDF
+    /// pages OCR couldn't read.
+    pub fn parse_path_reporting(path: &Path, opts: &ParseOptions) -> Result<(Vec<Chunk>, Vec<String>)> {
+        let mut warnings = Vec::new();
+        let mut chunks = Self::parse_chunks(path, opts, &mut warnings)?;
+        let mut seen = HashSet::new();
+        for (i, chunk) in chunks.iter_mut().enumerate() {
+            let id = opts.ids.chunk_id(chunk, i);
+            let mut unique = id.clone();
+            let mut n = 1;
+            while !seen.insert(unique.clone()) {
+                unique = format!("{}-{}", id, n);
+                n += 1;
+            }
+            chunk.id = unique;
+        }
+        #[cfg(feature = "lang-detect")]
+        for chunk in &mut chunks {
+            chunk.language = whatlang::detect_lang(&chunk.text).map(|lang| lang.code().to_string());
+        }
+        Ok((chunks, warnings))
+    }
+
+    /// The document format `path`'s leading bytes identify (`pdf`, `docx` or `html`), with the
+    /// `sniff` feature; `None` without it or when the content isn't recognized.
+    pub fn sniff_format(path: &Path) -> Option<&'static str> {
+        #[cfg(feature = "sniff")]
+        {
+            match infer::get_from_path(path).ok()??.extension() {
+                "pdf" => Some("pdf"),
+                "docx" => Some("docx"),
+                "html" => Some("html"),
+                _ => None,
+            }
+        }
+        #[cfg(not(feature = "sniff"))]
+        {
+            let _ = path;
+            None
+        }
+    }
+
+    /// The extension `path` is parsed as, and the mismatch when its sniffed content overrode a
+    /// different extension. Sniffed content wins when the extension is missing or unsupported,
+    /// and when a PDF or DOCX carries a different one.
+    pub fn detect_format(path: &Path) -> (String, Option<FormatMismatch>) {
         let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("").to_ascii_lowercase();
+        let Some(sniffed) = Self::sniff_format(path) else { return (ext, None) };
+        let matches = match sniffed {
+            "html" => ext == "html" || ext == "htm",
+            other => ext == other,
+        };
+        let known = SUPPORTED_EXTENSIONS.contains(&ext.as_str());
+        if matches || (known && sniffed == "html") { return (ext, None); }
+        let mismatch = (!ext.is_empty()).then(|| FormatMismatch { path: path.to_path_buf(), extension: ext, sniffed });
+        (sniffed.to_string(), mismatch)
+    }
+
+    #[cfg_attr(not(feature = "ocr"), allow(unused_variables, clippy::ptr_arg))]
+    fn parse_chunks(path: &Path, opts: &ParseOptions, warnings: &mut Vec<String>) -> Result<Vec<Chunk>> {
+        let (ext, _) = Self::detect_format(path);
         match ext.as_str() {
-            "txt" | "text" | "md" | "markdown" => {
-                let text = fs::read_to_string(path)?;
-                Ok(Self::chunk_text(&text, 512, Some(path.to_path_buf())))
+            "csv" => {
+                // One chunk per row, with each cell labelled by its column header.
+                let data = read_text(path)?;
+                let mut reader = csv::Reader::from_reader(data.as_bytes());
+                let headers = reader.headers()?.clone();
+                let mut chunks = Vec::new();
+                for (i, row) in reader.records().enumerate() {
+                    let row = row?;
+                    let text = headers.iter().zip(row.iter()).map(|(h, v)| format!("{}: {}", h, v)).collect::<Vec<_>>().join("\n");
+                    chunks.extend(record_chunks(&text, &record_id(path, "row", i + 1), path, opts));
+                }
+                Ok(chunks)
+            }
+            "jsonl" | "ndjson" => {
+                // One chunk per JSON line, flattened to `key.path: value` lines.
+                let mut chunks = Vec::new();
+                for (i, line) in read_text(path)?.lines().enumerate() {
+                    if line.trim().is_empty() { continue; }
+                    let value: serde_json::Value = serde_json::from_str(line).with_context(|| format!("{}:{}: invalid JSON", path.display(), i + 1))?;
+                    let mut fields = Vec::new();
+                    flatten_json("", &value, &mut fields);
+                    chunks.extend(record_chunks(&fields.join("\n"), &record_id(path, "line", i + 1), path, opts));
+                }
+                Ok(chunks)
+            }
+            "txt" | "text" => {
+                let text = read_text(path)?;
+                Ok(Self::chunk_with(&text, path, opts))
+            }
+            "md" | "markdown" => {
+                let text = read_text(path)?;
+                Ok(Self::chunk_markdown_with_overlap(&text, opts.chunk_tokens, opts.overlap, Some(path.to_path_buf())))
             }
             "pdf" => {
                 #[cfg(feature = "pdf")]
@@ -29,22 +213,78 @@ impl DocumentProcessor {
                     use lopdf::Document;
                     let doc = Document::load(path)?;
                     let mut text = String::new();
-                    for page_id in doc.get_pages().values() {
-                        if let Ok(content) = doc.extract_text(&[*page_id]) { text.push_str(&content); text.push('\n'); }
+                    // (index of the page's first word in `text`, page number)
+                    let mut pages = Vec::new();
+                    let mut words_so_far = 0usize;
+                    // `extract_text` takes page numbers (the map keys), not object ids.
+                    for page_no in doc.get_pages().keys() {
+                        #[allow(unused_mut)]
+                        let mut content = doc.extract_text(&[*page_no]).unwrap_or_default();
+                        // Scanned pages have little or no text layer; OCR them instead.
+                        #[cfg(feature = "ocr")]
+                        if content.trim().chars().count() < opts.ocr_min_chars {
+                            match ocr_pdf_page(path, *page_no) {
+                                Ok(ocr) => { content.push('\n'); content.push_str(&ocr); }
+                                Err(err) => warnings.push(format!("OCR failed for {} page {}: {:#}", path.display(), page_no, err)),
+                            }
+                        }
+                        pages.push((words_so_far, *page_no as usize));
+                        words_so_far += content.split_whitespace().count();
+                        text.push_str(&content);
+                        text.push('\n');
                     }
-                    Ok(Self::chunk_text(&text, 512, Some(path.to_path_buf())))
+                    let mut chunks = Self::chunk_with(&text, path, opts);
+                    assign_pages(&mut chunks, &text, &pages);
+                    Ok(chunks)
                 }
                 #[cfg(not(feature = "pdf"))]
                 {
                     anyhow::bail!("PDF support not enabled. Build with --features pdf");
                 }
             }
+            "docx" => {
+                #[cfg(feature = "docx")]
+                {
+                    let text = docx_to_text(path)?;
+                    Ok(Self::chunk_with(&text, path, opts))
+                }
+                #[cfg(not(feature = "docx"))]
+                {
+                    anyhow::bail!("DOCX support not enabled. Build with --features docx");
+                }
+            }
+            "html" | "htm" => {
+                #[cfg(feature = "html")]
+                {
+                    let text = html_to_text(&read_text(path)?);
+                    Ok(Self::chunk_with(&text, path, opts))
+                }
+                #[cfg(not(feature = "html"))]
+                {
+                    anyhow::bail!("HTML support not enabled. Build with --features html");
+                }
+            }
             _ => {
                 anyhow::bail!("Unsupported file type: {}", path.display());
             }
         }
     }
 
+    fn chunk_with(text: &str, path: &Path, opts: &ParseOptions) -> Vec<Chunk> {
+        match opts.chunk_mode {
+            ChunkMode::Words => chunk_words(text, path, opts),
+            ChunkMode::Sentences => pack_sentences(text, opts.chunk_tokens, Some(path.to_path_buf()), |lens, end| {
+                // Step back over the trailing sentences that fit in `overlap` words.
+                let (mut start, mut words) = (end, 0);
+                while start > 0 && words + lens[start - 1] <= opts.overlap {
+                    start -= 1;
+                    words += lens[start];
+                }
+                start
+            }),
+        }
+    }
+
     pub fn chunk_text(text: &str, target_tokens: usize, source_path: Option<PathBuf>) -> Vec<Chunk> {
         // Very simple word-based chunking approximation.
         let words: Vec<&str> = text.split_whitespace().collect();
@@ -59,10 +299,11 @@ impl DocumentProcessor {
             let end = (start + stride).min(words.len());
             let chunk_text = words[start..end].join(" ");
             chunks.push(Chunk {
-                id: format!("chunk-{}", idx),
+                id: chunk_id(source_path.as_deref(), idx),
                 text: chunk_text,
                 token_estimate: end - start,
                 source_path: source_path.clone(),
+                ..Default::default()
             });
             idx += 1;
             start = end;
@@ -81,10 +322,11 @@ impl DocumentProcessor {
             let end = (start + target_tokens).min(words.len());
             let chunk_text = words[start..end].join(" ");
             chunks.push(Chunk {
-                id: format!("chunk-{}", idx),
+                id: chunk_id(source_path.as_deref(), idx),
                 text: chunk_text,
                 token_estimate: end - start,
                 source_path: source_path.clone(),
+                ..Default::default()
             });
             idx += 1;
             if end == words.len() { break; }
@@ -94,32 +336,607 @@ impl DocumentProcessor {
         chunks
     }
 
+    /// Packs whole sentences into chunks of at most `target_tokens` words; a single longer
+    /// sentence becomes its own chunk. Consecutive chunks share `overlap_sentences` sentences.
+    pub fn chunk_sentences(text: &str, target_tokens: usize, overlap_sentences: usize, source_path: Option<PathBuf>) -> Vec<Chunk> {
+        pack_sentences(text, target_tokens, source_path, |_, end| end.saturating_sub(overlap_sentences))
+    }
+
+    /// Splits Markdown on headings first, keeping each section whole when it fits in
+    /// `target_tokens` words and otherwise packing its paragraphs, falling back to word windows
+    /// for oversized paragraphs. Chunks record their heading path in `Chunk::heading`.
+    pub fn chunk_markdown(text: &str, target_tokens: usize, source_path: Option<PathBuf>) -> Vec<Chunk> {
+        Self::chunk_markdown_with_overlap(text, target_tokens, 0, source_path)
+    }
+
+    /// `chunk_markdown`, starting each later piece of a split section with the last `overlap`
+    /// words of the piece before it. Pieces are packed to `target_tokens - overlap` words so the
+    /// repeated words stay within budget; sections that fit whole are unaffected.
+    pub fn chunk_markdown_with_overlap(text: &str, target_tokens: usize, overlap: usize, source_path: Option<PathBuf>) -> Vec<Chunk> {
+        let target_tokens = target_tokens.max(1);
+        let overlap = overlap.min(target_tokens - 1);
+        let budget = target_tokens - overlap;
+        let mut pieces: Vec<(Option<String>, String)> = Vec::new();
+        for (heading, body) in markdown_sections(text) {
+            if body.split_whitespace().count() <= target_tokens {
+                pieces.push((heading, body.trim().to_string()));
+                continue;
+            }
+            let mut section: Vec<String> = Vec::new();
+            let mut current = String::new();
+            let mut current_words = 0usize;
+            for para in body.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+                let words = para.split_whitespace().count();
+                if current_words > 0 && current_words + words > budget {
+                    section.push(std::mem::take(&mut current));
+                    current_words = 0;
+                }
+                if words > budget {
+                    let all: Vec<&str> = para.split_whitespace().collect();
+                    section.extend(all.chunks(budget).map(|w| w.join(" ")));
+                    continue;
+                }
+                if !current.is_empty() { current.push_str("\n\n"); }
+                current.push_str(para);
+                current_words += words;
+            }
+            if !current.is_empty() { section.push(current); }
+            for (i, piece) in section.iter().enumerate() {
+                let text = match i.checked_sub(1).filter(|_| overlap > 0) {
+                    Some(prev) => {
+                        let prev: Vec<&str> = section[prev].split_whitespace().collect();
+                        format!("{} {}", prev[prev.len().saturating_sub(overlap)..].join(" "), piece)
+                    }
+                    None => piece.clone(),
+                };
+                pieces.push((heading.clone(), text));
+            }
+        }
+        pieces
+            .into_iter()
+            .filter(|(_, text)| !text.trim().is_empty())
+            .enumerate()
+            .map(|(idx, (heading, text))| Chunk {
+                id: chunk_id(source_path.as_deref(), idx),
+                token_estimate: text.split_whitespace().count(),
+                text,
+                source_path: source_path.clone(),
+                heading,
+                ..Default::default()
+            })
+            .collect()
+    }
+
     #[cfg(feature = "llm")]
     pub fn chunk_with_tokenizer(tokenizer: &tokenizers::Tokenizer, text: &str, target_tokens: usize, overlap: usize, source_path: Option<PathBuf>) -> Vec<Chunk> {
-        use tokenizers::EncodeInput;
         if target_tokens == 0 { return vec![]; }
+        // Special tokens carry empty offsets and would break the span mapping, so leave them out.
+        let enc = match tokenizer.encode(text, false) {
+            Ok(enc) => enc,
+            Err(_) => return Self::chunk_text_with_overlap(text, target_tokens, overlap, source_path),
+        };
+        let offsets = enc.get_offsets();
+        if offsets.is_empty() { return Self::chunk_text_with_overlap(text, target_tokens, overlap, source_path); }
         let mut chunks = Vec::new();
         let mut start = 0usize;
         let mut idx = 0usize;
-        let enc = tokenizer.encode(text, true).ok();
-        if enc.is_none() { return Self::chunk_text_with_overlap(text, target_tokens, overlap, source_path); }
-        let ids = enc.unwrap().get_ids().to_vec();
-        while start < ids.len() {
-            let end = (start + target_tokens).min(ids.len());
-            // Recover text slice best-effort using byte offsets (tokenizer must provide tokens with offsets).
-            // If offsets are unavailable, fall back to string slicing approximation.
-            let chunk_text = text.to_string();
+        while start < offsets.len() {
+            let end = (start + target_tokens).min(offsets.len());
+            // Byte span from the first token's start to the last token's end within this window.
+            let byte_start = offsets[start].0;
+            let byte_end = offsets[end - 1].1.max(byte_start);
+            let chunk_text = text.get(byte_start..byte_end).unwrap_or_default().to_string();
             chunks.push(Chunk {
-                id: format!("chunk-{}", idx),
+                id: chunk_id(source_path.as_deref(), idx),
                 text: chunk_text,
                 token_estimate: end - start,
                 source_path: source_path.clone(),
+                ..Default::default()
             });
             idx += 1;
-            if end == ids.len() { break; }
-            let back = overlap.min(end - start);
+            if end == offsets.len() { break; }
+            let back = overlap.min(end - start - 1);
             start = end - back;
         }
         chunks
     }
 }
+
+/// Sets `Chunk::page` by locating each chunk's opening words in `text`; `pages` holds the
+/// word index at which each page begins.
+#[cfg(feature = "pdf")]
+fn assign_pages(chunks: &mut [Chunk], text: &str, pages: &[(usize, usize)]) {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let mut cursor = 0usize;
+    for chunk in chunks {
+        let head: Vec<&str> = chunk.text.split_whitespace().take(8).collect();
+        if head.is_empty() { continue; }
+        // Chunks come in document order but may overlap, so search from just past the previous start.
+        let Some(start) = (cursor..words.len()).find(|&k| words[k..].starts_with(&head)) else { continue };
+        chunk.page = pages.iter().rev().find(|(first, _)| *first <= start).map(|(_, page)| *page);
+        cursor = start + 1;
+    }
+}
+
+/// Renders one PDF page with `pdftoppm` and reads it back with `tesseract`; both must be on `PATH`.
+#[cfg(feature = "ocr")]
+fn ocr_pdf_page(path: &Path, page_no: u32) -> Result<String> {
+    use std::process::Command;
+    #[cfg(test)]
+    tests::OCR_PAGES.lock().unwrap().push((path.to_path_buf(), page_no));
+    // A directory per call, so files parsed concurrently can't overwrite each other's images.
+    let dir = tempfile::Builder::new().prefix("forge-ocr-").tempdir()?;
+    let prefix = dir.path().join(format!("page-{}", page_no));
+    let page = page_no.to_string();
+    let status = Command::new("pdftoppm")
+        .args(["-r", "300", "-png", "-singlefile", "-f", &page, "-l", &page])
+        .arg(path)
+        .arg(&prefix)
+        .status()
+        .context("failed to run pdftoppm (install poppler-utils)")?;
+    anyhow::ensure!(status.success(), "pdftoppm exited with {}", status);
+    let image = prefix.with_extension("png");
+    let output = Command::new("tesseract").arg(&image).arg("stdout").output().context("failed to run tesseract")?;
+    anyhow::ensure!(output.status.success(), "tesseract exited with {}", output.status);
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Paragraph text of a Word document, read from `word/document.xml`, one paragraph per line.
+#[cfg(feature = "docx")]
+fn docx_to_text(path: &Path) -> Result<String> {
+    use std::io::Read;
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?)?;
+    let mut xml = String::new();
+    archive.by_name("word/document.xml")?.read_to_string(&mut xml)?;
+    let doc = roxmltree::Document::parse(&xml)?;
+    let mut paragraphs = Vec::new();
+    // Text boxes nest paragraphs inside paragraphs; only take outermost ones so nothing is read twice.
+    for p in doc.descendants().filter(|n| n.tag_name().name() == "p" && n.ancestors().skip(1).all(|a| a.tag_name().name() != "p")) {
+        let text: String = p.descendants().filter(|n| n.tag_name().name() == "t").filter_map(|n| n.text()).collect();
+        if !text.trim().is_empty() { paragraphs.push(text); }
+    }
+    Ok(paragraphs.join("\n"))
+}
+
+/// Readable text of an HTML document: script/style content is dropped, block elements end
+/// lines, and headings are kept as markdown-style `#` prefixes.
+#[cfg(feature = "html")]
+fn html_to_text(html: &str) -> String {
+    let doc = scraper::Html::parse_document(html);
+    let mut out = String::new();
+    html_walk(doc.root_element(), &mut out);
+    out
+}
+
+#[cfg(feature = "html")]
+fn html_walk(el: scraper::ElementRef, out: &mut String) {
+    let name = el.value().name();
+    if matches!(name, "head" | "script" | "style" | "noscript" | "template") { return; }
+    let heading = match name { "h1" => 1, "h2" => 2, "h3" => 3, "h4" => 4, "h5" => 5, "h6" => 6, _ => 0 };
+    let block = heading > 0 || matches!(name, "p" | "div" | "br" | "li" | "tr" | "pre" | "blockquote" | "section" | "article" | "header" | "footer" | "table" | "ul" | "ol");
+    if block && !out.is_empty() && !out.ends_with('\n') { out.push('\n'); }
+    if heading > 0 { out.push_str(&"#".repeat(heading)); out.push(' '); }
+    for child in el.children() {
+        if let Some(text) = child.value().as_text() {
+            out.push_str(text);
+        } else if let Some(child_el) = scraper::ElementRef::wrap(child) {
+            html_walk(child_el, out);
+        }
+    }
+    if block && !out.ends_with('\n') { out.push('\n'); }
+}
+
+/// Splits Markdown into `(heading path, section text)` pairs; the heading line stays at the top
+/// of its section. Headings inside fenced code blocks are ignored, and heading-only sections dropped.
+fn markdown_sections(text: &str) -> Vec<(Option<String>, String)> {
+    let mut sections = Vec::new();
+    let mut stack: Vec<String> = Vec::new();
+    let mut heading: Option<String> = None;
+    let mut body = String::new();
+    let mut has_content = false;
+    let mut in_fence = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") { in_fence = !in_fence; }
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        let is_heading = !in_fence && (1..=6).contains(&level) && trimmed[level..].starts_with(' ');
+        if is_heading {
+            if has_content { sections.push((heading.take(), std::mem::take(&mut body))); }
+            body.clear();
+            has_content = false;
+            stack.truncate(level - 1);
+            stack.push(trimmed[level..].trim().trim_end_matches('#').trim().to_string());
+            heading = Some(stack.join(" > "));
+        } else if !line.trim().is_empty() {
+            has_content = true;
+        }
+        body.push_str(line);
+        body.push('\n');
+    }
+    if has_content { sections.push((heading, body)); }
+    sections
+}
+
+const ABBREVIATIONS: &[&str] = &["mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "etc", "e.g", "i.e", "inc", "ltd", "co", "no", "fig", "approx"];
+
+/// Splits on `.`, `!` or `?` followed by whitespace, keeping trailing quotes/brackets with the
+/// sentence. Doesn't split before a lowercase word or after common abbreviations and initials.
+fn split_sentences(text: &str) -> Vec<&str> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut out = Vec::new();
+    let mut start = 0usize;
+    for (k, &(i, c)) in chars.iter().enumerate() {
+        if !matches!(c, '.' | '!' | '?') { continue; }
+        let mut j = k + 1;
+        while j < chars.len() && matches!(chars[j].1, '"' | '\'' | ')' | ']' | '\u{201d}' | '\u{2019}') { j += 1; }
+        if j < chars.len() && !chars[j].1.is_whitespace() { continue; }
+        // A lowercase continuation means this wasn't a boundary (`U.K. today`, `"Hi!" he said`).
+        if chars[j..].iter().find(|(_, ch)| !ch.is_whitespace()).is_some_and(|(_, ch)| ch.is_lowercase()) { continue; }
+        if c == '.' {
+            let word = text[start..i].split_whitespace().last().unwrap_or("").trim_start_matches(|ch: char| !ch.is_alphanumeric());
+            let initial = word.chars().count() == 1 && word.chars().all(|ch| ch.is_uppercase());
+            if initial || ABBREVIATIONS.contains(&word.to_lowercase().as_str()) { continue; }
+        }
+        let end = chars.get(j).map(|&(b, _)| b).unwrap_or(text.len());
+        let sentence = text[start..end].trim();
+        if !sentence.is_empty() { out.push(sentence); }
+        start = end;
+    }
+    let rest = text[start..].trim();
+    if !rest.is_empty() { out.push(rest); }
+    out
+}
+
+/// Reads a text file as UTF-8. With the `encoding` feature a BOM selects UTF-8/UTF-16 decoding
+/// and non-UTF-8 bytes are decoded as Windows-1252; otherwise invalid bytes are replaced.
+fn read_text(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)?;
+    #[cfg(feature = "encoding")]
+    {
+        if let Some((encoding, bom_len)) = encoding_rs::Encoding::for_bom(&bytes) {
+            return Ok(encoding.decode_without_bom_handling(&bytes[bom_len..]).0.into_owned());
+        }
+        if std::str::from_utf8(&bytes).is_err() {
+            return Ok(encoding_rs::WINDOWS_1252.decode_without_bom_handling(&bytes).0.into_owned());
+        }
+    }
+    Ok(String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+}
+
+/// Id for a chunk holding one record (CSV row or JSONL line) of `path`, numbered from 1.
+fn record_id(path: &Path, kind: &str, n: usize) -> String {
+    format!("{:016x}-{}-{}", crate::fnv1a(path.to_string_lossy().as_bytes()), kind, n)
+}
+
+/// Packs whole sentences into chunks of at most `target_tokens` words. After each chunk ending
+/// before sentence `end`, the next starts at `restart(lens, end)` (clamped to make progress),
+/// where `lens` holds each sentence's word count.
+fn pack_sentences(text: &str, target_tokens: usize, source_path: Option<PathBuf>, restart: impl Fn(&[usize], usize) -> usize) -> Vec<Chunk> {
+    let sentences = split_sentences(text);
+    let lens: Vec<usize> = sentences.iter().map(|s| s.split_whitespace().count()).collect();
+    let mut chunks = Vec::new();
+    let mut start = 0usize;
+    while start < sentences.len() {
+        let mut end = start + 1;
+        let mut words = lens[start];
+        while end < sentences.len() && words + lens[end] <= target_tokens {
+            words += lens[end];
+            end += 1;
+        }
+        chunks.push(Chunk {
+            id: chunk_id(source_path.as_deref(), chunks.len()),
+            text: sentences[start..end].join(" "),
+            token_estimate: words,
+            source_path: source_path.clone(),
+            ..Default::default()
+        });
+        if end == sentences.len() { break; }
+        start = restart(&lens, end).max(start + 1);
+    }
+    chunks
+}
+
+fn chunk_words(text: &str, path: &Path, opts: &ParseOptions) -> Vec<Chunk> {
+    // An overlap as large as the chunk would never advance.
+    let overlap = opts.overlap.min(opts.chunk_tokens.saturating_sub(1));
+    if overlap == 0 { return DocumentProcessor::chunk_text(text, opts.chunk_tokens, Some(path.to_path_buf())); }
+    DocumentProcessor::chunk_text_with_overlap(text, opts.chunk_tokens, overlap, Some(path.to_path_buf()))
+}
+
+fn record_chunks(text: &str, id: &str, path: &Path, opts: &ParseOptions) -> Vec<Chunk> {
+    let words = text.split_whitespace().count();
+    if words == 0 { return vec![]; }
+    if !opts.split_rows || words <= opts.chunk_tokens {
+        return vec![Chunk { id: id.to_string(), text: text.to_string(), token_estimate: words, source_path: Some(path.to_path_buf()), ..Default::default() }];
+    }
+    chunk_words(text, path, opts)
+        .into_iter()
+        .enumerate()
+        .map(|(i, c)| Chunk { id: format!("{}-{}", id, i), ..c })
+        .collect()
+}
+
+fn flatten_json(prefix: &str, value: &serde_json::Value, out: &mut Vec<String>) {
+    let key = |k: &str| if prefix.is_empty() { k.to_string() } else { format!("{}.{}", prefix, k) };
+    match value {
+        serde_json::Value::Object(map) => for (k, v) in map { flatten_json(&key(k), v, out) },
+        serde_json::Value::Array(items) => for (i, v) in items.iter().enumerate() { flatten_json(&key(&i.to_string()), v, out) },
+        serde_json::Value::Null => {}
+        leaf => {
+            let text = match leaf { serde_json::Value::String(s) => s.clone(), other => other.to_string() };
+            out.push(if prefix.is_empty() { text } else { format!("{}: {}", prefix, text) });
+        }
+    }
+}
+
+/// Chunk ids are prefixed with a stable hash of the source path so that chunks
+/// from different documents never collide within one index.
+fn chunk_id(source_path: Option<&Path>, idx: usize) -> String {
+    match source_path {
+        Some(path) => format!("{:016x}-chunk-{}", crate::fnv1a(path.to_string_lossy().as_bytes()), idx),
+        None => format!("chunk-{}", idx),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pages `ocr_pdf_page` was asked to read.
+    #[cfg(feature = "ocr")]
+    pub(super) static OCR_PAGES: std::sync::Mutex<Vec<(PathBuf, u32)>> = std::sync::Mutex::new(Vec::new());
+
+    // Words of each chunk after the first, minus the `overlap` words it repeats, rejoined.
+    fn stitch(chunks: &[Chunk], overlap: usize) -> String {
+        let mut words: Vec<&str> = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            words.extend(chunk.text.split_whitespace().skip(if i == 0 { 0 } else { overlap }));
+        }
+        words.join(" ")
+    }
+
+    #[test]
+    fn word_windows_stitch_back_to_the_source() {
+        let text = "one two three four five six seven eight nine ten eleven";
+        for overlap in [0, 2] {
+            let chunks = DocumentProcessor::chunk_text_with_overlap(text, 4, overlap, None);
+            assert!(chunks.len() > 2);
+            assert_eq!(stitch(&chunks, overlap), text);
+        }
+    }
+
+    #[cfg(feature = "llm")]
+    #[test]
+    fn tokenizer_chunks_slice_the_source_text() {
+        use tokenizers::{models::wordlevel::WordLevel, pre_tokenizers::whitespace::Whitespace, Tokenizer};
+        let text = "alpha beta gamma delta epsilon zeta eta theta iota kappa lambda";
+        let mut vocab: std::collections::HashMap<String, u32> = text.split(' ').enumerate().map(|(i, w)| (w.to_string(), i as u32)).collect();
+        vocab.insert("[UNK]".into(), vocab.len() as u32);
+        let model = WordLevel::builder().vocab(vocab).unk_token("[UNK]".into()).build().unwrap();
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Whitespace {});
+
+        for overlap in [0, 2] {
+            let chunks = DocumentProcessor::chunk_with_tokenizer(&tokenizer, text, 4, overlap, None);
+            assert!(chunks.len() > 2);
+            assert!(chunks.iter().all(|c| text.contains(&c.text)));
+            assert_eq!(stitch(&chunks, overlap), text);
+        }
+    }
+
+    #[cfg(feature = "html")]
+    #[test]
+    fn html_chunks_leave_out_scripts_and_styles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("page.html");
+        let html = "<html><head><title>T</title><style>body { color: red }</style></head><body>\
+            <h1>Guide</h1><script>var secret = 1;</script><p>First paragraph.</p><noscript>enable js</noscript><p>Second one.</p></body></html>";
+        fs::write(&path, html).unwrap();
+        let chunks = DocumentProcessor::parse_path(&path).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].text, "# Guide First paragraph. Second one.");
+    }
+
+    #[cfg(feature = "docx")]
+    #[test]
+    fn docx_paragraphs_become_chunk_text() {
+        use std::io::Write;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("memo.docx");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("[Content_Types].xml", options).unwrap();
+        zip.write_all(br#"<?xml version="1.0"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"/>"#).unwrap();
+        zip.start_file("word/document.xml", options).unwrap();
+        zip.write_all(br#"<?xml version="1.0"?>
+            <w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>
+              <w:p><w:r><w:t>Quarterly </w:t></w:r><w:r><w:t>memo</w:t></w:r></w:p>
+              <w:p/>
+              <w:p><w:r><w:t>Sales rose &amp; costs fell.</w:t></w:r></w:p>
+            </w:body></w:document>"#).unwrap();
+        zip.finish().unwrap();
+
+        let chunks = DocumentProcessor::parse_path(&path).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].text, "Quarterly memo Sales rose & costs fell.");
+    }
+
+    #[test]
+    fn csv_rows_and_jsonl_lines_become_labelled_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("people.csv");
+        fs::write(&csv, "name,city\n\"Lovelace, Ada\",London\nTuring,\"Wilmslow, Cheshire\"\n").unwrap();
+        let chunks = DocumentProcessor::parse_path(&csv).unwrap();
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, ["name: Lovelace, Ada\ncity: London", "name: Turing\ncity: Wilmslow, Cheshire"]);
+        assert!(chunks[0].id.ends_with("-row-1") && chunks[1].id.ends_with("-row-2"));
+
+        let jsonl = dir.path().join("events.jsonl");
+        fs::write(&jsonl, "{\"event\": \"launch\", \"meta\": {\"tags\": [\"a\", \"b\"], \"skip\": null}}\n\n{\"event\": \"landing\", \"crew\": 3}\n").unwrap();
+        let chunks = DocumentProcessor::parse_path(&jsonl).unwrap();
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, ["event: launch\nmeta.tags.0: a\nmeta.tags.1: b", "crew: 3\nevent: landing"]);
+        assert!(chunks[1].id.ends_with("-line-3"));
+
+        fs::write(&jsonl, "{\"ok\": 1}\nnot json\n").unwrap();
+        assert!(DocumentProcessor::parse_path(&jsonl).unwrap_err().to_string().contains(":2: invalid JSON"));
+    }
+
+    #[test]
+    fn sentence_chunks_never_split_a_sentence() {
+        let text = "Dr. Smith arrived at 9 a.m. sharp. The U.K. team was late! Why? Nobody knew. \
+            \"We missed the train,\" they said. It was a long day, and everyone went home tired. Fin.";
+        let sentences = split_sentences(text);
+        assert_eq!(sentences.len(), 7);
+        for overlap in [0, 1] {
+            let chunks = DocumentProcessor::chunk_sentences(text, 8, overlap, None);
+            assert!(chunks.len() > 2);
+            for chunk in &chunks {
+                assert!(split_sentences(&chunk.text).iter().all(|s| sentences.contains(s)), "{:?} cuts a sentence", chunk.text);
+            }
+            assert!(sentences.iter().all(|s| chunks.iter().any(|c| c.text.contains(s))));
+        }
+    }
+
+    #[test]
+    fn markdown_chunks_follow_sections() {
+        let text = "# Guide\nIntro words here.\n\n## Install\nRun the installer.\n\n```sh\n# not a heading\n```\n\n## Use\n\
+            First paragraph of usage notes.\n\nSecond paragraph of usage notes.\n\n# Appendix\n";
+        // Install fits the 11-word budget whole, fenced `#` line included; Use doesn't and splits by paragraph.
+        let chunks = DocumentProcessor::chunk_markdown(text, 11, None);
+        let got: Vec<(Option<&str>, &str)> = chunks.iter().map(|c| (c.heading.as_deref(), c.text.as_str())).collect();
+        assert_eq!(got, [
+            (Some("Guide"), "# Guide\nIntro words here."),
+            (Some("Guide > Install"), "## Install\nRun the installer.\n\n```sh\n# not a heading\n```"),
+            (Some("Guide > Use"), "## Use\nFirst paragraph of usage notes."),
+            (Some("Guide > Use"), "Second paragraph of usage notes."),
+        ]);
+    }
+
+    #[test]
+    fn configured_overlap_repeats_text_in_every_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let parse = |name: &str, text: &str, chunk_mode| {
+            let path = dir.path().join(name);
+            fs::write(&path, text).unwrap();
+            let opts = ParseOptions { chunk_tokens: 8, overlap: 3, chunk_mode, ..ParseOptions::default() };
+            DocumentProcessor::parse_path_opts(&path, &opts).unwrap().into_iter().map(|c| c.text).collect::<Vec<_>>()
+        };
+
+        let words = parse("words.txt", "one two three four five six seven eight nine ten eleven twelve", ChunkMode::Words);
+        assert_eq!(words, ["one two three four five six seven eight", "six seven eight nine ten eleven twelve"]);
+
+        // The last sentence of each chunk fits the 3-word overlap, so it opens the next one.
+        let sentences = parse("sentences.txt", "Ada wrote notes. Babbage built engines. Turing broke codes. Hopper wrote compilers.", ChunkMode::Sentences);
+        assert_eq!(sentences, ["Ada wrote notes. Babbage built engines.", "Babbage built engines. Turing broke codes.", "Turing broke codes. Hopper wrote compilers."]);
+
+        // A split section's second piece starts with the first's last three words; Short fits whole.
+        let markdown = parse("guide.md", "# Long\nalpha beta gamma\n\nzeta eta theta iota\n\n# Short\nfits fine", ChunkMode::Words);
+        assert_eq!(markdown, ["# Long\nalpha beta gamma", "alpha beta gamma zeta eta theta iota", "# Short\nfits fine"]);
+    }
+
+    #[cfg(feature = "lang-detect")]
+    #[test]
+    fn chunks_record_their_language() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        let english = "The quick brown fox jumps over the lazy dog while the farmer watches from the old wooden porch.";
+        let french = "Le renard brun rapide saute par-dessus le chien paresseux pendant que le fermier regarde depuis la vieille terrasse.";
+        fs::write(&path, format!("{} {}", english, french)).unwrap();
+        let opts = ParseOptions { chunk_tokens: english.split_whitespace().count(), ..ParseOptions::default() };
+        let chunks = DocumentProcessor::parse_path_opts(&path, &opts).unwrap();
+        let languages: Vec<Option<&str>> = chunks.iter().map(|c| c.language.as_deref()).collect();
+        assert_eq!(languages, [Some("eng"), Some("fra")]);
+    }
+
+    /// Writes a PDF with one Courier text line per page; an empty string makes a blank page.
+    #[cfg(feature = "pdf")]
+    fn write_pdf(path: &Path, pages: &[&str]) {
+        use lopdf::{content::{Content, Operation}, dictionary, Document, Object, Stream};
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let font_id = doc.add_object(dictionary! { "Type" => "Font", "Subtype" => "Type1", "BaseFont" => "Courier" });
+        let resources_id = doc.add_object(dictionary! { "Font" => dictionary! { "F1" => font_id } });
+        let mut kids = Vec::new();
+        for text in pages {
+            let mut operations = Vec::new();
+            if !text.is_empty() {
+                operations = vec![
+                    Operation::new("BT", vec![]),
+                    Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                    Operation::new("Td", vec![50.into(), 700.into()]),
+                    Operation::new("Tj", vec![Object::string_literal(*text)]),
+                    Operation::new("ET", vec![]),
+                ];
+            }
+            let content_id = doc.add_object(Stream::new(dictionary! {}, Content { operations }.encode().unwrap()));
+            kids.push(doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id, "Contents" => content_id }).into());
+        }
+        let count = kids.len() as i64;
+        doc.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages", "Kids" => kids, "Count" => count, "Resources" => resources_id, "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        }));
+        let catalog_id = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        doc.trailer.set("Root", catalog_id);
+        doc.save(path).unwrap();
+    }
+
+    #[cfg(feature = "ocr")]
+    #[test]
+    fn ocr_only_reads_pages_without_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mixed.pdf");
+        write_pdf(&path, &["This page has a real text layer with plenty of characters.", ""]);
+        // OCR tools may be missing here; a failed OCR attempt is reported and the page's text kept.
+        let (chunks, warnings) = DocumentProcessor::parse_path_reporting(&path, &ParseOptions::default()).unwrap();
+        assert!(chunks[0].text.starts_with("This page has a real text layer"));
+        assert!(warnings.iter().all(|w| w.starts_with(&format!("OCR failed for {} page 2: ", path.display()))), "{:?}", warnings);
+        let ocr_pages: Vec<u32> = OCR_PAGES.lock().unwrap().iter().filter(|(p, _)| *p == path).map(|(_, page)| *page).collect();
+        assert_eq!(ocr_pages, [2]);
+    }
+
+    #[cfg(feature = "pdf")]
+    #[test]
+    fn pdf_chunks_carry_their_starting_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("two-pages.pdf");
+        write_pdf(&path, &["one two three four five six seven eight", "nine ten eleven twelve thirteen fourteen"]);
+        let opts = ParseOptions { chunk_tokens: 5, ..ParseOptions::default() };
+        let chunks = DocumentProcessor::parse_path_opts(&path, &opts).unwrap();
+        let got: Vec<(&str, Option<usize>)> = chunks.iter().map(|c| (c.text.as_str(), c.page)).collect();
+        // The middle chunk straddles the page break and records the page it starts on.
+        assert_eq!(got, [("one two three four five", Some(1)), ("six seven eight nine ten", Some(1)), ("eleven twelve thirteen fourteen", Some(2))]);
+    }
+
+    #[cfg(all(feature = "sniff", feature = "pdf"))]
+    #[test]
+    fn pdfs_are_recognized_without_or_despite_their_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let (bare, misnamed) = (dir.path().join("report"), dir.path().join("report.txt"));
+        for path in [&bare, &misnamed] {
+            write_pdf(path, &["quarterly numbers went up"]);
+            assert_eq!(DocumentProcessor::parse_path(path).unwrap()[0].text, "quarterly numbers went up");
+        }
+        // A missing extension isn't worth a warning; a wrong one is, for the caller to report.
+        assert_eq!(DocumentProcessor::detect_format(&bare), ("pdf".to_string(), None));
+        let (format, mismatch) = DocumentProcessor::detect_format(&misnamed);
+        assert_eq!(format, "pdf");
+        assert_eq!(mismatch.unwrap().to_string(), format!("{} looks like pdf despite its .txt extension; parsing it as pdf", misnamed.display()));
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn legacy_encodings_decode_to_utf8() {
+        let dir = tempfile::tempdir().unwrap();
+        let cp1252 = dir.path().join("quotes.txt");
+        // \x93 and \x94 are Windows-1252 curly double quotes, \x92 an apostrophe, \xe9 "é".
+        fs::write(&cp1252, b"\x93Caf\xe9\x94 isn\x92t ASCII").unwrap();
+        assert_eq!(DocumentProcessor::parse_path(&cp1252).unwrap()[0].text, "\u{201c}Caf\u{e9}\u{201d} isn\u{2019}t ASCII");
+
+        let utf16 = dir.path().join("wide.csv");
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend("name\nZo\u{eb}\n".encode_utf16().flat_map(u16::to_le_bytes));
+        fs::write(&utf16, bytes).unwrap();
+        assert_eq!(DocumentProcessor::parse_path(&utf16).unwrap()[0].text, "name: Zo\u{eb}");
+    }
+}
@@ -1,7 +1,10 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
 
+use crate::{ids::{IdStrategy, Standard}, vector::{cosine_similarity, Embedder}, Result};
+
 pub type EntityId = String;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +14,9 @@ pub struct Entity {
     pub entity_type: String,
     pub description: String,
     pub source_chunks: Vec<String>,
+    /// How sure the extractor was, from 0 to 1; `None` when it didn't say.
+    #[serde(default)]
+    pub confidence: Option<f32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,23 +26,109 @@ pub struct Relationship {
     pub rel_type: String,
     pub description: String,
     pub strength: f32,
+    /// Ids of the chunks this relationship was extracted from.
+    #[serde(default)]
+    pub source_chunks: Vec<String>,
+}
+
+/// How `add_relationship_with` combines the strength of a repeated edge with the existing one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StrengthAggregation {
+    /// Accumulate evidence: each repetition adds its strength.
+    #[default]
+    Sum,
+    /// Keep the strongest single observation.
+    Max,
+}
+
+/// Everything `merge_entities_recording` changed, for `KnowledgeGraph::unmerge`.
+#[derive(Debug, Clone)]
+pub struct MergeRecord {
+    /// The surviving entity as it was before the merge.
+    pub survivor: Entity,
+    /// The entity merged away.
+    pub removed: Entity,
+    /// Post-merge indices of the edges that had an endpoint moved from `removed` to `survivor`.
+    pub rewired: Vec<usize>,
+    /// Self-loops the merge dropped, as they were and at their pre-merge indices, ascending.
+    pub dropped: Vec<(usize, Relationship)>,
+}
+
+/// A problem found by `KnowledgeGraph::validate`; `edge` indexes `KnowledgeGraph::edges`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphIssue {
+    /// An endpoint names no entity in `nodes`.
+    DanglingEdge { edge: usize, missing: EntityId },
+    SelfLoop { edge: usize, id: EntityId },
+}
+
+impl std::fmt::Display for GraphIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphIssue::DanglingEdge { edge, missing } => write!(f, "edge {} points to missing entity '{}'", edge, missing),
+            GraphIssue::SelfLoop { edge, id } => write!(f, "edge {} loops on '{}'", edge, id),
+        }
+    }
+}
+
+/// What changed between two versions of a graph, from `KnowledgeGraph::diff`. Relationships
+/// refer to ids in the graph they come from: removed ones the old graph, the rest the new one.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GraphDiff {
+    pub added_entities: Vec<Entity>,
+    pub removed_entities: Vec<Entity>,
+    pub added_relationships: Vec<Relationship>,
+    pub removed_relationships: Vec<Relationship>,
+    /// Relationships in both versions whose strength differs.
+    pub changed_strength: Vec<StrengthChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StrengthChange {
+    pub source: EntityId,
+    pub target: EntityId,
+    pub rel_type: String,
+    pub old: f32,
+    pub new: f32,
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_entities.is_empty()
+            && self.removed_entities.is_empty()
+            && self.added_relationships.is_empty()
+            && self.removed_relationships.is_empty()
+            && self.changed_strength.is_empty()
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct KnowledgeGraph {
     pub nodes: HashMap<EntityId, Entity>,
     pub edges: Vec<Relationship>,
+    /// Whether edges point from `source` to `target`. Traversal helpers such as `neighborhood`
+    /// and community detection ignore direction either way.
+    #[serde(default)]
+    pub directed: bool,
 }
 
 impl KnowledgeGraph {
-    pub fn add_entity(&mut self, mut entity: Entity) -> EntityId {
-        // Deduplicate by name (case-insensitive) for now.
-        if let Some((id, _)) = self.nodes.iter().find(|(_, e)| e.name.eq_ignore_ascii_case(&entity.name)) {
-            return id.clone();
+    /// Inserts `entity` and returns its id. An entity with the same name (ignoring case) absorbs
+    /// it instead: its type is upgraded if generic, descriptions and source chunks are merged, and
+    /// the higher confidence is kept.
+    pub fn add_entity(&mut self, entity: Entity) -> EntityId { self.add_entity_with(entity, &Standard) }
+
+    /// `add_entity`, naming a new entity with `ids` rather than the `Standard` scheme.
+    pub fn add_entity_with(&mut self, mut entity: Entity, ids: &dyn IdStrategy) -> EntityId {
+        if let Some(existing) = self.nodes.values_mut().find(|e| e.name.eq_ignore_ascii_case(&entity.name)) {
+            absorb(existing, &entity);
+            return existing.id.clone();
         }
-        let base_id = sanitize_id(&entity.name);
+        let base_id = ids.entity_id(&entity.name, self.nodes.len());
         let mut id = base_id.clone();
         let mut i = 1;
+        // Only reached when the strategy repeats an id (a hash collision, or an entity inserted
+        // under this id directly).
         while self.nodes.contains_key(&id) {
             id = format!("{}-{}", base_id, i);
             i += 1;
@@ -46,43 +138,202 @@ impl KnowledgeGraph {
         id
     }
 
-    pub fn add_relationship(&mut self, rel: Relationship) {
-        // Avoid duplicates
-        let exists = self.edges.iter().any(|r| r.source == rel.source && r.target == rel.target && r.rel_type == rel.rel_type);
-        if !exists { self.edges.push(rel); }
-    }
+    /// Adds `rel`, folding it into an existing edge with the same source, target and type using
+    /// `StrengthAggregation::Sum`.
+    pub fn add_relationship(&mut self, rel: Relationship) { self.add_relationship_with(rel, StrengthAggregation::default()) }
 
-    pub fn merge_entities(&mut self, id1: &EntityId, id2: &EntityId) {
-        if id1 == id2 { return; }
-        if let Some(e2) = self.nodes.remove(id2) {
-            if let Some(e1) = self.nodes.get_mut(id1) {
-                // Merge descriptions and source chunks.
-                if !e2.description.is_empty() {
-                    if !e1.description.is_empty() { e1.description.push_str(" \u{2014} "); }
-                    e1.description.push_str(&e2.description);
+    pub fn add_relationship_with(&mut self, rel: Relationship, agg: StrengthAggregation) {
+        match self.edges.iter_mut().find(|r| r.source == rel.source && r.target == rel.target && r.rel_type == rel.rel_type) {
+            Some(existing) => {
+                existing.strength = match agg {
+                    StrengthAggregation::Sum => existing.strength + rel.strength,
+                    StrengthAggregation::Max => existing.strength.max(rel.strength),
+                };
+                if !rel.description.is_empty() && !existing.description.contains(&rel.description) {
+                    if !existing.description.is_empty() { existing.description.push_str(" \u{2014} "); }
+                    existing.description.push_str(&rel.description);
+                }
+                for chunk in rel.source_chunks {
+                    if !existing.source_chunks.contains(&chunk) { existing.source_chunks.push(chunk); }
                 }
-                let mut set: HashSet<String> = e1.source_chunks.iter().cloned().collect();
-                set.extend(e2.source_chunks.iter().cloned());
-                e1.source_chunks = set.into_iter().collect();
-            } else {
-                // id1 missing; put e2 back
-                self.nodes.insert(id2.clone(), e2);
-                return;
             }
-            // Rewire edges from id2 to id1
-            for edge in self.edges.iter_mut() {
-                if edge.source == *id2 { edge.source = id1.clone(); }
-                if edge.target == *id2 { edge.target = id1.clone(); }
+            None => self.edges.push(rel),
+        }
+    }
+
+    pub fn merge_entities(&mut self, id1: &EntityId, id2: &EntityId) { self.merge_entities_recording(id1, id2); }
+
+    /// Merges `id2` into `id1` like `merge_entities`, returning what changed so `unmerge` can
+    /// restore it. `None` (and no change) if the ids are equal or either is missing.
+    pub fn merge_entities_recording(&mut self, id1: &EntityId, id2: &EntityId) -> Option<MergeRecord> {
+        if id1 == id2 || !self.nodes.contains_key(id1) { return None; }
+        let removed = self.nodes.remove(id2)?;
+        let e1 = self.nodes.get_mut(id1).expect("checked above");
+        let survivor = e1.clone();
+        absorb(e1, &removed);
+        // Rewire edges from id2 to id1, dropping the self-loops that leaves.
+        let mut rewired = Vec::new();
+        let mut dropped = Vec::new();
+        let mut kept = Vec::with_capacity(self.edges.len());
+        for (i, mut edge) in std::mem::take(&mut self.edges).into_iter().enumerate() {
+            let touches = edge.source == *id2 || edge.target == *id2;
+            let original = (touches || edge.source == edge.target).then(|| edge.clone());
+            if edge.source == *id2 { edge.source = id1.clone(); }
+            if edge.target == *id2 { edge.target = id1.clone(); }
+            if edge.source == edge.target {
+                dropped.push((i, original.expect("self-loops are cloned")));
+                continue;
             }
-            // Remove possible self-loops duplicates
-            self.edges.retain(|r| r.source != r.target);
+            if touches { rewired.push(kept.len()); }
+            kept.push(edge);
         }
+        self.edges = kept;
+        Some(MergeRecord { survivor, removed, rewired, dropped })
+    }
+
+    /// Reverses `merge_entities_recording`. Only exact if the graph hasn't changed since.
+    pub fn unmerge(&mut self, record: MergeRecord) {
+        let (id1, id2) = (&record.survivor.id, &record.removed.id);
+        for &i in &record.rewired {
+            let Some(edge) = self.edges.get_mut(i) else { continue };
+            if edge.source == *id1 { edge.source = id2.clone(); } else if edge.target == *id1 { edge.target = id2.clone(); }
+        }
+        for (i, edge) in record.dropped {
+            self.edges.insert(i.min(self.edges.len()), edge);
+        }
+        self.nodes.insert(record.removed.id.clone(), record.removed);
+        self.nodes.insert(record.survivor.id.clone(), record.survivor);
+    }
+
+    /// Collapses each group of aliases into its first id present in the graph. Groups that share an
+    /// id are joined first (union-find), so overlapping groups end up as one entity, kept under the
+    /// earliest-listed id. Unknown ids are skipped. Returns the number of entities merged away.
+    pub fn merge_all(&mut self, groups: &[Vec<EntityId>]) -> usize {
+        fn find(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        // Ids in order of first mention; the root of each set is always its earliest member.
+        let mut order: Vec<&EntityId> = Vec::new();
+        let mut index: HashMap<&EntityId, usize> = HashMap::new();
+        for id in groups.iter().flatten() {
+            if self.nodes.contains_key(id) && !index.contains_key(id) {
+                index.insert(id, order.len());
+                order.push(id);
+            }
+        }
+        let mut parent: Vec<usize> = (0..order.len()).collect();
+        for group in groups {
+            let mut present = group.iter().filter_map(|id| index.get(id).copied());
+            let Some(first) = present.next() else { continue };
+            for other in present {
+                let (a, b) = (find(&mut parent, first), find(&mut parent, other));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+        let mut merged = 0;
+        for i in 0..order.len() {
+            let root = find(&mut parent, i);
+            if root != i {
+                self.merge_entities(order[root], order[i]);
+                merged += 1;
+            }
+        }
+        merged
+    }
+
+    /// Greedily merges entities whose "name: description" embeddings reach `threshold` cosine
+    /// similarity, visiting ids in sorted order. Returns the number of entities merged away.
+    pub fn dedupe_by_embedding(&mut self, embedder: &dyn Embedder, threshold: f32) -> usize {
+        self.dedupe_by_embedding_with(embedder, threshold, false)
+    }
+
+    /// Like `dedupe_by_embedding`, optionally allowing entities of different `entity_type` to merge.
+    pub fn dedupe_by_embedding_with(&mut self, embedder: &dyn Embedder, threshold: f32, allow_cross_type: bool) -> usize {
+        let mut ids: Vec<EntityId> = self.nodes.keys().cloned().collect();
+        ids.sort();
+        let vectors: Vec<Vec<f32>> = ids.iter().map(|id| {
+            let e = &self.nodes[id];
+            embedder.embed(&format!("{}: {}", e.name, e.description))
+        }).collect();
+        let mut merged = vec![false; ids.len()];
+        for i in 0..ids.len() {
+            if merged[i] { continue; }
+            for j in (i + 1)..ids.len() {
+                if merged[j] { continue; }
+                if !allow_cross_type && self.nodes[&ids[i]].entity_type != self.nodes[&ids[j]].entity_type { continue; }
+                if cosine_similarity(&vectors[i], &vectors[j]) >= threshold {
+                    self.merge_entities(&ids[i], &ids[j]);
+                    merged[j] = true;
+                }
+            }
+        }
+        merged.iter().filter(|m| **m).count()
+    }
+
+    /// Forgets the given chunk ids. Entities and relationships whose every source chunk was
+    /// removed are dropped, along with edges touching dropped entities.
+    pub fn remove_chunks(&mut self, chunk_ids: &HashSet<String>) {
+        if chunk_ids.is_empty() { return; }
+        let mut removed = HashSet::new();
+        self.nodes.retain(|id, e| {
+            let had_sources = !e.source_chunks.is_empty();
+            e.source_chunks.retain(|c| !chunk_ids.contains(c));
+            let orphaned = had_sources && e.source_chunks.is_empty();
+            if orphaned { removed.insert(id.clone()); }
+            !orphaned
+        });
+        self.edges.retain_mut(|r| {
+            let had_sources = !r.source_chunks.is_empty();
+            r.source_chunks.retain(|c| !chunk_ids.contains(c));
+            let orphaned = had_sources && r.source_chunks.is_empty();
+            !orphaned && !removed.contains(&r.source) && !removed.contains(&r.target)
+        });
     }
 
     pub fn find_entity(&self, name: &str) -> Option<&Entity> {
         self.nodes.values().find(|e| e.name.eq_ignore_ascii_case(name))
     }
 
+    /// Up to `k` entities whose names best match `query`, best first, scoring at least
+    /// `ENTITY_MATCH_THRESHOLD`. Names are compared ignoring case with Jaro-Winkler, both whole and
+    /// word by word, so "Einstein" finds "Albert Einstein" and survives small typos.
+    pub fn search_entities(&self, query: &str, k: usize) -> Vec<(&Entity, f32)> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() { return Vec::new(); }
+        let mut scored: Vec<(&Entity, f32)> = self
+            .nodes
+            .values()
+            .map(|e| (e, name_similarity(&query, &e.name.to_lowercase())))
+            .filter(|(_, score)| *score >= ENTITY_MATCH_THRESHOLD)
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.name.cmp(&b.0.name)).then_with(|| a.0.id.cmp(&b.0.id)));
+        scored.truncate(k);
+        scored
+    }
+
+    /// Id of the entity called `name` (case-insensitive) or with that id. Unknown names get a
+    /// placeholder entity of type `Unknown`, so edges built from extracted names never dangle.
+    pub fn resolve_entity(&mut self, name: &str, source_chunks: &[String]) -> EntityId { self.resolve_entity_with(name, source_chunks, &Standard) }
+
+    /// `resolve_entity`, naming a created entity with `ids`.
+    pub fn resolve_entity_with(&mut self, name: &str, source_chunks: &[String], ids: &dyn IdStrategy) -> EntityId {
+        let name = name.trim();
+        if let Some(e) = self.find_entity(name) { return e.id.clone(); }
+        if self.nodes.contains_key(name) { return name.to_string(); }
+        self.add_entity_with(Entity {
+            id: String::new(),
+            name: name.to_string(),
+            entity_type: "Unknown".to_string(),
+            description: String::new(),
+            source_chunks: source_chunks.to_vec(),
+            confidence: None,
+        }, ids)
+    }
+
     pub fn neighbors(&self, id: &EntityId) -> Vec<&Entity> {
         let mut out = Vec::new();
         for edge in &self.edges {
@@ -95,27 +346,821 @@ impl KnowledgeGraph {
         out
     }
 
+    /// Targets of edges leaving `id`; same as `neighbors` on an undirected graph.
+    pub fn out_neighbors(&self, id: &EntityId) -> Vec<&Entity> {
+        if !self.directed { return self.neighbors(id); }
+        self.edges.iter().filter(|e| &e.source == id).filter_map(|e| self.nodes.get(&e.target)).collect()
+    }
+
+    /// Sources of edges entering `id`; same as `neighbors` on an undirected graph.
+    pub fn in_neighbors(&self, id: &EntityId) -> Vec<&Entity> {
+        if !self.directed { return self.neighbors(id); }
+        self.edges.iter().filter(|e| &e.target == id).filter_map(|e| self.nodes.get(&e.source)).collect()
+    }
+
+    /// Fewest-hops path between two entities, treating edges as undirected.
+    pub fn shortest_path(&self, from: &EntityId, to: &EntityId) -> Option<Vec<EntityId>> {
+        if !self.nodes.contains_key(from) || !self.nodes.contains_key(to) { return None; }
+        if from == to { return Some(vec![from.clone()]); }
+        let adj = self.adjacency();
+        let mut parent: HashMap<&EntityId, &EntityId> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(cur) = queue.pop_front() {
+            for &next in adj.get(cur).into_iter().flatten() {
+                if next == from || parent.contains_key(next) { continue; }
+                parent.insert(next, cur);
+                if next == to {
+                    let mut path = vec![to.clone()];
+                    let mut at = to;
+                    while let Some(&p) = parent.get(at) { path.push(p.clone()); at = p; }
+                    path.reverse();
+                    return Some(path);
+                }
+                queue.push_back(next);
+            }
+        }
+        None
+    }
+
+    /// Ids reachable from `id` within `hops` undirected steps, including `id` itself.
+    pub fn neighborhood(&self, id: &EntityId, hops: usize) -> HashSet<EntityId> {
+        let mut seen = HashSet::new();
+        if !self.nodes.contains_key(id) { return seen; }
+        let adj = self.adjacency();
+        seen.insert(id.clone());
+        let mut frontier = vec![id];
+        for _ in 0..hops {
+            let mut next_frontier = Vec::new();
+            for cur in frontier {
+                for &next in adj.get(cur).into_iter().flatten() {
+                    if seen.insert(next.clone()) { next_frontier.push(next); }
+                }
+            }
+            if next_frontier.is_empty() { break; }
+            frontier = next_frontier;
+        }
+        seen
+    }
+
+    /// The seeds, everything within `hops` of them, and every edge among those nodes.
+    pub fn subgraph(&self, seed_ids: &[EntityId], hops: usize) -> KnowledgeGraph {
+        let mut keep = HashSet::new();
+        for id in seed_ids { keep.extend(self.neighborhood(id, hops)); }
+        KnowledgeGraph {
+            nodes: self.nodes.iter().filter(|(id, _)| keep.contains(*id)).map(|(id, e)| (id.clone(), e.clone())).collect(),
+            edges: self.edges.iter().filter(|r| keep.contains(&r.source) && keep.contains(&r.target)).cloned().collect(),
+            directed: self.directed,
+        }
+    }
+
+    /// Only the entities whose `entity_type` is one of `types` (ignoring case), and the edges
+    /// among them.
+    pub fn filter_by_types(&self, types: &[&str]) -> KnowledgeGraph {
+        let nodes: HashMap<EntityId, Entity> = self.nodes.iter().filter(|(_, e)| types.iter().any(|t| t.eq_ignore_ascii_case(&e.entity_type))).map(|(id, e)| (id.clone(), e.clone())).collect();
+        KnowledgeGraph {
+            edges: self.edges.iter().filter(|r| nodes.contains_key(&r.source) && nodes.contains_key(&r.target)).cloned().collect(),
+            nodes,
+            directed: self.directed,
+        }
+    }
+
+    /// Number of distinct entities `id` shares an edge with, in either direction. Parallel edges
+    /// and self-loops add nothing.
+    pub fn degree(&self, id: &EntityId) -> usize {
+        let mut seen = HashSet::new();
+        for edge in &self.edges {
+            let other = if &edge.source == id { &edge.target } else if &edge.target == id { &edge.source } else { continue };
+            if other != id && self.nodes.contains_key(other) { seen.insert(other); }
+        }
+        seen.len()
+    }
+
+    /// Every entity's `degree`.
+    pub fn degrees(&self) -> HashMap<&EntityId, usize> {
+        self.distinct_adjacency().into_iter().map(|(id, adj)| (id, adj.len())).collect()
+    }
+
+    /// How many entities have each degree.
+    pub fn degree_distribution(&self) -> BTreeMap<usize, usize> {
+        let mut dist = BTreeMap::new();
+        for degree in self.degrees().into_values() { *dist.entry(degree).or_insert(0) += 1; }
+        dist
+    }
+
+    /// Unnormalized betweenness centrality (Brandes): for each entity, the share of shortest
+    /// paths between other pairs that pass through it. Edges are undirected and unweighted, and
+    /// each unordered pair is counted once.
+    pub fn betweenness_centrality(&self) -> HashMap<EntityId, f32> {
+        let adj = self.distinct_adjacency();
+        let mut centrality: HashMap<&EntityId, f64> = adj.keys().map(|&id| (id, 0.0)).collect();
+        for &source in adj.keys() {
+            // BFS from `source`, counting shortest paths to each node and remembering the order.
+            let mut order = Vec::new();
+            let mut preds: HashMap<&EntityId, Vec<&EntityId>> = HashMap::new();
+            let mut paths: HashMap<&EntityId, f64> = HashMap::from([(source, 1.0)]);
+            let mut dist: HashMap<&EntityId, usize> = HashMap::from([(source, 0)]);
+            let mut queue = VecDeque::from([source]);
+            while let Some(cur) = queue.pop_front() {
+                order.push(cur);
+                for &next in &adj[cur] {
+                    if !dist.contains_key(next) {
+                        dist.insert(next, dist[cur] + 1);
+                        queue.push_back(next);
+                    }
+                    if dist[next] == dist[cur] + 1 {
+                        *paths.entry(next).or_insert(0.0) += paths[cur];
+                        preds.entry(next).or_default().push(cur);
+                    }
+                }
+            }
+            // Walk back from the farthest nodes, passing each node's dependency to its predecessors.
+            let mut dependency: HashMap<&EntityId, f64> = HashMap::new();
+            for &node in order.iter().rev() {
+                let share = (1.0 + dependency.get(node).copied().unwrap_or(0.0)) / paths[node];
+                for &pred in preds.get(node).into_iter().flatten() {
+                    *dependency.entry(pred).or_insert(0.0) += paths[pred] * share;
+                }
+                if node != source { *centrality.get_mut(node).expect("every node has an entry") += dependency.get(node).copied().unwrap_or(0.0); }
+            }
+        }
+        // Every pair was walked from both ends.
+        centrality.into_iter().map(|(id, c)| (id.clone(), (c / 2.0) as f32)).collect()
+    }
+
+    /// Groups of entities connected by edges in either direction, largest first (ties by first
+    /// id); each group is sorted and an entity without edges is a group of its own.
+    pub fn connected_components(&self) -> Vec<Vec<EntityId>> {
+        let adj = self.distinct_adjacency();
+        let mut seen: HashSet<&EntityId> = HashSet::new();
+        let mut components = Vec::new();
+        for &start in adj.keys() {
+            if !seen.insert(start) { continue; }
+            let mut component = vec![start.clone()];
+            let mut queue = VecDeque::from([start]);
+            while let Some(cur) = queue.pop_front() {
+                for &next in &adj[cur] {
+                    if seen.insert(next) { component.push(next.clone()); queue.push_back(next); }
+                }
+            }
+            component.sort();
+            components.push(component);
+        }
+        components.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a[0].cmp(&b[0])));
+        components
+    }
+
+    /// What changed from `self` to the newer `other`. Entities are matched by id, or by name
+    /// (ignoring case) when the id changed; relationships by source, target and type once old
+    /// endpoints are mapped to their matches. Every list is sorted by id.
+    pub fn diff(&self, other: &KnowledgeGraph) -> GraphDiff {
+        let by_name: HashMap<String, &EntityId> = other.nodes.values().map(|e| (e.name.to_lowercase(), &e.id)).collect();
+        // Old id -> the id of the same entity in `other`.
+        let matched: HashMap<&EntityId, &EntityId> = self
+            .nodes
+            .values()
+            .filter_map(|e| {
+                let id = other.nodes.get_key_value(&e.id).map(|(id, _)| id).or_else(|| by_name.get(&e.name.to_lowercase()).copied());
+                id.map(|id| (&e.id, id))
+            })
+            .collect();
+        let kept: HashSet<&EntityId> = matched.values().copied().collect();
+        let mut diff = GraphDiff {
+            added_entities: other.nodes.values().filter(|e| !kept.contains(&e.id)).cloned().collect(),
+            removed_entities: self.nodes.values().filter(|e| !matched.contains_key(&e.id)).cloned().collect(),
+            ..Default::default()
+        };
+
+        let new_edges: HashMap<(&str, &str, &str), &Relationship> = other.edges.iter().map(|r| (edge_key(r), r)).collect();
+        let mut old_keys = HashSet::new();
+        for r in &self.edges {
+            let source = matched.get(&r.source).map_or(r.source.as_str(), |id| id.as_str());
+            let target = matched.get(&r.target).map_or(r.target.as_str(), |id| id.as_str());
+            let key = (source, target, r.rel_type.as_str());
+            old_keys.insert(key);
+            match new_edges.get(&key) {
+                None => diff.removed_relationships.push(r.clone()),
+                Some(new) if (new.strength - r.strength).abs() > f32::EPSILON => diff.changed_strength.push(StrengthChange {
+                    source: new.source.clone(),
+                    target: new.target.clone(),
+                    rel_type: new.rel_type.clone(),
+                    old: r.strength,
+                    new: new.strength,
+                }),
+                Some(_) => {}
+            }
+        }
+        diff.added_relationships = other.edges.iter().filter(|r| !old_keys.contains(&edge_key(r))).cloned().collect();
+
+        diff.added_entities.sort_by(|a, b| a.id.cmp(&b.id));
+        diff.removed_entities.sort_by(|a, b| a.id.cmp(&b.id));
+        diff.added_relationships.sort_by(|a, b| edge_key(a).cmp(&edge_key(b)));
+        diff.removed_relationships.sort_by(|a, b| edge_key(a).cmp(&edge_key(b)));
+        diff.changed_strength.sort_by(|a, b| (&a.source, &a.target, &a.rel_type).cmp(&(&b.source, &b.target, &b.rel_type)));
+        diff
+    }
+
+    /// Structural problems with the edges, in edge order.
+    pub fn validate(&self) -> Vec<GraphIssue> {
+        let mut issues = Vec::new();
+        for (edge, r) in self.edges.iter().enumerate() {
+            if r.source == r.target {
+                issues.push(GraphIssue::SelfLoop { edge, id: r.source.clone() });
+                continue;
+            }
+            for end in [&r.source, &r.target] {
+                if !self.nodes.contains_key(end) { issues.push(GraphIssue::DanglingEdge { edge, missing: end.clone() }); }
+            }
+        }
+        issues
+    }
+
+    /// Removes the edges `validate` flags: those touching a missing entity and self-loops.
+    /// Returns how many were removed.
+    pub fn prune_dangling(&mut self) -> usize {
+        let before = self.edges.len();
+        let nodes = &self.nodes;
+        self.edges.retain(|r| r.source != r.target && nodes.contains_key(&r.source) && nodes.contains_key(&r.target));
+        before - self.edges.len()
+    }
+
+    // Distinct neighbors of every entity, isolated ones included, without self-loops.
+    fn distinct_adjacency(&self) -> HashMap<&EntityId, HashSet<&EntityId>> {
+        let mut adj: HashMap<&EntityId, HashSet<&EntityId>> = self.nodes.keys().map(|id| (id, HashSet::new())).collect();
+        for (from, tos) in self.adjacency() {
+            adj.entry(from).or_default().extend(tos.into_iter().filter(|&to| to != from));
+        }
+        adj
+    }
+
+    fn adjacency(&self) -> HashMap<&EntityId, Vec<&EntityId>> {
+        let mut adj: HashMap<&EntityId, Vec<&EntityId>> = HashMap::new();
+        for edge in &self.edges {
+            if !self.nodes.contains_key(&edge.source) || !self.nodes.contains_key(&edge.target) { continue; }
+            adj.entry(&edge.source).or_default().push(&edge.target);
+            adj.entry(&edge.target).or_default().push(&edge.source);
+        }
+        adj
+    }
+
     pub fn to_graphml(&self) -> String {
         let mut s = String::new();
         s.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
         s.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
-        s.push_str("  <graph id=\"G\" edgedefault=\"undirected\">\n");
-        for (id, e) in &self.nodes {
-            s.push_str(&format!("    <node id=\"{}\"><data key=\"label\">{}</data></node>\n", xml_escape(id), xml_escape(&e.name)));
+        s.push_str("  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n");
+        s.push_str("  <key id=\"entity_type\" for=\"node\" attr.name=\"entity_type\" attr.type=\"string\"/>\n");
+        s.push_str("  <key id=\"type\" for=\"edge\" attr.name=\"type\" attr.type=\"string\"/>\n");
+        s.push_str("  <key id=\"strength\" for=\"edge\" attr.name=\"strength\" attr.type=\"double\"/>\n");
+        s.push_str("  <key id=\"description\" for=\"all\" attr.name=\"description\" attr.type=\"string\"/>\n");
+        s.push_str("  <key id=\"source_chunks\" for=\"all\" attr.name=\"source_chunks\" attr.type=\"string\"/>\n");
+        s.push_str(&format!("  <graph id=\"G\" edgedefault=\"{}\">\n", if self.directed { "directed" } else { "undirected" }));
+        for id in self.sorted_ids() {
+            let e = &self.nodes[id];
+            s.push_str(&format!(
+                "    <node id=\"{}\"><data key=\"label\">{}</data><data key=\"entity_type\">{}</data><data key=\"description\">{}</data><data key=\"source_chunks\">{}</data></node>\n",
+                xml_escape(id), xml_escape(&e.name), xml_escape(&e.entity_type), xml_escape(&e.description), xml_escape(&e.source_chunks.join(","))
+            ));
         }
         for (i, r) in self.edges.iter().enumerate() {
             s.push_str(&format!(
-                "    <edge id=\"e{}\" source=\"{}\" target=\"{}\"><data key=\"type\">{}</data></edge>\n",
-                i, xml_escape(&r.source), xml_escape(&r.target), xml_escape(&r.rel_type)
+                "    <edge id=\"e{}\" source=\"{}\" target=\"{}\"><data key=\"type\">{}</data><data key=\"strength\">{}</data><data key=\"description\">{}</data><data key=\"source_chunks\">{}</data></edge>\n",
+                i, xml_escape(&r.source), xml_escape(&r.target), xml_escape(&r.rel_type), r.strength, xml_escape(&r.description), xml_escape(&r.source_chunks.join(","))
             ));
         }
         s.push_str("  </graph>\n</graphml>\n");
         s
     }
+
+    /// Parses GraphML as written by `to_graphml`. Missing `<data>` values fall back to empty
+    /// strings, `strength` to 1.0.
+    pub fn from_graphml(xml: &str) -> Result<KnowledgeGraph> {
+        let doc = roxmltree::Document::parse(xml).context("invalid GraphML")?;
+        let graph_el = doc.descendants().find(|n| n.has_tag_name("graph")).context("GraphML has no <graph> element")?;
+        let mut graph = KnowledgeGraph { directed: graph_el.attribute("edgedefault") == Some("directed"), ..Default::default() };
+        for el in graph_el.children().filter(|n| n.is_element()) {
+            let data = |key: &str| -> String {
+                el.children()
+                    .find(|d| d.has_tag_name("data") && d.attribute("key") == Some(key))
+                    .and_then(|d| d.text())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            let chunks = |v: String| -> Vec<String> { v.split(',').filter(|c| !c.is_empty()).map(str::to_string).collect() };
+            if el.has_tag_name("node") {
+                let id = el.attribute("id").context("GraphML node without id")?.to_string();
+                let name = data("label");
+                let entity = Entity {
+                    id: id.clone(),
+                    name: if name.is_empty() { id.clone() } else { name },
+                    entity_type: data("entity_type"),
+                    description: data("description"),
+                    source_chunks: chunks(data("source_chunks")),
+                    confidence: None,
+                };
+                graph.nodes.insert(id, entity);
+            } else if el.has_tag_name("edge") {
+                graph.edges.push(Relationship {
+                    source: el.attribute("source").context("GraphML edge without source")?.to_string(),
+                    target: el.attribute("target").context("GraphML edge without target")?.to_string(),
+                    rel_type: data("type"),
+                    description: data("description"),
+                    strength: data("strength").parse().unwrap_or(1.0),
+                    source_chunks: chunks(data("source_chunks")),
+                });
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Graphviz DOT, with entity names as node labels and `rel_type` as edge labels.
+    pub fn to_dot(&self) -> String {
+        let (kind, op) = if self.directed { ("digraph", "->") } else { ("graph", "--") };
+        let mut s = format!("{} G {{\n", kind);
+        for id in self.sorted_ids() {
+            s.push_str(&format!("  \"{}\" [label=\"{}\"];\n", dot_escape(id), dot_escape(&self.nodes[id].name)));
+        }
+        for r in &self.edges {
+            s.push_str(&format!("  \"{}\" {} \"{}\" [label=\"{}\"];\n", dot_escape(&r.source), op, dot_escape(&r.target), dot_escape(&r.rel_type)));
+        }
+        s.push_str("}\n");
+        s
+    }
+
+    /// GEXF 1.3 (Gephi), with `strength` as edge weight.
+    pub fn to_gexf(&self) -> String {
+        let mut s = String::new();
+        s.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        s.push_str("<gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">\n");
+        s.push_str(&format!("  <graph defaultedgetype=\"{}\">\n", if self.directed { "directed" } else { "undirected" }));
+        s.push_str("    <nodes>\n");
+        for id in self.sorted_ids() {
+            s.push_str(&format!("      <node id=\"{}\" label=\"{}\"/>\n", xml_escape(id), xml_escape(&self.nodes[id].name)));
+        }
+        s.push_str("    </nodes>\n    <edges>\n");
+        for (i, r) in self.edges.iter().enumerate() {
+            s.push_str(&format!(
+                "      <edge id=\"e{}\" source=\"{}\" target=\"{}\" label=\"{}\" weight=\"{}\"/>\n",
+                i, xml_escape(&r.source), xml_escape(&r.target), xml_escape(&r.rel_type), r.strength
+            ));
+        }
+        s.push_str("    </edges>\n  </graph>\n</gexf>\n");
+        s
+    }
+
+    /// Node and edge CSV bodies: `id,name,entity_type,description` and
+    /// `source,target,rel_type,strength,description`.
+    pub fn to_csv(&self) -> (String, String) {
+        let mut nodes = String::from("id,name,entity_type,description\n");
+        for id in self.sorted_ids() {
+            let e = &self.nodes[id];
+            nodes.push_str(&format!("{},{},{},{}\n", csv_escape(id), csv_escape(&e.name), csv_escape(&e.entity_type), csv_escape(&e.description)));
+        }
+        let mut edges = String::from("source,target,rel_type,strength,description\n");
+        for r in &self.edges {
+            edges.push_str(&format!("{},{},{},{},{}\n", csv_escape(&r.source), csv_escape(&r.target), csv_escape(&r.rel_type), r.strength, csv_escape(&r.description)));
+        }
+        (nodes, edges)
+    }
+
+    /// Neo4j Cypher: one `CREATE` per entity (labelled by `entity_type`) and one `MATCH ... CREATE`
+    /// per relationship (typed by `rel_type`), each statement on its own line.
+    pub fn to_cypher(&self) -> String {
+        let mut s = String::new();
+        for id in self.sorted_ids() {
+            let e = &self.nodes[id];
+            s.push_str(&format!(
+                "CREATE (:{} {{id: '{}', name: '{}', description: '{}'}});\n",
+                cypher_ident(&e.entity_type, "Entity"), cypher_escape(id), cypher_escape(&e.name), cypher_escape(&e.description)
+            ));
+        }
+        for r in &self.edges {
+            s.push_str(&format!(
+                "MATCH (a {{id: '{}'}}), (b {{id: '{}'}}) CREATE (a)-[:{} {{strength: {}, description: '{}'}}]->(b);\n",
+                cypher_escape(&r.source), cypher_escape(&r.target), cypher_ident(&r.rel_type, "RELATED_TO").to_ascii_uppercase(), r.strength, cypher_escape(&r.description)
+            ));
+        }
+        s
+    }
+
+    fn sorted_ids(&self) -> Vec<&EntityId> {
+        let mut ids: Vec<&EntityId> = self.nodes.keys().collect();
+        ids.sort();
+        ids
+    }
+}
+
+/// Lowest `search_entities` score worth returning.
+pub const ENTITY_MATCH_THRESHOLD: f32 = 0.8;
+
+// Both arguments lowercased. A match on words alone scores a little below a whole-name match, so
+// "Einstein" ranks an entity named exactly that above "Albert Einstein". Whole names use plain
+// Jaro: the prefix bonus would make "Albert Einstein" look close to "Albert Camus".
+fn name_similarity(query: &str, name: &str) -> f32 {
+    let words = |s: &str| s.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).map(|w| w.chars().collect::<Vec<_>>()).collect::<Vec<_>>();
+    let whole = jaro(&query.chars().collect::<Vec<_>>(), &name.chars().collect::<Vec<_>>());
+    let (query_words, name_words) = (words(query), words(name));
+    if query_words.is_empty() || name_words.is_empty() { return whole as f32; }
+    let by_word = query_words.iter().map(|q| name_words.iter().map(|n| jaro_winkler(q, n)).fold(0.0, f64::max)).sum::<f64>() / query_words.len() as f64;
+    whole.max(0.9 * by_word) as f32
+}
+
+fn jaro_winkler(a: &[char], b: &[char]) -> f64 {
+    let jaro = jaro(a, b);
+    let prefix = a.iter().zip(b).take(4).take_while(|(x, y)| x == y).count() as f64;
+    jaro + prefix * 0.1 * (1.0 - jaro)
+}
+
+fn jaro(a: &[char], b: &[char]) -> f64 {
+    if a.is_empty() || b.is_empty() { return if a == b { 1.0 } else { 0.0 }; }
+    let window = (a.len().max(b.len()) / 2).saturating_sub(1);
+    let mut b_matched = vec![false; b.len()];
+    let mut a_matches = Vec::new();
+    for (i, ca) in a.iter().enumerate() {
+        let lo = i.saturating_sub(window);
+        let hi = (i + window + 1).min(b.len());
+        if let Some(j) = (lo..hi).find(|&j| !b_matched[j] && b[j] == *ca) {
+            b_matched[j] = true;
+            a_matches.push(*ca);
+        }
+    }
+    if a_matches.is_empty() { return 0.0; }
+    let b_matches = b.iter().zip(&b_matched).filter(|(_, &m)| m).map(|(c, _)| *c);
+    let transpositions = a_matches.iter().zip(b_matches).filter(|(x, y)| **x != *y).count() as f64 / 2.0;
+    let m = a_matches.len() as f64;
+    (m / a.len() as f64 + m / b.len() as f64 + (m - transpositions) / m) / 3.0
+}
+
+/// Types that say nothing about an entity: the heuristic extractor's `Concept` and the `Unknown`
+/// placeholder from `resolve_entity`.
+const GENERIC_TYPES: &[&str] = &["", "concept", "unknown"];
+
+/// Folds `other` into `entity`: a specific type replaces a generic one, and new descriptions and
+/// source chunks are appended.
+fn absorb(entity: &mut Entity, other: &Entity) {
+    let is_generic = |ty: &str| GENERIC_TYPES.contains(&ty.trim().to_ascii_lowercase().as_str());
+    if is_generic(&entity.entity_type) && !is_generic(&other.entity_type) { entity.entity_type = other.entity_type.clone(); }
+    if !other.description.is_empty() && !entity.description.contains(&other.description) {
+        if !entity.description.is_empty() { entity.description.push_str(" \u{2014} "); }
+        entity.description.push_str(&other.description);
+    }
+    for chunk in &other.source_chunks {
+        if !entity.source_chunks.contains(chunk) { entity.source_chunks.push(chunk.clone()); }
+    }
+    // Any confident sighting vouches for the entity.
+    entity.confidence = match (entity.confidence, other.confidence) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    };
+}
+
+fn edge_key(r: &Relationship) -> (&str, &str, &str) { (r.source.as_str(), r.target.as_str(), r.rel_type.as_str()) }
+
+/// The sanitized name plus a hash of the lowercased name, so an entity gets the same id in every
+/// run whatever was added before it, and names that sanitize alike ("C++", "C#") stay apart.
+pub(crate) fn entity_id(name: &str) -> EntityId {
+    format!("{}-{:08x}", sanitize_id(name), crate::fnv1a(name.to_ascii_lowercase().as_bytes()) as u32)
 }
 
 fn sanitize_id(s: &str) -> String {
     s.to_ascii_lowercase().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect::<String>()
 }
 
-fn xml_escape(s: &str) -> String { s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;") }
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+fn dot_escape(s: &str) -> String { s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n") }
+
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() }
+}
+
+fn cypher_escape(s: &str) -> String { s.replace('\\', "\\\\").replace('\'', "\\'").replace('\n', "\\n") }
+
+// Labels and relationship types must be bare identifiers: letters, digits and underscores, not starting with a digit.
+fn cypher_ident(s: &str, fallback: &str) -> String {
+    let ident: String = s.trim().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    match ident.chars().next() {
+        None => fallback.to_string(),
+        Some(c) if c.is_ascii_digit() => format!("_{}", ident),
+        Some(_) => ident,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entity(name: &str, entity_type: &str, chunk: &str) -> Entity {
+        Entity { id: String::new(), name: name.into(), entity_type: entity_type.into(), description: String::new(), source_chunks: vec![chunk.into()], confidence: None }
+    }
+
+    fn rel(source: &str, target: &str, strength: f32, description: &str) -> Relationship {
+        Relationship { source: source.into(), target: target.into(), rel_type: "knows".into(), description: description.into(), strength, source_chunks: Vec::new() }
+    }
+
+    /// Nodes keyed by their names, so tests can refer to them directly.
+    fn graph(edges: &[(&str, &str)], isolated: &[&str]) -> KnowledgeGraph {
+        let mut g = KnowledgeGraph::default();
+        for &name in edges.iter().flat_map(|(a, b)| [a, b]).chain(isolated) {
+            g.nodes.entry(name.into()).or_insert_with(|| Entity { id: name.into(), ..entity(name, "Thing", "c0") });
+        }
+        for &(a, b) in edges { g.edges.push(rel(a, b, 1.0, "")); }
+        g
+    }
+
+    fn ids(names: &[&str]) -> HashSet<EntityId> { names.iter().map(|n| n.to_string()).collect() }
+
+    #[test]
+    fn repeated_relationships_aggregate_strength() {
+        for (agg, expected) in [(StrengthAggregation::Sum, 1.75), (StrengthAggregation::Max, 1.0)] {
+            let mut g = KnowledgeGraph::default();
+            for (strength, description) in [(0.5, "met at work"), (1.0, ""), (0.25, "met at work")] {
+                g.add_relationship_with(rel("a", "b", strength, description), agg);
+            }
+            assert_eq!(g.edges.len(), 1);
+            assert_eq!(g.edges[0].strength, expected);
+            assert_eq!(g.edges[0].description, "met at work");
+        }
+    }
+
+    #[test]
+    fn near_duplicate_entities_collapse() {
+        let mut g = KnowledgeGraph::default();
+        let acme = g.add_entity(entity("Acme Corporation", "Org", "c1"));
+        g.add_entity(entity("Acme Corporation Inc", "Org", "c2"));
+        g.add_entity(entity("Acme Corporation Ltd", "Product", "c3"));
+        g.add_entity(entity("Zebra Crossing", "Org", "c4"));
+        let merged = g.dedupe_by_embedding(&crate::vector::ByteHistogramEmbedder::default(), 0.9);
+        assert_eq!(merged, 1);
+        assert_eq!(g.nodes.len(), 3);
+        let mut chunks = g.nodes[&acme].source_chunks.clone();
+        chunks.sort();
+        assert_eq!(chunks, ["c1", "c2"]);
+    }
+
+    #[test]
+    fn paths_and_neighborhoods_ignore_direction() {
+        // a-b-c-d as a chain whose last edge points backwards, a separate x-y pair, and a lone z.
+        let g = graph(&[("a", "b"), ("b", "c"), ("d", "c"), ("x", "y")], &["z"]);
+        let path = |from: &str, to: &str| g.shortest_path(&from.into(), &to.into());
+        assert_eq!(path("a", "b").unwrap(), ["a", "b"]);
+        assert_eq!(path("a", "d").unwrap(), ["a", "b", "c", "d"]);
+        assert_eq!(path("d", "a").unwrap(), ["d", "c", "b", "a"]);
+        assert_eq!(path("z", "z").unwrap(), ["z"]);
+        assert!(path("a", "x").is_none());
+        assert!(path("a", "z").is_none());
+        assert!(path("a", "missing").is_none());
+
+        assert_eq!(g.neighborhood(&"a".into(), 0), ids(&["a"]));
+        assert_eq!(g.neighborhood(&"a".into(), 2), ids(&["a", "b", "c"]));
+        assert_eq!(g.neighborhood(&"c".into(), 1), ids(&["b", "c", "d"]));
+        assert_eq!(g.neighborhood(&"a".into(), 10), ids(&["a", "b", "c", "d"]));
+        assert_eq!(g.neighborhood(&"z".into(), 3), ids(&["z"]));
+    }
+
+    #[test]
+    fn subgraph_keeps_the_neighborhood_and_its_edges() {
+        let g = graph(&[("a", "b"), ("b", "c"), ("c", "d"), ("a", "c")], &["z"]);
+        let sub = g.subgraph(&["a".into()], 1);
+        assert_eq!(sub.nodes.keys().cloned().collect::<HashSet<_>>(), ids(&["a", "b", "c"]));
+        // b-c lies inside the neighborhood even though neither endpoint is the seed; c-d leaves it.
+        let mut edges: Vec<_> = sub.edges.iter().map(|e| (e.source.as_str(), e.target.as_str())).collect();
+        edges.sort();
+        assert_eq!(edges, [("a", "b"), ("a", "c"), ("b", "c")]);
+
+        let lone = g.subgraph(&["z".into()], 2);
+        assert_eq!(lone.nodes.keys().cloned().collect::<HashSet<_>>(), ids(&["z"]));
+        assert!(lone.edges.is_empty());
+    }
+
+    #[test]
+    fn neighbors_follow_direction_only_when_directed() {
+        let mut g = graph(&[("a", "b"), ("c", "a"), ("a", "d")], &[]);
+        let names = |v: Vec<&Entity>| { let mut n: Vec<_> = v.into_iter().map(|e| e.id.clone()).collect(); n.sort(); n };
+        let a: EntityId = "a".into();
+        for list in [g.neighbors(&a), g.out_neighbors(&a), g.in_neighbors(&a)] { assert_eq!(names(list), ["b", "c", "d"]); }
+
+        g.directed = true;
+        assert_eq!(names(g.out_neighbors(&a)), ["b", "d"]);
+        assert_eq!(names(g.in_neighbors(&a)), ["c"]);
+        assert_eq!(names(g.neighbors(&a)), ["b", "c", "d"]);
+        assert!(g.out_neighbors(&"b".into()).is_empty());
+    }
+
+    /// A three-node graph whose names and descriptions need escaping in every export format.
+    fn tricky() -> KnowledgeGraph {
+        let mut g = graph(&[("a", "b"), ("b", "c")], &[]);
+        g.nodes.get_mut("a").unwrap().name = r#"Say "hi" & <leave>"#.into();
+        g.nodes.get_mut("b").unwrap().name = "O'Brien, Jr.".into();
+        g.nodes.get_mut("c").unwrap().description = "line one\nline \\two".into();
+        g.edges[0].description = "said \"hi\", then left".into();
+        g
+    }
+
+    #[test]
+    fn dot_and_gexf_have_every_node_and_edge() {
+        let mut g = tricky();
+        for directed in [false, true] {
+            g.directed = directed;
+            let dot = g.to_dot();
+            let op = if directed { "\" -> \"" } else { "\" -- \"" };
+            assert!(dot.starts_with(if directed { "digraph G {" } else { "graph G {" }) && dot.trim_end().ends_with('}'));
+            let statements: Vec<&str> = dot.lines().filter(|l| l.ends_with("];")).collect();
+            assert_eq!(statements.iter().filter(|l| l.contains(op)).count(), 2);
+            assert_eq!(statements.len(), 5);
+            assert!(dot.contains(r#"[label="Say \"hi\" & <leave>"]"#));
+
+            let gexf = g.to_gexf();
+            let doc = roxmltree::Document::parse(&gexf).expect("GEXF is well-formed XML");
+            assert_eq!(doc.descendants().filter(|n| n.has_tag_name("node")).count(), 3);
+            assert_eq!(doc.descendants().filter(|n| n.has_tag_name("edge")).count(), 2);
+            let label = doc.descendants().find(|n| n.attribute("id") == Some("a")).and_then(|n| n.attribute("label"));
+            assert_eq!(label, Some(r#"Say "hi" & <leave>"#));
+        }
+    }
+
+    #[test]
+    fn csv_fields_with_commas_quotes_and_newlines_parse_back() {
+        let g = tricky();
+        let (nodes, edges) = g.to_csv();
+        let rows = |body: &str| -> Vec<Vec<String>> {
+            csv::Reader::from_reader(body.as_bytes()).records().map(|r| r.unwrap().iter().map(str::to_string).collect()).collect()
+        };
+        let nodes = rows(&nodes);
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(nodes[0][1], r#"Say "hi" & <leave>"#);
+        assert_eq!(nodes[1][1], "O'Brien, Jr.");
+        assert_eq!(nodes[2][3], "line one\nline \\two");
+        let edges = rows(&edges);
+        assert_eq!(edges.len(), 2);
+        assert_eq!(edges[0], ["a", "b", "knows", "1", "said \"hi\", then left"]);
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("cr\ronly"), "\"cr\ronly\"");
+    }
+
+    #[test]
+    fn cypher_has_a_statement_per_node_and_edge() {
+        let mut g = tricky();
+        g.nodes.get_mut("b").unwrap().entity_type = "9 lives".into();
+        g.edges[1].rel_type = "works-for".into();
+        let cypher = g.to_cypher();
+        let statements: Vec<&str> = cypher.lines().collect();
+        assert_eq!(statements.len(), g.nodes.len() + g.edges.len());
+        assert!(statements.iter().all(|s| s.ends_with(';')));
+        assert_eq!(statements.iter().filter(|s| s.starts_with("CREATE (:")).count(), 3);
+        assert_eq!(statements.iter().filter(|s| s.starts_with("MATCH ")).count(), 2);
+        assert!(statements[1].starts_with(r"CREATE (:_9_lives {id: 'b', name: 'O\'Brien, Jr.'"));
+        assert!(statements[2].contains(r"description: 'line one\nline \\two'"));
+        assert_eq!(statements[4], "MATCH (a {id: 'b'}), (b {id: 'c'}) CREATE (a)-[:WORKS_FOR {strength: 1, description: ''}]->(b);");
+    }
+
+    #[test]
+    fn graphml_round_trips_tricky_names() {
+        let mut g = tricky();
+        let odd = g.add_entity(entity(r#"Tom & "Jerry's" <show>"#, "Show & Tell", "c1"));
+        g.add_relationship(Relationship { strength: 0.25, source_chunks: vec!["c9".into()], ..rel("a", &odd, 0.0, "A & B \"quoted\"") });
+        g.directed = true;
+        let back = KnowledgeGraph::from_graphml(&g.to_graphml()).unwrap();
+        assert!(back.directed);
+        assert_eq!(serde_json::to_value(&back).unwrap(), serde_json::to_value(&g).unwrap());
+    }
+
+    fn edge_list(g: &KnowledgeGraph) -> Vec<(&str, &str)> {
+        let mut edges: Vec<_> = g.edges.iter().map(|e| (e.source.as_str(), e.target.as_str())).collect();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn merging_aliases_rewires_edges_and_joins_descriptions() {
+        let mut g = graph(&[("ibm", "watson"), ("i.b.m.", "armonk"), ("watson", "intl"), ("ibm", "intl")], &[]);
+        for (id, description) in [("ibm", "tech company"), ("i.b.m.", "tech company"), ("intl", "makes mainframes")] {
+            g.nodes.get_mut(id).unwrap().description = description.into();
+        }
+        let merged = g.merge_all(&[vec!["ibm".into(), "i.b.m.".into(), "intl".into()]]);
+        assert_eq!(merged, 2);
+        assert_eq!(g.nodes.keys().cloned().collect::<HashSet<_>>(), ids(&["ibm", "watson", "armonk"]));
+        assert_eq!(g.nodes["ibm"].description, "tech company \u{2014} makes mainframes");
+        // The ibm-intl edge became a self-loop and is gone; the rest now point at ibm.
+        assert_eq!(edge_list(&g), [("ibm", "armonk"), ("ibm", "watson"), ("watson", "ibm")]);
+    }
+
+    #[test]
+    fn unmerge_restores_the_original_graph() {
+        let mut g = graph(&[("a", "x"), ("b", "y"), ("a", "b"), ("x", "b"), ("y", "z")], &["lone"]);
+        g.nodes.get_mut("a").unwrap().description = "first".into();
+        g.nodes.get_mut("b").unwrap().description = "second".into();
+        let original = serde_json::to_value(&g).unwrap();
+
+        let record = g.merge_entities_recording(&"a".into(), &"b".into()).unwrap();
+        assert!(!g.nodes.contains_key("b"));
+        assert_eq!(g.edges.len(), 4);
+        g.unmerge(record);
+        assert_eq!(serde_json::to_value(&g).unwrap(), original);
+        assert!(g.merge_entities_recording(&"a".into(), &"missing".into()).is_none());
+    }
+
+    #[test]
+    fn dangling_edges_are_found_and_pruned() {
+        let mut g = graph(&[("a", "b"), ("b", "c")], &[]);
+        g.edges.insert(1, rel("a", "ghost", 1.0, ""));
+        g.edges.push(rel("c", "c", 1.0, ""));
+        assert_eq!(g.validate(), [GraphIssue::DanglingEdge { edge: 1, missing: "ghost".into() }, GraphIssue::SelfLoop { edge: 3, id: "c".into() }]);
+        assert_eq!(g.validate()[0].to_string(), "edge 1 points to missing entity 'ghost'");
+        assert_eq!(g.prune_dangling(), 2);
+        assert!(g.validate().is_empty());
+        assert_eq!(edge_list(&g), [("a", "b"), ("b", "c")]);
+    }
+
+    #[test]
+    fn type_filter_drops_other_nodes_and_their_edges() {
+        let mut g = graph(&[("ada", "alan"), ("ada", "london"), ("alan", "manchester"), ("london", "manchester")], &["grace"]);
+        for (id, ty) in [("ada", "Person"), ("alan", "person"), ("grace", "Person"), ("london", "City"), ("manchester", "City")] {
+            g.nodes.get_mut(id).unwrap().entity_type = ty.into();
+        }
+        let people = g.filter_by_types(&["PERSON"]);
+        assert_eq!(people.nodes.keys().cloned().collect::<HashSet<_>>(), ids(&["ada", "alan", "grace"]));
+        assert_eq!(edge_list(&people), [("ada", "alan")]);
+        let none = g.filter_by_types(&["Planet"]);
+        assert!(none.nodes.is_empty() && none.edges.is_empty());
+    }
+
+    #[test]
+    fn degrees_and_betweenness_on_a_small_tree() {
+        // Hub h with leaves a and b, and a c-d tail; the repeated h-a edge and c's self-loop add nothing.
+        let g = graph(&[("h", "a"), ("a", "h"), ("h", "b"), ("h", "c"), ("c", "d"), ("c", "c")], &["lone"]);
+        let degrees: BTreeMap<&str, usize> = g.degrees().into_iter().map(|(id, d)| (id.as_str(), d)).collect();
+        assert_eq!(degrees, BTreeMap::from([("a", 1), ("b", 1), ("c", 2), ("d", 1), ("h", 3), ("lone", 0)]));
+        assert_eq!(g.degree(&"h".into()), 3);
+        assert_eq!(g.degree_distribution(), BTreeMap::from([(0, 1), (1, 3), (2, 1), (3, 1)]));
+
+        // h sits on the a-b, a-c, a-d, b-c and b-d paths; c on h-d, a-d and b-d.
+        let betweenness = g.betweenness_centrality();
+        assert_eq!((betweenness["h"], betweenness["c"]), (5.0, 3.0));
+        for leaf in ["a", "b", "d", "lone"] { assert_eq!(betweenness[leaf], 0.0); }
+    }
+
+    #[test]
+    fn components_include_isolated_entities() {
+        let g = graph(&[("a", "b"), ("c", "b"), ("b", "d"), ("x", "y")], &["lone"]);
+        let components = g.connected_components();
+        assert_eq!(components, [vec!["a", "b", "c", "d"], vec!["x", "y"], vec!["lone"]]);
+    }
+
+    #[test]
+    fn ids_do_not_depend_on_insertion_order() {
+        let names = ["Ada Lovelace", "Charles Babbage", "C++", "C#", "London"];
+        let build = |order: &[&str]| {
+            let mut g = KnowledgeGraph::default();
+            order.iter().map(|n| (n.to_string(), g.add_entity(entity(n, "Thing", "c0")))).collect::<HashMap<_, _>>()
+        };
+        let forward = build(&names);
+        let reversed: Vec<_> = names.iter().rev().copied().collect();
+        assert_eq!(forward, build(&reversed));
+        // Names that sanitize alike still get distinct ids.
+        assert_ne!(forward["C++"], forward["C#"]);
+        assert_eq!(forward.values().collect::<HashSet<_>>().len(), names.len());
+    }
+
+    #[test]
+    fn readding_an_entity_enriches_it() {
+        let mut g = KnowledgeGraph::default();
+        let id = g.add_entity(Entity { description: "an early programmer".into(), confidence: Some(0.4), ..entity("Ada Lovelace", "concept", "c0") });
+        let again = g.add_entity(Entity { description: "wrote the first published algorithm".into(), confidence: Some(0.9), ..entity("ada lovelace", "Person", "c1") });
+        // A generic type never overwrites a specific one, and a repeated description isn't added twice.
+        g.add_entity(Entity { description: "an early programmer".into(), ..entity("ADA LOVELACE", "Unknown", "c0") });
+        assert_eq!(again, id);
+        assert_eq!(g.nodes.len(), 1);
+        let ada = &g.nodes[&id];
+        assert_eq!((ada.name.as_str(), ada.entity_type.as_str()), ("Ada Lovelace", "Person"));
+        assert_eq!(ada.description, "an early programmer \u{2014} wrote the first published algorithm");
+        assert_eq!(ada.source_chunks, ["c0", "c1"]);
+        assert_eq!(ada.confidence, Some(0.9));
+    }
+
+    #[test]
+    fn einstein_finds_albert_einstein_first() {
+        let mut g = KnowledgeGraph::default();
+        for name in ["Albert Einstein", "Albert Camus", "Marie Curie", "Niels Bohr"] { g.add_entity(entity(name, "Person", "c0")); }
+        let names = |query: &str| g.search_entities(query, 3).into_iter().map(|(e, _)| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names("Einstein")[0], "Albert Einstein");
+        assert_eq!(names("einstien")[0], "Albert Einstein");
+        assert!(!names("Einstein").contains(&"Marie Curie") && !names("Einstein").contains(&"Albert Camus"));
+        let score = |query: &str, name: &str| g.search_entities(query, 5).into_iter().find(|(e, _)| e.name == name).map_or(0.0, |(_, s)| s);
+        assert!(score("Einstein", "Albert Einstein") > score("Einstein", "Albert Camus"));
+        assert!(names("Zebra").is_empty());
+    }
+
+    #[test]
+    fn diff_holds_exactly_the_added_node_and_edge() {
+        let old = graph(&[("a", "b"), ("b", "c")], &["lone"]);
+        let mut new = old.clone();
+        new.nodes.insert("d".into(), Entity { id: "d".into(), ..entity("d", "Thing", "c1") });
+        new.edges.push(rel("c", "d", 1.0, ""));
+        let diff = old.diff(&new);
+        assert_eq!(diff.added_entities.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(), ["d"]);
+        assert_eq!(diff.added_relationships.iter().map(|r| (r.source.as_str(), r.target.as_str())).collect::<Vec<_>>(), [("c", "d")]);
+        assert!(diff.removed_entities.is_empty() && diff.removed_relationships.is_empty() && diff.changed_strength.is_empty());
+        assert!(old.diff(&old).is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+use sha2::{Digest, Sha256};
+
+use crate::document::Chunk;
+
+/// Names chunks and new entities. Set on `ParseOptions::ids` for chunks and passed to
+/// `KnowledgeGraph::add_entity_with` for entities; `Standard` is what both use by default.
+pub trait IdStrategy: Send + Sync + std::fmt::Debug {
+    /// Id for `chunk`, the `idx`th (from 0) of its document; `chunk.id` holds the built-in id.
+    fn chunk_id(&self, chunk: &Chunk, idx: usize) -> String;
+    /// Id for a new entity named `name` joining a graph of `n` entities. `add_entity_with`
+    /// suffixes it if the graph already has that id.
+    fn entity_id(&self, name: &str, n: usize) -> String;
+}
+
+/// The built-in scheme: chunks `{path hash}-chunk-{n}` (records `-row-{n}` / `-line-{n}`) and
+/// entities `{sanitized name}-{name hash}`, so the same name gets the same id in any graph.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Standard;
+
+impl IdStrategy for Standard {
+    fn chunk_id(&self, chunk: &Chunk, _idx: usize) -> String { chunk.id.clone() }
+
+    fn entity_id(&self, name: &str, _n: usize) -> String { crate::graph::entity_id(name) }
+}
+
+/// Numbers in order: a document's chunks `{path hash}-{n}` and entities `entity-{n}` by arrival.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sequential;
+
+impl IdStrategy for Sequential {
+    fn chunk_id(&self, chunk: &Chunk, idx: usize) -> String {
+        match &chunk.source_path {
+            Some(path) => format!("{:016x}-{}", crate::fnv1a(path.to_string_lossy().as_bytes()), idx),
+            None => idx.to_string(),
+        }
+    }
+
+    fn entity_id(&self, _name: &str, n: usize) -> String { format!("entity-{}", n) }
+}
+
+/// 16 hex digits of SHA-256 over a chunk's source path and text, or an entity's lowercased name,
+/// so unchanged content keeps its id across runs and machines.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ContentHash;
+
+impl IdStrategy for ContentHash {
+    fn chunk_id(&self, chunk: &Chunk, _idx: usize) -> String {
+        let mut hasher = Sha256::new();
+        if let Some(path) = &chunk.source_path { hasher.update(path.to_string_lossy().as_bytes()); }
+        hasher.update([0]);
+        hasher.update(chunk.text.as_bytes());
+        short_hex(&hasher.finalize())
+    }
+
+    fn entity_id(&self, name: &str, _n: usize) -> String { short_hex(&Sha256::digest(name.to_lowercase().as_bytes())) }
+}
+
+/// A random (v4) UUID for every chunk and entity.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Uuid;
+
+impl IdStrategy for Uuid {
+    fn chunk_id(&self, _chunk: &Chunk, _idx: usize) -> String { uuid::Uuid::new_v4().to_string() }
+
+    fn entity_id(&self, _name: &str, _n: usize) -> String { uuid::Uuid::new_v4().to_string() }
+}
+
+fn short_hex(digest: &[u8]) -> String { digest[..8].iter().map(|b| format!("{:02x}", b)).collect() }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn chunk(path: Option<&str>, text: &str) -> Chunk {
+        Chunk { id: "builtin-id".into(), text: text.into(), source_path: path.map(PathBuf::from), ..Chunk::default() }
+    }
+
+    #[test]
+    fn each_strategy_gives_its_id_shape() {
+        let ada = chunk(Some("docs/a.md"), "Ada Lovelace wrote notes.");
+        assert_eq!(Standard.chunk_id(&ada, 3), "builtin-id");
+        let standard = Standard.entity_id("Ada Lovelace", 7);
+        assert!(standard.starts_with("ada-lovelace-") && standard.len() == "ada-lovelace-".len() + 8, "{}", standard);
+
+        let path_hash = format!("{:016x}", crate::fnv1a(b"docs/a.md"));
+        assert_eq!(Sequential.chunk_id(&ada, 3), format!("{}-3", path_hash));
+        assert_eq!(Sequential.chunk_id(&chunk(None, "x"), 3), "3");
+        assert_eq!(Sequential.entity_id("Ada Lovelace", 7), "entity-7");
+
+        let uuid = Uuid.chunk_id(&ada, 0);
+        assert_eq!(uuid::Uuid::parse_str(&uuid).unwrap().get_version_num(), 4);
+        assert_ne!(uuid, Uuid.chunk_id(&ada, 0));
+        assert_ne!(Uuid.entity_id("Ada", 0), Uuid.entity_id("Ada", 0));
+    }
+
+    #[test]
+    fn content_hash_ids_are_fixed_by_content() {
+        // Hard-coded so a change to the hashing, which would orphan saved ids, fails here.
+        let ada = chunk(Some("docs/a.md"), "Ada Lovelace wrote notes.");
+        assert_eq!(ContentHash.chunk_id(&ada, 0), "452c505817f3e087");
+        assert_eq!(ContentHash.chunk_id(&ada, 9), "452c505817f3e087");
+        assert_eq!(ContentHash.entity_id("Ada Lovelace", 0), "177f85df57ad121d");
+        assert_eq!(ContentHash.entity_id("ADA LOVELACE", 5), "177f85df57ad121d");
+        assert_ne!(ContentHash.chunk_id(&chunk(Some("docs/b.md"), "Ada Lovelace wrote notes."), 0), "452c505817f3e087");
+    }
+}
@@ -4,8 +4,17 @@ pub mod graph;
 pub mod vector;
 pub mod rag;
 pub mod config;
-#[cfg(feature = "vector-qdrant")]
-pub mod qdrant_integration { pub mod qdrant; }
+pub mod prompts;
+pub mod ids;
+#[cfg(test)]
+mod testutil;
 
 
 pub type Result<T> = anyhow::Result<T>;
+
+/// Stable 64-bit FNV-1a hash, used wherever ids must be reproducible across runs.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for &b in bytes { h ^= b as u64; h = h.wrapping_mul(0x100000001b3); }
+    h
+}
@@ -0,0 +1,187 @@
+use std::{path::PathBuf, time::Duration};
+
+#[cfg(feature = "llm")]
+use super::{CandleBackend, VllmBackend};
+#[cfg(feature = "ollama")]
+use super::OllamaBackend;
+use super::{Backend, ChatTemplate, LLMEngine, ResponseCache, RetryHook, RetryPolicy, DEFAULT_TIMEOUT};
+
+/// Fluent configuration for an `LLMEngine`.
+///
+/// `build` uses the vLLM or Ollama server if one was chosen, otherwise Candle when a model path
+/// is set (with the `llm` feature), otherwise the stub. `max_tokens`, `temperature`, `top_p`,
+/// `top_k` and `seed` apply to every backend; the repeat penalty only to Candle.
+#[derive(Debug, Clone)]
+pub struct LLMEngineBuilder {
+    pub(super) model_path: Option<PathBuf>,
+    pub(super) device: Option<String>,
+    pub(super) tokenizer_path: Option<PathBuf>,
+    pub(super) max_tokens: Option<usize>,
+    pub(super) temperature: Option<f64>,
+    pub(super) top_p: Option<f64>,
+    pub(super) top_k: Option<usize>,
+    repeat_penalty: Option<f32>,
+    repeat_last_n: Option<usize>,
+    seed: Option<u64>,
+    // A server backend chosen with `vllm` or `ollama`; takes precedence over the model path.
+    server: Option<Backend>,
+    cache_dir: Option<PathBuf>,
+    cache_sampled: bool,
+    retry: RetryPolicy,
+    retry_hook: Option<RetryHook>,
+    timeout: Option<Duration>,
+    chat_template: Option<ChatTemplate>,
+}
+
+impl Default for LLMEngineBuilder {
+    fn default() -> Self {
+        Self {
+            model_path: None,
+            device: None,
+            tokenizer_path: None,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            repeat_penalty: None,
+            repeat_last_n: None,
+            seed: None,
+            server: None,
+            cache_dir: None,
+            cache_sampled: false,
+            retry: RetryPolicy::default(),
+            retry_hook: None,
+            timeout: Some(DEFAULT_TIMEOUT),
+            chat_template: None,
+        }
+    }
+}
+
+impl LLMEngineBuilder {
+    /// GGUF weights for the Candle backend.
+    pub fn model_path(mut self, path: impl Into<PathBuf>) -> Self { self.model_path = Some(path.into()); self }
+
+    /// `cpu`, `cuda`, `cuda:N` or `metal` (see `DeviceSpec`); defaults to cpu.
+    pub fn device(mut self, device: impl Into<String>) -> Self { self.device = Some(device.into()); self }
+
+    /// tokenizer.json for the Candle backend; defaults to `models/tokenizer.json`.
+    pub fn tokenizer_path(mut self, path: impl Into<PathBuf>) -> Self { self.tokenizer_path = Some(path.into()); self }
+
+    pub fn max_tokens(mut self, max_tokens: usize) -> Self { self.max_tokens = Some(max_tokens); self }
+
+    /// 0 (the default) samples greedily.
+    pub fn temperature(mut self, temperature: f64) -> Self { self.temperature = Some(temperature); self }
+
+    pub fn top_p(mut self, top_p: f64) -> Self { self.top_p = Some(top_p); self }
+
+    pub fn top_k(mut self, top_k: usize) -> Self { self.top_k = Some(top_k); self }
+
+    /// Values above 1 discourage repeating recent tokens; 1 disables it.
+    pub fn repeat_penalty(mut self, penalty: f32) -> Self { self.repeat_penalty = Some(penalty); self }
+
+    /// Tokens the repeat penalty looks back over (default 64).
+    pub fn repeat_last_n(mut self, n: usize) -> Self { self.repeat_last_n = Some(n); self }
+
+    /// Fixes the sampler seed so sampled output is reproducible (and cacheable).
+    pub fn seed(mut self, seed: u64) -> Self { self.seed = Some(seed); self }
+
+    /// Uses a vLLM server's OpenAI-compatible API; `base_url` defaults to `http://localhost:8000/v1`.
+    #[cfg(feature = "llm")]
+    pub fn vllm(mut self, model: impl Into<String>, base_url: Option<String>) -> Self {
+        self.server = Some(Backend::Vllm(VllmBackend::new(model.into(), base_url)));
+        self
+    }
+
+    /// Uses an Ollama server; `base_url` defaults to `http://localhost:11434`.
+    #[cfg(feature = "ollama")]
+    pub fn ollama(mut self, base_url: Option<String>, model: impl Into<String>) -> Self {
+        self.server = Some(Backend::Ollama(OllamaBackend::new(base_url, model.into())));
+        self
+    }
+
+    /// See `LLMEngine::with_cache`.
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self { self.cache_dir = Some(dir.into()); self }
+
+    /// See `LLMEngine::cache_sampled`.
+    pub fn cache_sampled(mut self, sampled: bool) -> Self { self.cache_sampled = sampled; self }
+
+    /// See `LLMEngine::with_retry`.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self { self.retry = retry; self }
+
+    /// See `LLMEngine::with_retry_hook`.
+    pub fn retry_hook(mut self, hook: RetryHook) -> Self { self.retry_hook = Some(hook); self }
+
+    /// See `LLMEngine::with_timeout`; defaults to `DEFAULT_TIMEOUT`.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self { self.timeout = timeout; self }
+
+    /// See `LLMEngine::with_chat_template`; detected from the model when unset.
+    pub fn chat_template(mut self, template: ChatTemplate) -> Self { self.chat_template = Some(template); self }
+
+    pub fn build(self) -> LLMEngine {
+        let backend = match self.server {
+            #[cfg(feature = "llm")]
+            Some(Backend::Vllm(mut vllm)) => {
+                (vllm.max_tokens, vllm.temperature, vllm.top_p, vllm.top_k, vllm.seed) = (self.max_tokens, self.temperature, self.top_p, self.top_k, self.seed);
+                Backend::Vllm(vllm)
+            }
+            #[cfg(feature = "ollama")]
+            Some(Backend::Ollama(mut ollama)) => {
+                (ollama.max_tokens, ollama.temperature, ollama.top_p, ollama.top_k, ollama.seed) = (self.max_tokens, self.temperature, self.top_p, self.top_k, self.seed);
+                Backend::Ollama(ollama)
+            }
+            Some(server) => server,
+            #[cfg(feature = "llm")]
+            None if self.model_path.is_some() => {
+                let model_path = self.model_path.expect("checked above");
+                let mut candle = CandleBackend::new(model_path, self.device, self.tokenizer_path, self.max_tokens, self.temperature, self.top_p, self.top_k);
+                candle.repeat_penalty = self.repeat_penalty;
+                candle.repeat_last_n = self.repeat_last_n;
+                candle.seed = self.seed;
+                Backend::Candle(candle)
+            }
+            None => Backend::Stub,
+        };
+        let cache = self.cache_dir.map(|dir| ResponseCache { dir, sampled: self.cache_sampled });
+        LLMEngine { backend, cache, retry: self.retry, retry_hook: self.retry_hook, timeout: None, chat_template: self.chat_template }.with_timeout(self.timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "llm")]
+    use crate::llm::cache::SamplingParams;
+
+    #[test]
+    fn builder_settings_reach_the_engine() {
+        let retry = RetryPolicy { max_attempts: 5, ..RetryPolicy::default() };
+        let llm = LLMEngine::builder().retry(retry).timeout(Some(Duration::from_secs(9))).chat_template(ChatTemplate::ChatMl).cache_dir("cache").cache_sampled(true).build();
+        assert_eq!((llm.retry, llm.timeout, llm.chat_template), (retry, Some(Duration::from_secs(9)), Some(ChatTemplate::ChatMl)));
+        let cache = llm.cache.expect("cache configured");
+        assert_eq!((cache.dir, cache.sampled), (PathBuf::from("cache"), true));
+        assert!(LLMEngine::builder().build().sampling_params().is_none());
+    }
+
+    #[cfg(feature = "llm")]
+    #[test]
+    fn sampling_params_are_stored_per_backend() {
+        let knobs = |b: LLMEngineBuilder| b.max_tokens(64).temperature(0.7).top_p(0.9).top_k(40).seed(7);
+        let candle = knobs(LLMEngine::builder().model_path("model.gguf")).repeat_penalty(1.1).build();
+        let expected = SamplingParams {
+            model: "model.gguf".into(),
+            temperature: Some(0.7),
+            top_p: Some(0.9),
+            top_k: Some(40),
+            max_tokens: Some(64),
+            repeat_penalty: Some(1.1),
+            repeat_last_n: Some(64),
+            seed: Some(7),
+        };
+        assert_eq!(candle.sampling_params(), Some(expected.clone()));
+
+        // A server backend wins over the model path and takes the same knobs, but no repeat penalty.
+        let vllm = knobs(LLMEngine::builder().model_path("model.gguf")).vllm("qwen", Some("http://gpu:8000/v1".into())).repeat_penalty(1.1).build();
+        let expected = SamplingParams { model: "qwen@http://gpu:8000/v1".into(), repeat_penalty: None, repeat_last_n: None, ..expected };
+        assert_eq!(vllm.sampling_params(), Some(expected));
+    }
+}
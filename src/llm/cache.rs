@@ -0,0 +1,56 @@
+use std::{fs, path::PathBuf};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Everything besides the prompt that determines a backend's output.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SamplingParams {
+    pub model: String,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub max_tokens: Option<usize>,
+    // Skipped when unset so keys for backends without these knobs stay stable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_last_n: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl SamplingParams {
+    /// Greedy or explicitly seeded decoding repeats itself exactly, so its responses are always safe to reuse.
+    pub fn is_deterministic(&self) -> bool { self.temperature.is_some_and(|t| t <= 0.0) || self.seed.is_some() }
+}
+
+/// On-disk store of responses, one file per hash of prompt and sampling parameters.
+#[derive(Debug, Clone)]
+pub(crate) struct ResponseCache {
+    pub dir: PathBuf,
+    /// Also reuse responses sampled with a nonzero temperature, trading variety for speed.
+    pub sampled: bool,
+}
+
+impl ResponseCache {
+    pub fn key(prompt: &str, params: &SamplingParams) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(serde_json::to_vec(&(prompt, params)).unwrap_or_default());
+        hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        fs::read_to_string(self.dir.join(key)).ok()
+    }
+
+    /// Best effort: a cache that can't be written only costs speed, so errors are ignored.
+    pub fn put(&self, key: &str, response: &str) {
+        if fs::create_dir_all(&self.dir).is_err() { return; }
+        // Write then rename so a concurrent reader never sees a half-written entry.
+        let tmp = self.dir.join(format!("{}.tmp{}", key, std::process::id()));
+        if fs::write(&tmp, response).is_ok() && fs::rename(&tmp, self.dir.join(key)).is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+}
@@ -0,0 +1,395 @@
+use std::{fmt, path::{Path, PathBuf}, sync::{Arc, Mutex, OnceLock}, time::{Duration, Instant}};
+
+use anyhow::Context;
+use candle_core::{quantized::gguf_file, Device, Tensor};
+use candle_transformers::{generation::{LogitsProcessor, Sampling}, models::quantized_llama::ModelWeights, utils::apply_repeat_penalty};
+use tokenizers::Tokenizer;
+
+use super::{cache::SamplingParams, ChatTemplate, DeviceSpec, Generation, TimeoutError};
+use crate::Result;
+
+const DEFAULT_TOKENIZER: &str = "models/tokenizer.json";
+const DEFAULT_MAX_TOKENS: usize = 256;
+const DEFAULT_SEED: u64 = 299792458;
+const DEFAULT_REPEAT_LAST_N: usize = 64;
+const EOS_CANDIDATES: &[&str] = &["<|im_end|>", "<|endoftext|>", "<|eot_id|>", "</s>"];
+/// `general.architecture` values the quantized llama loader can run.
+const ARCHITECTURES: &[&str] = &["llama", "mistral"];
+
+#[derive(Clone)]
+pub(crate) struct CandleBackend {
+    model_path: PathBuf,
+    device: Option<String>,
+    tokenizer_path: Option<PathBuf>,
+    max_tokens: Option<usize>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    top_k: Option<usize>,
+    /// Divides the logits of recently seen tokens by this (> 1 discourages loops); unset or 1 disables it.
+    pub repeat_penalty: Option<f32>,
+    /// How many of the latest tokens the repeat penalty looks at (default 64).
+    pub repeat_last_n: Option<usize>,
+    /// Sampler seed; a fixed one makes sampled output reproducible across runs.
+    pub seed: Option<u64>,
+    /// Sampling stops with a `TimeoutError` once this much time has passed.
+    pub timeout: Option<Duration>,
+    // Loaded lazily on first use and shared between clones of the engine.
+    loaded: Arc<Mutex<Option<Loaded>>>,
+    // Tokenizer alone, so counting tokens doesn't load the model weights.
+    counter: Arc<Mutex<Option<Tokenizer>>>,
+    chat_template: Arc<OnceLock<Option<ChatTemplate>>>,
+}
+
+struct Loaded {
+    model: ModelWeights,
+    tokenizer: Tokenizer,
+    device: Device,
+    eos: Vec<u32>,
+}
+
+impl fmt::Debug for CandleBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CandleBackend")
+            .field("model_path", &self.model_path)
+            .field("device", &self.device)
+            .field("tokenizer_path", &self.tokenizer_path)
+            .field("max_tokens", &self.max_tokens)
+            .field("temperature", &self.temperature)
+            .field("top_p", &self.top_p)
+            .field("top_k", &self.top_k)
+            .field("repeat_penalty", &self.repeat_penalty)
+            .field("repeat_last_n", &self.repeat_last_n)
+            .field("seed", &self.seed)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl CandleBackend {
+    pub fn new(model_path: PathBuf, device: Option<String>, tokenizer_path: Option<PathBuf>, max_tokens: Option<usize>, temperature: Option<f64>, top_p: Option<f64>, top_k: Option<usize>) -> Self {
+        Self { model_path, device, tokenizer_path, max_tokens, temperature, top_p, top_k, repeat_penalty: None, repeat_last_n: None, seed: None, timeout: None, loaded: Arc::new(Mutex::new(None)), counter: Arc::new(Mutex::new(None)), chat_template: Arc::new(OnceLock::new()) }
+    }
+
+    pub fn sampling_params(&self) -> SamplingParams {
+        SamplingParams {
+            model: self.model_path.display().to_string(),
+            temperature: Some(self.temperature.unwrap_or(0.0)),
+            top_p: self.top_p,
+            top_k: self.top_k,
+            max_tokens: Some(self.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS)),
+            repeat_penalty: self.repeat_penalty,
+            repeat_last_n: self.repeat_penalty.map(|_| self.repeat_last_n.unwrap_or(DEFAULT_REPEAT_LAST_N)),
+            seed: self.seed,
+        }
+    }
+
+    pub fn count_tokens(&self, text: &str) -> Result<usize> {
+        let mut guard = self.counter.lock().map_err(|_| anyhow::anyhow!("tokenizer lock poisoned"))?;
+        if guard.is_none() {
+            *guard = Some(load_tokenizer(&self.tokenizer_path.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_TOKENIZER)))?);
+        }
+        let tokenizer = guard.as_ref().expect("tokenizer loaded above");
+        Ok(tokenizer.encode(text, false).map_err(anyhow::Error::msg)?.len())
+    }
+
+    /// The chat format named by the `tokenizer_config.json` next to the tokenizer, if any.
+    pub fn detected_chat_template(&self) -> Option<ChatTemplate> {
+        *self.chat_template.get_or_init(|| {
+            let tokenizer_path = self.tokenizer_path.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_TOKENIZER));
+            ChatTemplate::from_tokenizer_config(&tokenizer_path.with_file_name("tokenizer_config.json")).ok().flatten()
+        })
+    }
+
+    pub async fn generate(&self, prompt: &str) -> Result<String> {
+        // Sampling is CPU/GPU bound; keep it off the async executor threads.
+        let this = self.clone();
+        let prompt = prompt.to_string();
+        tokio::task::spawn_blocking(move || this.generate_blocking(&prompt, &mut |_| {})).await?
+    }
+
+    /// `generate` with the prompt and completion token counts and the sampling time.
+    pub async fn generate_with_meta(&self, prompt: &str) -> Result<Generation> {
+        let this = self.clone();
+        let prompt = prompt.to_string();
+        tokio::task::spawn_blocking(move || this.sample(&prompt, &mut |_| {})).await?
+    }
+
+    pub fn generate_stream(&self, prompt: &str) -> impl futures::Stream<Item = Result<String>> {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let this = self.clone();
+        let prompt = prompt.to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut send = |fragment: &str| { let _ = tx.unbounded_send(Ok(fragment.to_string())); };
+            if let Err(e) = this.generate_blocking(&prompt, &mut send) { let _ = tx.unbounded_send(Err(e)); }
+        });
+        rx
+    }
+
+    /// Runs the full sampling loop, invoking `on_token` with each newly decoded text fragment.
+    pub(crate) fn generate_blocking(&self, prompt: &str, on_token: &mut dyn FnMut(&str)) -> Result<String> {
+        Ok(self.sample(prompt, on_token)?.text)
+    }
+
+    fn sample(&self, prompt: &str, on_token: &mut dyn FnMut(&str)) -> Result<Generation> {
+        let mut guard = self.loaded.lock().map_err(|_| anyhow::anyhow!("candle model lock poisoned"))?;
+        if guard.is_none() { *guard = Some(self.load()?); }
+        let Loaded { model, tokenizer, device, eos } = guard.as_mut().expect("model loaded above");
+        let mut forward = |input: &[u32], pos: usize| -> Result<Tensor> { Ok(model.forward(&Tensor::new(input, device)?.unsqueeze(0)?, pos)?.squeeze(0)?) };
+        self.sample_with(tokenizer, eos, &mut forward, prompt, on_token)
+    }
+
+    // The sampling loop, with the model reduced to `forward(new tokens, position) -> logits`.
+    fn sample_with(&self, tokenizer: &Tokenizer, eos: &[u32], forward: &mut dyn FnMut(&[u32], usize) -> Result<Tensor>, prompt: &str, on_token: &mut dyn FnMut(&str)) -> Result<Generation> {
+        let prompt_ids = tokenizer.encode(prompt, true).map_err(anyhow::Error::msg)?.get_ids().to_vec();
+        if prompt_ids.is_empty() { anyhow::bail!("prompt produced no tokens"); }
+        let mut sampler = self.sampler();
+        let penalty = self.repeat_penalty.filter(|p| *p != 1.0);
+        let last_n = self.repeat_last_n.unwrap_or(DEFAULT_REPEAT_LAST_N);
+        let mut context = prompt_ids.clone();
+        let max_tokens = self.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+
+        let mut stream = TokenStream::new(tokenizer);
+        let mut input = prompt_ids.clone();
+        let mut pos = 0usize;
+        // Timed from here so a first call's model load doesn't count against it.
+        let started = Instant::now();
+        for _ in 0..max_tokens {
+            if let Some(after) = self.timeout.filter(|limit| started.elapsed() > *limit) {
+                return Err(TimeoutError { after }.into());
+            }
+            let mut logits = forward(&input, pos)?.to_dtype(candle_core::DType::F32)?;
+            pos += input.len();
+            if let Some(penalty) = penalty {
+                logits = apply_repeat_penalty(&logits, penalty, &context[context.len().saturating_sub(last_n)..])?;
+            }
+            let next = sampler.sample(&logits)?;
+            if eos.contains(&next) { break; }
+            context.push(next);
+            input = vec![next];
+            if let Some(fragment) = stream.push(next)? { on_token(&fragment); }
+        }
+        if let Some(fragment) = stream.rest()? { on_token(&fragment); }
+        let text = decode(tokenizer, &stream.tokens)?;
+        Ok(Generation { text, prompt_tokens: prompt_ids.len(), completion_tokens: stream.tokens.len(), elapsed: started.elapsed() })
+    }
+
+    fn sampler(&self) -> LogitsProcessor { LogitsProcessor::from_sampling(self.seed.unwrap_or(DEFAULT_SEED), self.sampling()) }
+
+    fn sampling(&self) -> Sampling {
+        let temperature = self.temperature.unwrap_or(0.0);
+        if temperature <= 0.0 { return Sampling::ArgMax; }
+        match (self.top_k, self.top_p) {
+            (Some(k), Some(p)) => Sampling::TopKThenTopP { k, p, temperature },
+            (Some(k), None) => Sampling::TopK { k, temperature },
+            (None, Some(p)) => Sampling::TopP { p, temperature },
+            (None, None) => Sampling::All { temperature },
+        }
+    }
+
+    fn load(&self) -> Result<Loaded> {
+        let device = self.device.as_deref().map(str::parse::<DeviceSpec>).transpose()?.unwrap_or_default().to_candle()?;
+        let tokenizer_path = self.tokenizer_path.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_TOKENIZER));
+        let tokenizer = load_tokenizer(&tokenizer_path)?;
+        if !self.model_path.exists() { anyhow::bail!("model file not found: {}", self.model_path.display()); }
+        let mut file = std::fs::File::open(&self.model_path).with_context(|| format!("opening {}", self.model_path.display()))?;
+        let content = gguf_file::Content::read(&mut file).map_err(|e| e.with_path(&self.model_path))?;
+        check_architecture(&content.metadata).with_context(|| format!("loading {}", self.model_path.display()))?;
+        let model = ModelWeights::from_gguf(content, &mut file, &device)?;
+        let eos = EOS_CANDIDATES.iter().filter_map(|t| tokenizer.token_to_id(t)).collect();
+        Ok(Loaded { model, tokenizer, device, eos })
+    }
+}
+
+/// Turns sampled tokens into text fragments as they arrive. Each step decodes only the tokens
+/// since the last fragment together with the ones that fragment came from, so spacing between
+/// them comes out right, and emits what they add once it isn't a partial character. Fragments
+/// can't be taken back, so a decoder that rewrites earlier text (e.g. one that merges
+/// characters across tokens) streams differently from the completion decoded whole.
+struct TokenStream<'a> {
+    tokenizer: &'a Tokenizer,
+    tokens: Vec<u32>,
+    // Start of the tokens behind the last fragment, and of those not yet emitted.
+    prev_index: usize,
+    current_index: usize,
+}
+
+impl<'a> TokenStream<'a> {
+    fn new(tokenizer: &'a Tokenizer) -> Self { Self { tokenizer, tokens: Vec::new(), prev_index: 0, current_index: 0 } }
+
+    fn push(&mut self, token: u32) -> Result<Option<String>> {
+        let prev_text = decode(self.tokenizer, &self.tokens[self.prev_index..self.current_index])?;
+        self.tokens.push(token);
+        let text = decode(self.tokenizer, &self.tokens[self.prev_index..])?;
+        if text.ends_with('\u{FFFD}') { return Ok(None); }
+        let fresh = tail(&text, prev_text.len()).to_string();
+        if fresh.is_empty() { return Ok(None); }
+        self.prev_index = self.current_index;
+        self.current_index = self.tokens.len();
+        Ok(Some(fresh))
+    }
+
+    // Whatever is still held back, such as a trailing partial character.
+    fn rest(&self) -> Result<Option<String>> {
+        let prev_text = decode(self.tokenizer, &self.tokens[self.prev_index..self.current_index])?;
+        let text = decode(self.tokenizer, &self.tokens[self.prev_index..])?;
+        Ok(Some(tail(&text, prev_text.len()).to_string()).filter(|fresh| !fresh.is_empty()))
+    }
+}
+
+// `text` from byte `at` on, or from the next character when a rewrite left `at` inside one.
+fn tail(text: &str, at: usize) -> &str {
+    let start = (at.min(text.len())..=text.len()).find(|&i| text.is_char_boundary(i)).unwrap_or(text.len());
+    &text[start..]
+}
+
+fn decode(tokenizer: &Tokenizer, tokens: &[u32]) -> Result<String> { tokenizer.decode(tokens, true).map_err(anyhow::Error::msg) }
+
+// Other architectures would fail deep in the loader on a missing tensor, or load and emit garbage.
+fn check_architecture(metadata: &std::collections::HashMap<String, gguf_file::Value>) -> Result<()> {
+    let arch = metadata.get("general.architecture").map(|v| v.to_string()).transpose()?.map(String::as_str);
+    match arch {
+        Some(arch) if ARCHITECTURES.contains(&arch) => Ok(()),
+        Some(arch) => anyhow::bail!("unsupported architecture {} (the Candle backend runs {})", arch, ARCHITECTURES.join(" and ")),
+        None => anyhow::bail!("the model declares no general.architecture (the Candle backend runs {})", ARCHITECTURES.join(" and ")),
+    }
+}
+
+pub(crate) fn load_tokenizer(path: &Path) -> Result<Tokenizer> {
+    if !path.exists() { anyhow::bail!("tokenizer file not found: {}", path.display()); }
+    Tokenizer::from_file(path).map_err(|e| anyhow::anyhow!("loading tokenizer {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{estimate_tokens, ChatMessage, LLMEngine};
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs a GGUF model: set FORGE_TEST_MODEL and FORGE_TEST_TOKENIZER"]
+    async fn generates_with_a_tiny_model() {
+        let model = std::env::var("FORGE_TEST_MODEL").expect("FORGE_TEST_MODEL");
+        let tokenizer = std::env::var("FORGE_TEST_TOKENIZER").expect("FORGE_TEST_TOKENIZER");
+        let llm = LLMEngine::builder().model_path(&model).tokenizer_path(&tokenizer).max_tokens(8).build();
+        let first = llm.generate("The capital of France is").await.unwrap();
+        assert!(!first.trim().is_empty());
+        // Greedy decoding by default, so a second run repeats the first.
+        assert_eq!(llm.generate("The capital of France is").await.unwrap(), first);
+
+        let sampled = || LLMEngine::builder().model_path(&model).tokenizer_path(&tokenizer).max_tokens(16).temperature(0.9).top_p(0.95).seed(7).build();
+        assert_eq!(sampled().generate("Once upon a time").await.unwrap(), sampled().generate("Once upon a time").await.unwrap());
+    }
+
+    #[test]
+    fn same_seed_samples_the_same_tokens() {
+        let logits = Tensor::new((0..100).map(|i| (i % 7) as f32 * 0.3).collect::<Vec<f32>>().as_slice(), &candle_core::Device::Cpu).unwrap();
+        let backend = |seed| CandleBackend { seed: Some(seed), ..CandleBackend::new("model.gguf".into(), None, None, None, Some(1.0), Some(0.95), Some(50)) };
+        let draw = |backend: &CandleBackend| {
+            let mut sampler = backend.sampler();
+            (0..32).map(|_| sampler.sample(&logits).unwrap()).collect::<Vec<u32>>()
+        };
+        assert_eq!(draw(&backend(42)), draw(&backend(42)));
+        assert_ne!(draw(&backend(42)), draw(&backend(43)));
+    }
+
+    #[test]
+    fn only_llama_architectures_load() {
+        let metadata = |arch: Option<&str>| arch.map(|a| ("general.architecture".to_string(), gguf_file::Value::String(a.into()))).into_iter().collect();
+        assert!(check_architecture(&metadata(Some("llama"))).is_ok());
+        assert!(check_architecture(&metadata(Some("mistral"))).is_ok());
+        assert_eq!(check_architecture(&metadata(Some("qwen3"))).unwrap_err().to_string(), "unsupported architecture qwen3 (the Candle backend runs llama and mistral)");
+        assert!(check_architecture(&metadata(None)).unwrap_err().to_string().starts_with("the model declares no general.architecture"));
+    }
+
+    /// A word-level tokenizer.json: one token per word or punctuation run, whatever its length.
+    fn word_level(vocab: &str) -> String {
+        format!(
+            r#"{{"version": "1.0", "truncation": null, "padding": null, "added_tokens": [], "normalizer": null,
+            "pre_tokenizer": {{"type": "Whitespace"}}, "post_processor": null, "decoder": null,
+            "model": {{"type": "WordLevel", "vocab": {}, "unk_token": "[UNK]"}}}}"#,
+            vocab
+        )
+    }
+
+    #[test]
+    fn counts_with_the_tokenizer_rather_than_the_estimate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tokenizer.json");
+        std::fs::write(&path, word_level(r#"{"[UNK]": 0, "is": 1, "hard": 2}"#)).unwrap();
+        // Only the tokenizer is loaded to count, so the model path needn't exist.
+        let llm = LLMEngine::builder().model_path(dir.path().join("model.gguf")).tokenizer_path(&path).build();
+        let text = "internationalization is hard!";
+        assert_eq!(llm.count_tokens(text).unwrap(), 4);
+        assert_eq!(estimate_tokens(text), 8);
+        assert_eq!(LLMEngine::new().count_tokens(text).unwrap(), 8);
+    }
+
+    #[test]
+    fn completion_tokens_count_what_was_sampled() {
+        let tokenizer: Tokenizer = word_level(r#"{"[UNK]": 0, "hello": 1, "big": 2, "world": 3, "</s>": 4}"#).parse().unwrap();
+        // A stub model whose logits put each scripted token on top in turn, repeating the last.
+        let run = |script: &[u32], max_tokens| {
+            let backend = CandleBackend::new("model.gguf".into(), None, None, Some(max_tokens), None, None, None);
+            let mut calls = 0;
+            let mut forward = |_: &[u32], _: usize| -> Result<Tensor> {
+                let next = script[calls.min(script.len() - 1)];
+                calls += 1;
+                Ok(Tensor::new((0..5).map(|i| if i == next { 1.0f32 } else { 0.0 }).collect::<Vec<_>>().as_slice(), &Device::Cpu)?)
+            };
+            let mut streamed = String::new();
+            let generation = backend.sample_with(&tokenizer, &[4], &mut forward, "hello world", &mut |f| streamed.push_str(f)).unwrap();
+            assert_eq!(streamed, generation.text);
+            (generation, calls)
+        };
+        // The fourth call samples the end-of-sequence token, which isn't part of the completion.
+        let (generation, calls) = run(&[2, 3, 2, 4], 10);
+        assert_eq!((generation.text.as_str(), generation.prompt_tokens, generation.completion_tokens, calls), ("big world big", 2, 3, 4));
+        let (generation, calls) = run(&[3], 5);
+        assert_eq!((generation.completion_tokens, calls), (5, 5));
+    }
+
+    #[test]
+    fn templated_prompts_start_with_one_bos() {
+        // A tokenizer that, like Llama 3's, prepends <|begin_of_text|> to whatever it encodes.
+        let bos = r#"{"SpecialToken": {"id": "<|begin_of_text|>", "type_id": 0}}"#;
+        let post_processor = format!(
+            r#""post_processor": {{"type": "TemplateProcessing", "single": [{bos}, {{"Sequence": {{"id": "A", "type_id": 0}}}}],
+            "pair": [{bos}, {{"Sequence": {{"id": "A", "type_id": 0}}}}, {{"Sequence": {{"id": "B", "type_id": 1}}}}],
+            "special_tokens": {{"<|begin_of_text|>": {{"id": "<|begin_of_text|>", "ids": [2], "tokens": ["<|begin_of_text|>"]}}}}}}"#
+        );
+        let added = r#""added_tokens": [{"id": 2, "content": "<|begin_of_text|>", "single_word": false, "lstrip": false, "rstrip": false, "normalized": false, "special": true}]"#;
+        let json = word_level(r#"{"[UNK]": 0, "hello": 1, "<|begin_of_text|>": 2}"#).replace(r#""post_processor": null"#, &post_processor).replace(r#""added_tokens": []"#, added);
+        let tokenizer: Tokenizer = json.parse().unwrap();
+        let backend = CandleBackend::new("model.gguf".into(), None, None, Some(1), None, None, None);
+        for template in [ChatTemplate::Llama3, ChatTemplate::Mistral, ChatTemplate::ChatMl] {
+            let mut prompt_ids = Vec::new();
+            let mut forward = |input: &[u32], _: usize| -> Result<Tensor> {
+                prompt_ids = input.to_vec();
+                Ok(Tensor::new(&[0.0f32, 1.0, 0.0], &Device::Cpu)?)
+            };
+            backend.sample_with(&tokenizer, &[], &mut forward, &template.render(&[ChatMessage::user("hello")]), &mut |_| {}).unwrap();
+            assert_eq!(prompt_ids.iter().filter(|&&id| id == 2).count(), 1, "{:?}: {:?}", template, prompt_ids);
+            assert_eq!(prompt_ids[0], 2);
+        }
+    }
+
+    #[test]
+    fn streams_when_decoding_rewrites_earlier_text() {
+        // The decoder turns "a" followed by "b" into "é", so the second token rewrites the first,
+        // and into more bytes than "a" had.
+        let decoder = r#""decoder": {"type": "Sequence", "decoders": [{"type": "Fuse"}, {"type": "Replace", "pattern": {"String": "ab"}, "content": "é"}]}"#;
+        let tokenizer: Tokenizer = word_level(r#"{"[UNK]": 0, "a": 1, "b": 2, "c": 3, "</s>": 4}"#).replace(r#""decoder": null"#, decoder).parse().unwrap();
+        let script = [1u32, 2, 3, 4];
+        let mut calls = 0;
+        let mut forward = |_: &[u32], _: usize| -> Result<Tensor> {
+            let next = script[calls];
+            calls += 1;
+            Ok(Tensor::new((0..5).map(|i| if i == next { 1.0f32 } else { 0.0 }).collect::<Vec<_>>().as_slice(), &Device::Cpu)?)
+        };
+        let backend = CandleBackend::new("model.gguf".into(), None, None, Some(10), None, None, None);
+        let mut fragments = Vec::new();
+        let generation = backend.sample_with(&tokenizer, &[4], &mut forward, "a", &mut |f| fragments.push(f.to_string())).unwrap();
+        assert_eq!(generation.text, "éc");
+        // The streamed "a" can't be taken back, but what follows the rewrite still comes through.
+        assert_eq!(fragments, ["a", "c"]);
+    }
+}
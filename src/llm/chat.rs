@@ -0,0 +1,160 @@
+use std::{fmt, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self { Self { role: Role::System, content: content.into() } }
+
+    pub fn user(content: impl Into<String>) -> Self { Self { role: Role::User, content: content.into() } }
+
+    pub fn assistant(content: impl Into<String>) -> Self { Self { role: Role::Assistant, content: content.into() } }
+}
+
+/// How chat messages are laid out as a single prompt for an instruction-tuned model.
+///
+/// Models ship their format as a Jinja `chat_template` in `tokenizer_config.json`; rather than
+/// evaluate Jinja, `detect` recognizes which of the common formats below it produces.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChatTemplate {
+    /// Contents separated by blank lines, for base models and servers that template themselves.
+    #[default]
+    Plain,
+    /// `<|im_start|>role ... <|im_end|>`, used by Qwen and many fine-tunes.
+    ChatMl,
+    /// `<|start_header_id|>role<|end_header_id|> ... <|eot_id|>`.
+    Llama3,
+    /// `[INST] ... [/INST]`, used by Mistral and Llama 2; the system prompt joins the first user turn.
+    Mistral,
+}
+
+impl FromStr for ChatTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "plain" | "none" => Ok(ChatTemplate::Plain),
+            "chatml" | "qwen" => Ok(ChatTemplate::ChatMl),
+            "llama3" => Ok(ChatTemplate::Llama3),
+            "mistral" | "llama2" => Ok(ChatTemplate::Mistral),
+            _ => anyhow::bail!("unknown chat template '{}' (expected plain, chatml, llama3 or mistral)", s),
+        }
+    }
+}
+
+impl ChatTemplate {
+    /// The preset a Jinja `chat_template` corresponds to, judged by its special tokens.
+    pub fn detect(jinja: &str) -> Option<Self> {
+        if jinja.contains("<|im_start|>") { return Some(ChatTemplate::ChatMl); }
+        if jinja.contains("<|start_header_id|>") { return Some(ChatTemplate::Llama3); }
+        if jinja.contains("[INST]") { return Some(ChatTemplate::Mistral); }
+        None
+    }
+
+    /// Detects the template from a `tokenizer_config.json`; `None` if it has no recognizable one.
+    pub fn from_tokenizer_config(path: &Path) -> Result<Option<Self>> {
+        let config: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        // Either a single template or a list of named ones, of which "default" is used for chat.
+        let jinja = match &config["chat_template"] {
+            serde_json::Value::String(s) => Some(s.as_str()),
+            serde_json::Value::Array(named) => named
+                .iter()
+                .find(|t| t["name"] == "default")
+                .or_else(|| named.first())
+                .and_then(|t| t["template"].as_str()),
+            _ => None,
+        };
+        Ok(jinja.and_then(Self::detect))
+    }
+
+    /// Renders `messages` and opens an assistant turn for the model to complete. The
+    /// beginning-of-sequence token (`<|begin_of_text|>`, `<s>`) is left out: tokenizers add it
+    /// when encoding, and a second one degrades the output.
+    pub fn render(&self, messages: &[ChatMessage]) -> String {
+        let mut out = String::new();
+        match self {
+            ChatTemplate::Plain => {
+                let contents: Vec<&str> = messages.iter().map(|m| m.content.as_str()).collect();
+                out.push_str(&contents.join("\n\n"));
+            }
+            ChatTemplate::ChatMl => {
+                for m in messages { out.push_str(&format!("<|im_start|>{}\n{}<|im_end|>\n", m.role, m.content)); }
+                out.push_str("<|im_start|>assistant\n");
+            }
+            ChatTemplate::Llama3 => {
+                for m in messages { out.push_str(&format!("<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>", m.role, m.content)); }
+                out.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+            }
+            ChatTemplate::Mistral => {
+                let mut system: Option<&str> = None;
+                for m in messages {
+                    match m.role {
+                        Role::System => system = Some(&m.content),
+                        Role::User => match system.take() {
+                            Some(sys) => out.push_str(&format!("[INST] {}\n\n{} [/INST]", sys, m.content)),
+                            None => out.push_str(&format!("[INST] {} [/INST]", m.content)),
+                        },
+                        Role::Assistant => out.push_str(&format!(" {}</s>", m.content)),
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> [ChatMessage; 2] { [ChatMessage::system("Be brief."), ChatMessage::user("Hi?")] }
+
+    #[test]
+    fn known_templates_render_exactly() {
+        assert_eq!(ChatTemplate::Plain.render(&conversation()), "Be brief.\n\nHi?");
+        assert_eq!(
+            ChatTemplate::ChatMl.render(&conversation()),
+            "<|im_start|>system\nBe brief.<|im_end|>\n<|im_start|>user\nHi?<|im_end|>\n<|im_start|>assistant\n"
+        );
+        assert_eq!(
+            ChatTemplate::Llama3.render(&conversation()),
+            "<|start_header_id|>system<|end_header_id|>\n\nBe brief.<|eot_id|>\
+             <|start_header_id|>user<|end_header_id|>\n\nHi?<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n"
+        );
+        assert_eq!(ChatTemplate::Mistral.render(&conversation()), "[INST] Be brief.\n\nHi? [/INST]");
+    }
+
+    #[test]
+    fn tokenizer_config_picks_the_default_named_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tokenizer_config.json");
+        std::fs::write(&path, r#"{"chat_template": [{"name": "tool_use", "template": "[INST]"}, {"name": "default", "template": "{{ '<|im_start|>' }}"}]}"#).unwrap();
+        assert_eq!(ChatTemplate::from_tokenizer_config(&path).unwrap(), Some(ChatTemplate::ChatMl));
+        assert_eq!("Llama-3".parse::<ChatTemplate>().unwrap(), ChatTemplate::Llama3);
+    }
+}
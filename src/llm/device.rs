@@ -0,0 +1,80 @@
+use std::{fmt, str::FromStr};
+
+use crate::Result;
+
+/// Where the Candle backend runs: `cpu`, `cuda` or `cuda:N` for the Nth GPU, or `metal`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeviceSpec {
+    #[default]
+    Cpu,
+    Cuda(usize),
+    Metal,
+}
+
+impl FromStr for DeviceSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let spec = s.trim().to_ascii_lowercase();
+        let (name, ordinal) = match spec.split_once(':') {
+            Some((name, n)) => (name, Some(n)),
+            None => (spec.as_str(), None),
+        };
+        match (name, ordinal) {
+            ("cpu", None) => Ok(DeviceSpec::Cpu),
+            ("metal", None) => Ok(DeviceSpec::Metal),
+            ("cuda", None) => Ok(DeviceSpec::Cuda(0)),
+            ("cuda", Some(n)) => match n.parse() {
+                Ok(n) => Ok(DeviceSpec::Cuda(n)),
+                Err(_) => anyhow::bail!("invalid GPU index in device '{}' (expected e.g. cuda:1)", s),
+            },
+            _ => anyhow::bail!("unknown device '{}' (expected cpu, cuda, cuda:N or metal)", s),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSpec::Cpu => f.write_str("cpu"),
+            DeviceSpec::Cuda(n) => write!(f, "cuda:{}", n),
+            DeviceSpec::Metal => f.write_str("metal"),
+        }
+    }
+}
+
+#[cfg(any(feature = "llm", feature = "embeddings"))]
+impl DeviceSpec {
+    /// Opens the device; fails if Candle was built without CUDA or Metal support or the GPU is missing.
+    pub fn to_candle(self) -> Result<candle_core::Device> {
+        Ok(match self {
+            DeviceSpec::Cpu => candle_core::Device::Cpu,
+            DeviceSpec::Cuda(n) => candle_core::Device::new_cuda(n)?,
+            DeviceSpec::Metal => candle_core::Device::new_metal(0)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_strings_parse_and_print_back() {
+        for (input, spec) in [("cpu", DeviceSpec::Cpu), (" CPU ", DeviceSpec::Cpu), ("cuda", DeviceSpec::Cuda(0)), ("cuda:1", DeviceSpec::Cuda(1)), ("Metal", DeviceSpec::Metal)] {
+            assert_eq!(input.parse::<DeviceSpec>().unwrap(), spec, "{:?}", input);
+            assert_eq!(spec.to_string().parse::<DeviceSpec>().unwrap(), spec);
+        }
+        assert_eq!(DeviceSpec::Cuda(0).to_string(), "cuda:0");
+        assert_eq!("cuda:x".parse::<DeviceSpec>().unwrap_err().to_string(), "invalid GPU index in device 'cuda:x' (expected e.g. cuda:1)");
+        for bad in ["gpu", "cpu:0", "metal:1", "cuda:-1", ""] {
+            assert!(bad.parse::<DeviceSpec>().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[cfg(any(feature = "llm", feature = "embeddings"))]
+    #[test]
+    fn cpu_always_opens() {
+        assert!(DeviceSpec::Cpu.to_candle().unwrap().is_cpu());
+    }
+}
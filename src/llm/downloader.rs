@@ -1,14 +1,187 @@
 use anyhow::Context;
-use std::{fs, path::{Path, PathBuf}};
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::{header, StatusCode};
+use sha2::{Digest, Sha256};
+use std::{fs, io::{Read, Write}, path::{Path, PathBuf}};
 
-pub async fn ensure_model(path: &Path, url: &str) -> anyhow::Result<PathBuf> {
+use super::{Retry, RetryPolicy};
+
+/// Downloads `url` to `path` unless it already exists, streaming into `<path>.part` and resuming
+/// from a previous partial download when the server honours `Range` requests.
+///
+/// With `expected_sha256`, a download whose SHA-256 differs is deleted and reported as an error.
+/// `token` (or else the `HF_TOKEN` environment variable) is sent as a Bearer token for gated models.
+/// Transient failures are retried per `retry`, each attempt resuming from what was already saved;
+/// `on_retry` hears about each one.
+pub async fn ensure_model(path: &Path, url: &str, expected_sha256: Option<&str>, token: Option<&str>, retry: &RetryPolicy, on_retry: impl FnMut(&Retry<'_>)) -> anyhow::Result<PathBuf> {
     if path.exists() {
         return Ok(path.to_path_buf());
     }
     if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
-    // Async download using reqwest.
-    let resp = reqwest::get(url).await.with_context(|| format!("GET {}", url))?;
-    let bytes = resp.bytes().await.with_context(|| "reading response bytes")?;
-    fs::write(path, &bytes).with_context(|| format!("writing {}", path.display()))?;
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+
+    // reqwest follows redirects (HF serves files from a CDN) and drops the token on cross-host hops.
+    let client = reqwest::Client::new();
+    let token = token.map(str::to_string).or_else(|| std::env::var("HF_TOKEN").ok()).filter(|t| !t.is_empty());
+    retry.run_reporting(|| download(&client, url, token.as_deref(), &part), on_retry).await?;
+    if let Some(expected) = expected_sha256 {
+        let actual = sha256_file(&part)?;
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            fs::remove_file(&part)?;
+            anyhow::bail!("checksum mismatch for {}: expected sha256 {}, got {}", url, expected.trim(), actual);
+        }
+    }
+    fs::rename(&part, path).with_context(|| format!("moving {} to {}", part.display(), path.display()))?;
     Ok(path.to_path_buf())
 }
+
+// One attempt at fetching `url` into `part`, appending to it when the server honours `Range`.
+async fn download(client: &reqwest::Client, url: &str, token: Option<&str>, part: &Path) -> anyhow::Result<()> {
+    let get = || {
+        let request = client.get(url);
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    };
+    let mut offset = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+    let mut request = get();
+    if offset > 0 { request = request.header(header::RANGE, format!("bytes={}-", offset)); }
+    let mut resp = request.send().await.with_context(|| format!("GET {}", url))?;
+    if resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial file doesn't match what the server has; start over.
+        offset = 0;
+        resp = get().send().await.with_context(|| format!("GET {}", url))?;
+    }
+    match resp.status() {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => anyhow::bail!(
+            "GET {} was refused with HTTP {}; the model may be gated. Set HF_TOKEN or pass --hf-token{}",
+            url, resp.status().as_u16(), if token.is_some() { " (the given token was rejected)" } else { "" }
+        ),
+        StatusCode::NOT_FOUND => anyhow::bail!("GET {}: not found (HTTP 404); check the model URL", url),
+        _ => {}
+    }
+    let mut resp = resp.error_for_status().with_context(|| format!("GET {}", url))?;
+    // A plain 200 means the server ignored the range, so the partial file is discarded.
+    let resuming = offset > 0 && resp.status() == StatusCode::PARTIAL_CONTENT;
+    if !resuming { offset = 0; }
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resuming)
+        .truncate(!resuming)
+        .open(part)
+        .with_context(|| format!("opening {}", part.display()))?;
+    let pb = match resp.content_length() {
+        Some(len) => ProgressBar::new(offset + len),
+        None => ProgressBar::new_spinner(),
+    };
+    pb.set_style(ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")?);
+    pb.set_position(offset);
+
+    while let Some(chunk) = resp.chunk().await.with_context(|| "reading response body")? {
+        file.write_all(&chunk).with_context(|| format!("writing {}", part.display()))?;
+        pb.inc(chunk.len() as u64);
+    }
+    file.flush()?;
+    pb.finish();
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let mut file = fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 { break; }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{MockServer, Response};
+
+    const PAYLOAD_LEN: usize = 1000;
+
+    fn payload() -> Vec<u8> { (0..PAYLOAD_LEN).map(|i| (i % 251) as u8).collect() }
+
+    /// Serves `payload()`, honouring `Range: bytes=N-` when `ranges` is set.
+    fn server(ranges: bool) -> MockServer {
+        MockServer::start(move |req| {
+            let body = payload();
+            let from = req.header("range").filter(|_| ranges).and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse::<usize>().ok());
+            match from {
+                Some(from) => Response {
+                    status: 206,
+                    headers: vec![("Content-Range".into(), format!("bytes {}-{}/{}", from, PAYLOAD_LEN - 1, PAYLOAD_LEN))],
+                    body: body[from..].to_vec(),
+                },
+                None => Response { status: 200, headers: Vec::new(), body },
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn resumes_from_the_partial_file() {
+        for ranges in [true, false] {
+            let server = server(ranges);
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("model.gguf");
+            // A previous attempt saved the first 400 bytes.
+            fs::write(dir.path().join("model.gguf.part"), &payload()[..400]).unwrap();
+            let url = format!("{}/model.gguf", server.url);
+            ensure_model(&path, &url, None, None, &RetryPolicy::default(), |_| {}).await.unwrap();
+
+            assert_eq!(fs::read(&path).unwrap(), payload());
+            assert!(!dir.path().join("model.gguf.part").exists());
+            let requests = server.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].header("range"), Some("bytes=400-"));
+        }
+    }
+
+    #[tokio::test]
+    async fn checksum_mismatch_removes_the_download() {
+        let server = server(true);
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("{}/model.gguf", server.url);
+        let digest: String = Sha256::digest(payload()).iter().map(|b| format!("{:02x}", b)).collect();
+
+        let good = dir.path().join("good.gguf");
+        ensure_model(&good, &url, Some(&digest.to_uppercase()), None, &RetryPolicy::default(), |_| {}).await.unwrap();
+        assert_eq!(fs::read(&good).unwrap(), payload());
+
+        let bad = dir.path().join("bad.gguf");
+        let err = ensure_model(&bad, &url, Some(&"0".repeat(64)), None, &RetryPolicy::default(), |_| {}).await.unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"), "{}", err);
+        assert!(!bad.exists() && !dir.path().join("bad.gguf.part").exists());
+    }
+
+    #[tokio::test]
+    async fn sends_the_token_and_explains_refusals() {
+        let server = MockServer::start(|req| match (req.path.as_str(), req.header("authorization")) {
+            ("/missing", _) => Response::status(404, "no such file"),
+            (_, Some("Bearer hf_secret")) => Response { status: 200, headers: Vec::new(), body: b"weights".to_vec() },
+            _ => Response::status(401, "gated"),
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.gguf");
+        ensure_model(&path, &format!("{}/model", server.url), None, Some("hf_secret"), &RetryPolicy::default(), |_| {}).await.unwrap();
+        assert_eq!(server.requests()[0].header("authorization"), Some("Bearer hf_secret"));
+        assert_eq!(fs::read(&path).unwrap(), b"weights");
+
+        let refused = ensure_model(&dir.path().join("a"), &format!("{}/model", server.url), None, Some("wrong"), &RetryPolicy::default(), |_| {}).await.unwrap_err();
+        assert!(format!("{:#}", refused).contains("HTTP 401; the model may be gated"), "{:#}", refused);
+        let missing = ensure_model(&dir.path().join("b"), &format!("{}/missing", server.url), None, Some("hf_secret"), &RetryPolicy::default(), |_| {}).await.unwrap_err();
+        assert!(format!("{:#}", missing).contains("not found (HTTP 404)"), "{:#}", missing);
+        // Neither client error was retried.
+        assert_eq!(server.requests().len(), 3);
+    }
+}
@@ -0,0 +1,51 @@
+use std::{fmt, future::Future, sync::{atomic::{AtomicUsize, Ordering}, Arc}};
+
+use futures::{future::BoxFuture, FutureExt};
+
+use super::{cache::SamplingParams, LLMEngine};
+use crate::Result;
+
+type Reply = dyn Fn(String) -> BoxFuture<'static, Result<String>> + Send + Sync;
+
+/// Scripted replies for tests, run through the same retry, timeout and cache path as the HTTP
+/// backends; `calls` counts the requests that reached it.
+#[derive(Clone)]
+pub(crate) struct MockBackend {
+    reply: Arc<Reply>,
+    pub calls: Arc<AtomicUsize>,
+}
+
+impl fmt::Debug for MockBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.debug_struct("MockBackend").field("calls", &self.calls).finish() }
+}
+
+impl MockBackend {
+    pub fn sampling_params(&self) -> SamplingParams {
+        SamplingParams { model: "mock".into(), temperature: Some(0.0), top_p: None, top_k: None, max_tokens: None, repeat_penalty: None, repeat_last_n: None, seed: None }
+    }
+
+    pub async fn generate(&self, prompt: &str) -> Result<String> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        (self.reply)(prompt.to_string()).await
+    }
+}
+
+impl LLMEngine {
+    /// An engine whose backend answers each prompt with `reply`.
+    pub(crate) fn mock<F, Fut>(reply: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        let backend = MockBackend { reply: Arc::new(move |prompt| reply(prompt).boxed()), calls: Arc::new(AtomicUsize::new(0)) };
+        Self { backend: super::Backend::Mock(backend), ..Self::default() }
+    }
+
+    /// Requests that reached the mock backend, retries included.
+    pub(crate) fn mock_calls(&self) -> usize {
+        match &self.backend {
+            super::Backend::Mock(b) => b.calls.load(Ordering::SeqCst),
+            _ => 0,
+        }
+    }
+}
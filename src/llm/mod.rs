@@ -1,76 +1,372 @@
 use crate::Result;
-use std::path::PathBuf;
+use futures::{stream::BoxStream, Stream, StreamExt};
+use std::{fmt, path::PathBuf, time::{Duration, Instant}};
 
+mod builder;
+mod cache;
+mod chat;
+mod device;
 pub mod downloader;
+#[cfg(test)]
+mod mock;
+mod retry;
+#[cfg(feature = "llm")]
+mod candle;
+#[cfg(feature = "llm")]
+mod vllm;
+#[cfg(feature = "ollama")]
+mod ollama;
 
-#[derive(Debug, Clone, Default)]
+#[cfg(feature = "llm")]
+use candle::CandleBackend;
+#[cfg(feature = "llm")]
+use vllm::VllmBackend;
+#[cfg(feature = "ollama")]
+use ollama::OllamaBackend;
+use cache::{ResponseCache, SamplingParams};
+pub use builder::LLMEngineBuilder;
+pub use chat::{ChatMessage, ChatTemplate, Role};
+pub use device::DeviceSpec;
+pub use retry::{HttpStatusError, Retry, RetryHook, RetryPolicy};
+
+/// Default limit for one `generate` call; see `LLMEngine::with_timeout`.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone)]
 pub struct LLMEngine {
     backend: Backend,
+    cache: Option<ResponseCache>,
+    retry: RetryPolicy,
+    retry_hook: Option<RetryHook>,
+    timeout: Option<Duration>,
+    chat_template: Option<ChatTemplate>,
 }
 
-#[derive(Debug, Clone)]
+impl Default for LLMEngine {
+    fn default() -> Self { Self { backend: Backend::default(), cache: None, retry: RetryPolicy::default(), retry_hook: None, timeout: Some(DEFAULT_TIMEOUT), chat_template: None } }
+}
+
+/// Text from `LLMEngine::generate_with_meta` with what producing it took.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Generation {
+    pub text: String,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// Time spent generating; Candle leaves out loading the model on first use.
+    pub elapsed: Duration,
+}
+
+impl Generation {
+    pub fn tokens_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 { self.completion_tokens as f64 / secs } else { 0.0 }
+    }
+}
+
+/// A generation call that exceeded the engine's timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutError {
+    pub after: Duration,
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "LLM generation timed out after {:.1}s", self.after.as_secs_f64()) }
+}
+
+impl std::error::Error for TimeoutError {}
+
+#[derive(Debug, Clone, Default)]
 enum Backend {
     #[cfg(feature = "llm")]
     Candle(CandleBackend),
+    #[cfg(feature = "llm")]
+    Vllm(VllmBackend),
+    #[cfg(feature = "ollama")]
+    Ollama(OllamaBackend),
+    #[cfg(test)]
+    Mock(mock::MockBackend),
+    #[default]
     Stub,
 }
 
-impl Default for Backend {
-    fn default() -> Self { Backend::Stub }
-}
-
 impl LLMEngine {
-    pub fn new() -> Self { Self { backend: Backend::default() } }
+    pub fn new() -> Self { Self::default() }
 
+    pub fn builder() -> LLMEngineBuilder { LLMEngineBuilder::default() }
+
+    /// Shorthand for `builder().model_path(..)` with every optional Candle setting.
     pub fn with_candle(model_path: PathBuf, device: Option<String>, tokenizer_path: Option<PathBuf>, max_tokens: Option<usize>, temperature: Option<f64>, top_p: Option<f64>, top_k: Option<usize>) -> Self {
+        let mut builder = Self::builder().model_path(model_path);
+        builder.device = device;
+        builder.tokenizer_path = tokenizer_path;
+        builder.max_tokens = max_tokens;
+        builder.temperature = temperature;
+        builder.top_p = top_p;
+        builder.top_k = top_k;
+        builder.build()
+    }
+
+    /// Uses a vLLM server's OpenAI-compatible API; `base_url` defaults to `http://localhost:8000/v1`.
+    #[cfg(feature = "llm")]
+    pub fn with_vllm(model: impl Into<String>, base_url: Option<String>) -> Self {
+        Self { backend: Backend::Vllm(VllmBackend::new(model.into(), base_url)), ..Self::default() }
+    }
+
+    /// Uses an Ollama server; `base_url` defaults to `http://localhost:11434`.
+    #[cfg(feature = "ollama")]
+    pub fn with_ollama(base_url: Option<String>, model: String) -> Self {
+        Self { backend: Backend::Ollama(OllamaBackend::new(base_url, model)), ..Self::default() }
+    }
+
+    /// Reuses responses stored under `dir` for identical prompts and sampling parameters.
+    ///
+    /// Only greedy (temperature 0) generation is cached unless `cache_sampled(true)` opts in;
+    /// the stub backend and Candle's `generate_stream` are never cached.
+    pub fn with_cache(mut self, dir: PathBuf) -> Self {
+        self.cache = Some(ResponseCache { dir, sampled: false });
+        self
+    }
+
+    /// Also cache responses sampled with a nonzero temperature, so reruns repeat the first sample.
+    pub fn cache_sampled(mut self, sampled: bool) -> Self {
+        if let Some(cache) = &mut self.cache { cache.sampled = sampled; }
+        self
+    }
+
+    /// Retry policy for the HTTP backends (vLLM, Ollama); the local Candle backend never retries.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Called before each retry, e.g. to tell the user why a request is taking longer.
+    pub fn with_retry_hook(mut self, hook: RetryHook) -> Self {
+        self.retry_hook = Some(hook);
+        self
+    }
+
+    /// Limits each `generate` call (each attempt, when retrying); `None` waits indefinitely.
+    ///
+    /// HTTP backends are cancelled when the limit passes; Candle checks it between tokens and
+    /// stops sampling. Either way the error is a `TimeoutError`.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
         #[cfg(feature = "llm")]
-        {
-            Self { backend: Backend::Candle(CandleBackend::new(model_path, device, tokenizer_path, max_tokens, temperature, top_p, top_k)) }
+        if let Backend::Candle(b) = &mut self.backend { b.timeout = timeout; }
+        self
+    }
+
+    /// Overrides the chat template `generate_chat` renders messages with.
+    pub fn with_chat_template(mut self, template: ChatTemplate) -> Self {
+        self.chat_template = Some(template);
+        self
+    }
+
+    /// The template set with `with_chat_template`, else the one detected from the
+    /// `tokenizer_config.json` beside Candle's tokenizer, else `ChatTemplate::Plain`.
+    pub fn chat_template(&self) -> ChatTemplate {
+        if let Some(template) = self.chat_template { return template; }
+        match &self.backend {
+            #[cfg(feature = "llm")]
+            Backend::Candle(b) => b.detected_chat_template().unwrap_or_default(),
+            _ => ChatTemplate::Plain,
         }
-        #[cfg(not(feature = "llm"))]
-        {
-            let _ = (model_path, device, tokenizer_path, max_tokens, temperature, top_p, top_k);
-            Self { backend: Backend::Stub }
+    }
+
+    fn sampling_params(&self) -> Option<SamplingParams> {
+        match &self.backend {
+            #[cfg(feature = "llm")]
+            Backend::Candle(b) => Some(b.sampling_params()),
+            #[cfg(feature = "llm")]
+            Backend::Vllm(b) => Some(b.sampling_params()),
+            #[cfg(feature = "ollama")]
+            Backend::Ollama(b) => Some(b.sampling_params()),
+            #[cfg(test)]
+            Backend::Mock(b) => Some(b.sampling_params()),
+            _ => None,
+        }
+    }
+
+    /// Tokens `text` takes up for this model: exact with Candle's tokenizer, otherwise
+    /// `estimate_tokens`.
+    pub fn count_tokens(&self, text: &str) -> Result<usize> {
+        match &self.backend {
+            #[cfg(feature = "llm")]
+            Backend::Candle(b) => b.count_tokens(text),
+            _ => Ok(estimate_tokens(text)),
         }
     }
 
     pub async fn generate(&self, prompt: &str) -> Result<String> {
+        let cache_key = match (&self.cache, self.sampling_params()) {
+            (Some(cache), Some(params)) if cache.sampled || params.is_deterministic() => Some(ResponseCache::key(prompt, &params)),
+            _ => None,
+        };
+        if let (Some(cache), Some(key)) = (&self.cache, &cache_key) {
+            if let Some(hit) = cache.get(key) { return Ok(hit); }
+        }
+        let response = self.generate_uncached(prompt).await?;
+        if let (Some(cache), Some(key)) = (&self.cache, &cache_key) { cache.put(key, &response); }
+        Ok(response)
+    }
+
+    /// `generate` with token counts and timing. Candle counts the tokens it sampled; other
+    /// backends and cached responses are measured with `count_tokens`, so the stub's are estimates.
+    pub async fn generate_with_meta(&self, prompt: &str) -> Result<Generation> {
+        #[cfg(feature = "llm")]
+        if let (Backend::Candle(b), None) = (&self.backend, &self.cache) { return b.generate_with_meta(prompt).await; }
+        let started = Instant::now();
+        let text = self.generate(prompt).await?;
+        let elapsed = started.elapsed();
+        Ok(Generation { prompt_tokens: self.count_tokens(prompt)?, completion_tokens: self.count_tokens(&text)?, text, elapsed })
+    }
+
+    /// Renders `messages` with `chat_template()` and generates the assistant's reply.
+    pub async fn generate_chat(&self, messages: &[ChatMessage]) -> Result<String> {
+        self.generate(&self.chat_template().render(messages)).await
+    }
+
+    async fn generate_uncached(&self, prompt: &str) -> Result<String> {
         match &self.backend {
             #[cfg(feature = "llm")]
             Backend::Candle(b) => b.generate(prompt).await,
+            #[cfg(feature = "llm")]
+            Backend::Vllm(b) => self.retry(|| with_timeout(self.timeout, b.generate(prompt))).await,
+            #[cfg(feature = "ollama")]
+            Backend::Ollama(b) => self.retry(|| with_timeout(self.timeout, b.generate(prompt))).await,
+            #[cfg(test)]
+            Backend::Mock(b) => self.retry(|| with_timeout(self.timeout, b.generate(prompt))).await,
             _ => Ok(format!("LLM(stub) response for prompt ({} chars).", prompt.chars().count())),
         }
     }
+
+    #[cfg_attr(not(any(feature = "llm", feature = "ollama")), allow(dead_code))]
+    async fn retry<T, Fut: std::future::Future<Output = Result<T>>>(&self, op: impl FnMut() -> Fut) -> Result<T> {
+        self.retry.run_reporting(op, |retry| if let Some(hook) = &self.retry_hook { hook.call(retry) }).await
+    }
+
+    /// Yields decoded text fragments as they are produced; concatenated they equal `generate`'s output.
+    pub async fn generate_stream(&self, prompt: &str) -> Result<impl Stream<Item = Result<String>>> {
+        let stream: BoxStream<'static, Result<String>> = match &self.backend {
+            #[cfg(feature = "llm")]
+            Backend::Candle(b) => b.generate_stream(prompt).boxed(),
+            // The HTTP backends don't stream yet; going through `generate` keeps retries, the
+            // timeout and the cache the same as for a plain call. The stub's reply is split into
+            // words so streaming callers see several fragments.
+            _ => {
+                let text = self.generate(prompt).await?;
+                let pieces: Vec<Result<String>> = if matches!(self.backend, Backend::Stub) {
+                    text.split_inclusive(' ').map(|p| Ok(p.to_string())).collect()
+                } else {
+                    vec![Ok(text)]
+                };
+                futures::stream::iter(pieces).boxed()
+            }
+        };
+        Ok(stream)
+    }
 }
 
-#[cfg(feature = "llm")]
-#[derive(Debug, Clone)]
-struct CandleBackend {
-    model_path: PathBuf,
-    device: Option<String>,
-    tokenizer_path: Option<PathBuf>,
-    max_tokens: Option<usize>,
-    temperature: Option<f64>,
-    top_p: Option<f64>,
-    top_k: Option<usize>,
+/// Rough token count for when no tokenizer is at hand: subword tokenizers average about four
+/// characters per token on English, and never fewer tokens than words.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4).max(text.split_whitespace().count())
 }
 
-#[cfg(feature = "llm")]
-impl CandleBackend {
-    pub fn new(model_path: PathBuf, device: Option<String>, tokenizer_path: Option<PathBuf>, max_tokens: Option<usize>, temperature: Option<f64>, top_p: Option<f64>, top_k: Option<usize>) -> Self {
-        Self { model_path, device, tokenizer_path, max_tokens, temperature, top_p, top_k }
+#[cfg_attr(not(any(feature = "llm", feature = "ollama")), allow(dead_code))]
+async fn with_timeout<T>(limit: Option<Duration>, fut: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+    match limit {
+        Some(after) => tokio::time::timeout(after, fut).await.map_err(|_| anyhow::Error::new(TimeoutError { after }))?,
+        None => fut.await,
     }
+}
 
-    pub async fn generate(&self, prompt: &str) -> Result<String> {
-        // NOTE: This is a scaffold for Candle-based generation. It shows the structure
-        // required to run GGUF models with candle-transformers and tokenizers.
-        // Implement the actual model loading and generation on a machine with llm feature enabled.
-        // Suggested steps:
-        // 1) let device = if self.device.as_deref() == Some("cuda") { candle_core::Device::new_cuda(0)? } else { candle_core::Device::Cpu };
-        // 2) let tokenizer = tokenizers::Tokenizer::from_file(self.tokenizer_path.clone().unwrap_or_else(|| std::path::PathBuf::from("models/tokenizer.json")))?;
-        // 3) Load GGUF model via candle-transformers quantized loader and build a generation pipeline.
-        // 4) Tokenize prompt, run generation with temperature/top_p/top_k and max_tokens, decode tokens to String.
-        let dev = self.device.clone().unwrap_or_else(|| "cpu".into());
-        Ok(format!("[Candle (scaffold) on {} using {}] {} chars", dev, self.model_path.display(), prompt.len()))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(llm: &LLMEngine, prompt: &str) -> Result<String> {
+        let stream = llm.generate_stream(prompt).await?;
+        let pieces: Vec<String> = futures::TryStreamExt::try_collect(stream).await?;
+        Ok(pieces.concat())
+    }
+
+    #[tokio::test]
+    async fn collected_stream_equals_generate() {
+        let llm = LLMEngine::new();
+        let prompt = "Name three rivers in Europe.";
+        assert!(llm.generate_stream(prompt).await.unwrap().count().await > 1);
+        assert_eq!(collect(&llm, prompt).await.unwrap(), llm.generate(prompt).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn stream_retries_like_generate() {
+        // Only the first request fails.
+        let seen = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let llm = LLMEngine::mock(move |prompt| {
+            let seen = seen.clone();
+            async move {
+                if seen.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                    return Err(anyhow::Error::new(HttpStatusError { status: 503, message: "busy".into() }));
+                }
+                Ok(format!("echo: {}", prompt))
+            }
+        })
+        .with_retry(RetryPolicy { base_delay_ms: 1, ..RetryPolicy::default() });
+        assert_eq!(collect(&llm, "hi").await.unwrap(), "echo: hi");
+        assert_eq!(llm.mock_calls(), 2);
+        assert_eq!(collect(&llm, "hi").await.unwrap(), llm.generate("hi").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn cached_prompts_skip_the_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let echo = || LLMEngine::mock(|prompt| async move { Ok(format!("echo: {}", prompt)) }).with_cache(dir.path().to_path_buf());
+        let llm = echo();
+        assert_eq!(llm.generate("hi").await.unwrap(), "echo: hi");
+        assert_eq!(llm.generate("hi").await.unwrap(), "echo: hi");
+        assert_eq!(llm.mock_calls(), 1);
+        llm.generate("bye").await.unwrap();
+        assert_eq!(llm.mock_calls(), 2);
+
+        // The cache lives on disk, so a fresh engine over the same directory reuses it.
+        let again = echo();
+        assert_eq!(again.generate("hi").await.unwrap(), "echo: hi");
+        assert_eq!(again.mock_calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_and_reported() {
+        let seen = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let llm = LLMEngine::mock(move |prompt| {
+            let seen = seen.clone();
+            async move {
+                if seen.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < 2 {
+                    return Err(anyhow::Error::new(HttpStatusError { status: 503, message: "busy".into() }));
+                }
+                Ok(format!("echo: {}", prompt))
+            }
+        });
+        let reported = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = reported.clone();
+        let llm = llm
+            .with_retry(RetryPolicy { base_delay_ms: 1, jitter: false, ..RetryPolicy::default() })
+            .with_retry_hook(RetryHook::new(move |retry| log.lock().unwrap().push(retry.to_string())));
+        assert_eq!(llm.generate("hi").await.unwrap(), "echo: hi");
+        assert_eq!(llm.mock_calls(), 3);
+        assert_eq!(*reported.lock().unwrap(), ["HTTP 503: busy; retrying in 0.0s (1/2)", "HTTP 503: busy; retrying in 0.0s (2/2)"]);
+    }
+
+    #[tokio::test]
+    async fn slow_replies_time_out() {
+        let llm = LLMEngine::mock(|_| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok("too late".to_string())
+        })
+        .with_retry(RetryPolicy::none())
+        .with_timeout(Some(Duration::from_millis(20)));
+        let err = llm.generate("hi").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<TimeoutError>(), Some(&TimeoutError { after: Duration::from_millis(20) }));
+        assert!(retry::is_transient(&err));
     }
 }
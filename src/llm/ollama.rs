@@ -0,0 +1,111 @@
+use anyhow::Context;
+use serde::Deserialize;
+
+use super::{cache::SamplingParams, HttpStatusError};
+use crate::Result;
+
+const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+/// Calls a local Ollama server's `/api/generate` endpoint without streaming.
+#[derive(Debug, Clone)]
+pub(crate) struct OllamaBackend {
+    client: reqwest::Client,
+    model: String,
+    base_url: String,
+    pub max_tokens: Option<usize>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerateResponse {
+    response: String,
+}
+
+impl OllamaBackend {
+    pub fn new(base_url: Option<String>, model: String) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string()).trim_end_matches('/').to_string();
+        Self { client: reqwest::Client::new(), model, base_url, max_tokens: None, temperature: None, top_p: None, top_k: None, seed: None }
+    }
+
+    // The server picks its own defaults for unset options, so `None` means "not greedy".
+    pub fn sampling_params(&self) -> SamplingParams {
+        SamplingParams {
+            model: format!("{}@{}", self.model, self.base_url),
+            temperature: self.temperature,
+            top_p: self.top_p,
+            top_k: self.top_k,
+            max_tokens: self.max_tokens,
+            repeat_penalty: None,
+            repeat_last_n: None,
+            seed: self.seed,
+        }
+    }
+
+    pub async fn generate(&self, prompt: &str) -> Result<String> {
+        let endpoint = format!("{}/api/generate", self.base_url);
+        let mut options = serde_json::Map::new();
+        if let Some(v) = self.max_tokens { options.insert("num_predict".into(), v.into()); }
+        if let Some(v) = self.temperature { options.insert("temperature".into(), v.into()); }
+        if let Some(v) = self.top_p { options.insert("top_p".into(), v.into()); }
+        if let Some(v) = self.top_k { options.insert("top_k".into(), v.into()); }
+        if let Some(v) = self.seed { options.insert("seed".into(), v.into()); }
+        let body = serde_json::json!({ "model": self.model, "prompt": prompt, "stream": false, "options": options });
+
+        let resp = self
+            .client
+            .post(&endpoint)
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("POST {} failed (is Ollama running?)", endpoint))?;
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+            let err = HttpStatusError { status: status.as_u16(), message: text };
+            return Err(anyhow::Error::new(err).context(format!("POST {}", endpoint)));
+        }
+        let parsed: GenerateResponse = resp.json().await.with_context(|| format!("parsing response from {}", endpoint))?;
+        Ok(parsed.response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{llm::LLMEngine, testutil::{MockServer, Response}};
+
+    #[tokio::test]
+    async fn sends_prompt_and_sampling_options() {
+        let server = MockServer::start(|_| Response::json(serde_json::json!({ "response": "Paris", "done": true })));
+        let llm = LLMEngine::builder().ollama(Some(server.url.clone()), "llama3").temperature(0.2).top_p(0.9).top_k(40).max_tokens(64).seed(7).build();
+        assert_eq!(llm.generate("Capital of France?").await.unwrap(), "Paris");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!((requests[0].method.as_str(), requests[0].path.as_str()), ("POST", "/api/generate"));
+        let body = requests[0].json();
+        assert_eq!(body["model"], "llama3");
+        assert_eq!(body["prompt"], "Capital of France?");
+        assert_eq!(body["stream"], false);
+        assert_eq!(body["options"], serde_json::json!({ "temperature": 0.2, "top_p": 0.9, "top_k": 40, "num_predict": 64, "seed": 7 }));
+    }
+
+    #[tokio::test]
+    async fn omits_unset_options() {
+        let server = MockServer::start(|_| Response::json(serde_json::json!({ "response": "ok" })));
+        LLMEngine::with_ollama(Some(server.url.clone()), "llama3".into()).generate("hi").await.unwrap();
+        let request = &server.requests()[0];
+        assert_eq!(request.header("Content-Type"), Some("application/json"));
+        assert_eq!(request.json()["options"], serde_json::json!({}));
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let server = MockServer::start(|_| Response::status(404, "model 'nope' not found"));
+        let err = LLMEngine::with_ollama(Some(server.url.clone()), "nope".into()).generate("hi").await.unwrap_err();
+        assert!(format!("{:#}", err).contains("HTTP 404: model 'nope' not found"));
+        assert_eq!(server.requests().len(), 1);
+    }
+}
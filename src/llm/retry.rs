@@ -0,0 +1,122 @@
+use std::{fmt, future::Future, sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Retries transient failures (HTTP 429/5xx, timeouts, dropped connections) with exponential
+/// backoff; other errors, including 4xx responses, are returned immediately.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total tries including the first; 1 disables retrying.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after.
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Randomize each delay to between half and all of its value, so clients don't retry in lockstep.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self { Self { max_attempts: 3, base_delay_ms: 500, max_delay_ms: 8_000, jitter: true } }
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self { Self { max_attempts: 1, ..Self::default() } }
+
+    pub async fn run<T, F, Fut>(&self, op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.run_reporting(op, |_| {}).await
+    }
+
+    /// Like `run`, calling `on_retry` before each backoff so callers can tell the user.
+    pub async fn run_reporting<T, F, Fut>(&self, mut op: F, mut on_retry: impl FnMut(&Retry<'_>)) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(err) if attempt < self.max_attempts && is_transient(&err) => {
+                    let delay = self.delay(attempt);
+                    on_retry(&Retry { error: &err, attempt, max_retries: self.max_attempts - 1, delay });
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let ms = self.base_delay_ms.saturating_mul(1u64 << (attempt - 1).min(20)).min(self.max_delay_ms);
+        if !self.jitter { return Duration::from_millis(ms); }
+        // Clock nanos are random enough to spread out retries without pulling in an RNG.
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+        Duration::from_millis(ms / 2 + (ms / 2) * u64::from(nanos % 1000) / 1000)
+    }
+}
+
+/// A transient failure about to be retried; displays as e.g. `HTTP 503: busy; retrying in 0.5s (1/2)`.
+#[derive(Debug)]
+pub struct Retry<'a> {
+    pub error: &'a anyhow::Error,
+    /// 1 for the first retry.
+    pub attempt: u32,
+    pub max_retries: u32,
+    pub delay: Duration,
+}
+
+impl fmt::Display for Retry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}; retrying in {:.1}s ({}/{})", self.error, self.delay.as_secs_f64(), self.attempt, self.max_retries)
+    }
+}
+
+/// Shared callback an `LLMEngine` reports its retries to; see `LLMEngine::with_retry_hook`.
+#[derive(Clone)]
+pub struct RetryHook(Arc<dyn Fn(&Retry<'_>) + Send + Sync>);
+
+impl RetryHook {
+    pub fn new(hook: impl Fn(&Retry<'_>) + Send + Sync + 'static) -> Self { Self(Arc::new(hook)) }
+
+    pub fn call(&self, retry: &Retry<'_>) { (self.0)(retry) }
+}
+
+impl fmt::Debug for RetryHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { f.write_str("RetryHook") }
+}
+
+/// A non-success HTTP response, kept structured so retries can tell 503 from 400.
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "HTTP {}: {}", self.status, self.message) }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+fn is_transient_status(status: u16) -> bool { status == 408 || status == 429 || (500..600).contains(&status) }
+
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<HttpStatusError>() { return is_transient_status(e.status); }
+        if cause.is::<super::TimeoutError>() { return true; }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return match e.status() {
+                Some(status) => is_transient_status(status.as_u16()),
+                None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+            };
+        }
+        false
+    })
+}
@@ -0,0 +1,100 @@
+use anyhow::Context;
+use serde::Deserialize;
+
+use super::{cache::SamplingParams, HttpStatusError};
+use crate::Result;
+
+const DEFAULT_BASE_URL: &str = "http://localhost:8000/v1";
+
+/// Talks to a local vLLM server through its OpenAI-compatible completions API.
+#[derive(Debug, Clone)]
+pub(crate) struct VllmBackend {
+    client: reqwest::Client,
+    model: String,
+    base_url: String,
+    pub max_tokens: Option<usize>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub top_k: Option<usize>,
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionResponse {
+    choices: Vec<CompletionChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompletionChoice {
+    text: String,
+}
+
+impl VllmBackend {
+    pub fn new(model: String, base_url: Option<String>) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string()).trim_end_matches('/').to_string();
+        Self { client: reqwest::Client::new(), model, base_url, max_tokens: None, temperature: None, top_p: None, top_k: None, seed: None }
+    }
+
+    // The server picks its own defaults for unset options, so `None` means "not greedy".
+    pub fn sampling_params(&self) -> SamplingParams {
+        SamplingParams {
+            model: format!("{}@{}", self.model, self.base_url),
+            temperature: self.temperature,
+            top_p: self.top_p,
+            top_k: self.top_k,
+            max_tokens: self.max_tokens,
+            repeat_penalty: None,
+            repeat_last_n: None,
+            seed: self.seed,
+        }
+    }
+
+    fn endpoint(&self) -> String { format!("{}/completions", self.base_url) }
+
+    pub async fn generate(&self, prompt: &str) -> Result<String> {
+        let endpoint = self.endpoint();
+        let mut body = serde_json::json!({ "model": self.model, "prompt": prompt });
+        if let Some(v) = self.max_tokens { body["max_tokens"] = v.into(); }
+        if let Some(v) = self.temperature { body["temperature"] = v.into(); }
+        if let Some(v) = self.top_p { body["top_p"] = v.into(); }
+        if let Some(v) = self.top_k { body["top_k"] = v.into(); }
+        if let Some(v) = self.seed { body["seed"] = v.into(); }
+
+        let resp = self.client.post(&endpoint).json(&body).send().await.with_context(|| format!("POST {}", endpoint))?;
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+            let err = HttpStatusError { status: status.as_u16(), message: text };
+            return Err(anyhow::Error::new(err).context(format!("POST {}", endpoint)));
+        }
+        let parsed: CompletionResponse = resp.json().await.with_context(|| format!("parsing completion from {}", endpoint))?;
+        let choice = parsed.choices.into_iter().next().with_context(|| format!("no choices in completion from {}", endpoint))?;
+        Ok(choice.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{llm::LLMEngine, testutil::{MockServer, Response}};
+
+    #[tokio::test]
+    async fn sends_sampling_params() {
+        let server = MockServer::start(|_| Response::json(serde_json::json!({ "choices": [{ "text": " Paris" }] })));
+        let llm = LLMEngine::builder().vllm("qwen", Some(format!("{}/v1", server.url))).temperature(0.0).max_tokens(16).top_k(5).build();
+        assert_eq!(llm.generate("Capital of France?").await.unwrap(), " Paris");
+        let request = &server.requests()[0];
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/v1/completions"));
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        let body = request.json();
+        assert_eq!((body["model"].as_str(), body["max_tokens"].as_u64(), body["top_k"].as_u64()), (Some("qwen"), Some(16), Some(5)));
+        assert_eq!(body["temperature"], 0.0);
+        assert!(body.get("top_p").is_none());
+    }
+
+    #[tokio::test]
+    async fn reports_http_errors() {
+        let server = MockServer::start(|_| Response::status(400, "max_tokens too large"));
+        let err = LLMEngine::with_vllm("qwen", Some(server.url.clone())).generate("hi").await.unwrap_err();
+        assert!(format!("{:#}", err).contains("HTTP 400: max_tokens too large"));
+    }
+}
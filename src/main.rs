@@ -1,8 +1,11 @@
-use std::path::PathBuf;
+use std::{io::IsTerminal, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::{Duration, Instant}};
 
+use anyhow::Context;
 use clap::{Parser, Subcommand};
-use forge::{document::{DocumentProcessor}, llm::LLMEngine, rag::{EntityExtractor, ForgeIndex, QueryEngine}};
-use forge::graph::KnowledgeGraph;
+use futures::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use forge::{document::ParseOptions, llm::{ChatTemplate, Generation, LLMEngine, RetryHook}, rag::{build_communities, CommunityAlg, CommunityDetector, ForgeIndex, IndexObserver, Indexer, LlmReranker, QueryResult, RelTypes, SummaryGenerator}};
+use forge::{config::Config, graph::KnowledgeGraph, prompts::Prompts};
 use forge::vector::{InMemoryVectorStore, VectorStore};
 
 #[derive(Parser, Debug)]
@@ -16,14 +19,18 @@ struct Cli {
     #[arg(long, global = true)]
     tokenizer_path: Option<PathBuf>,
 
-    /// Device selection (cpu, cuda)
-    #[arg(long, global = true, default_value = "cpu")]
-    device: String,
+    /// Device selection (cpu, cuda, cuda:N, metal); defaults to cpu
+    #[arg(long, global = true)]
+    device: Option<String>,
 
     /// Optional path to a config file (TOML)
     #[arg(long, global = true)]
     config: Option<PathBuf>,
 
+    /// Always call the model instead of reusing cached responses
+    #[arg(long, global = true)]
+    no_cache: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -31,136 +38,588 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Download/setup the recommended model to models/
-    SetupModel { #[arg(long)] url: Option<String>, #[arg(long)] out: Option<PathBuf> },
+    SetupModel {
+        #[arg(long)]
+        url: Option<String>,
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Expected SHA-256 of the downloaded file; a mismatching download is deleted
+        #[arg(long)]
+        sha256: Option<String>,
+        /// Hugging Face access token for gated models (defaults to $HF_TOKEN)
+        #[arg(long)]
+        hf_token: Option<String>,
+    },
     /// Test LLM generation using Candle backend
-    LlmTest { prompt: String, #[arg(long)] tokenizer_path: Option<PathBuf>, #[arg(long)] max_tokens: Option<usize>, #[arg(long)] temperature: Option<f64>, #[arg(long)] top_p: Option<f64>, #[arg(long)] top_k: Option<usize> },
+    LlmTest {
+        prompt: String,
+        #[arg(long)]
+        tokenizer_path: Option<PathBuf>,
+        #[arg(long)]
+        max_tokens: Option<usize>,
+        #[arg(long)]
+        temperature: Option<f64>,
+        #[arg(long)]
+        top_p: Option<f64>,
+        #[arg(long)]
+        top_k: Option<usize>,
+        /// Penalize recently generated tokens (e.g. 1.1); 1 disables it
+        #[arg(long)]
+        repeat_penalty: Option<f32>,
+        /// Number of recent tokens the repeat penalty considers (default 64)
+        #[arg(long)]
+        repeat_last_n: Option<usize>,
+        /// Sampler seed, for reproducible output with temperature > 0
+        #[arg(long)]
+        seed: Option<u64>,
+        #[arg(long)]
+        stream: bool,
+        /// Print token counts and generation speed to stderr
+        #[arg(long)]
+        stats: bool,
+    },
     /// Index documents in a directory and build a knowledge graph
-    Index { input: PathBuf, output: PathBuf },
+    Index {
+        input: PathBuf,
+        output: PathBuf,
+        /// Chunking strategy: words or sentences
+        #[arg(long)]
+        chunk_mode: Option<String>,
+        /// Files parsed and extracted concurrently (defaults to the number of CPUs)
+        #[arg(long)]
+        jobs: Option<usize>,
+        /// Update an existing index at OUTPUT, re-processing only new or changed files
+        #[arg(long)]
+        incremental: bool,
+        /// Most extraction and summary calls to the model in flight at once (unbounded beyond --jobs by default)
+        #[arg(long)]
+        max_concurrency: Option<usize>,
+        /// Don't report progress while indexing
+        #[arg(long, short)]
+        quiet: bool,
+        /// Leave out paths matching these gitignore-style globs (repeatable or comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        exclude: Vec<String>,
+        /// Only index files matching one of these globs (repeatable or comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        include: Vec<String>,
+        /// Follow symlinked files and directories (cycles are detected and skipped)
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Leave out extracted entities with a lower confidence (0 to 1)
+        #[arg(long)]
+        min_confidence: Option<f32>,
+        /// Keep one chunk per distinct text (ignoring case and whitespace), e.g. for repeated boilerplate
+        #[arg(long)]
+        dedupe_chunks: bool,
+    },
     /// Query an existing index
-    Query { query: String, index: PathBuf },
+    Query {
+        query: String,
+        index: PathBuf,
+        /// Retrieval mode: local (chunk search) or global (community summaries)
+        #[arg(long, default_value = "local")]
+        mode: String,
+        /// Print the answer with its source chunks and entities as JSON
+        #[arg(long)]
+        json: bool,
+        /// Rerank a larger candidate set with the LLM before answering (one extra call per candidate)
+        #[arg(long)]
+        rerank: bool,
+        /// Only use entities of these types, e.g. Person,Organization
+        #[arg(long, value_delimiter = ',')]
+        entity_types: Vec<String>,
+        /// Only retrieve chunks from this file or from files under this directory (local mode)
+        #[arg(long)]
+        source: Option<PathBuf>,
+    },
+    /// Merge several indexes into one
+    Merge {
+        #[arg(required = true, num_args = 2..)]
+        inputs: Vec<PathBuf>,
+        #[arg(long, short)]
+        output: PathBuf,
+    },
+    /// Print counts, top entities by degree and entity types of an index
+    Stats {
+        index: PathBuf,
+        /// Print the stats as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show entities and relationships added, removed or re-weighted between two indexes
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Print the diff as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Export graph
-    Export { index: PathBuf, format: String, output: PathBuf },
+    Export {
+        index: PathBuf,
+        format: String,
+        output: PathBuf,
+        /// Only export entities of these types (and edges among them), e.g. Person,Organization
+        #[arg(long, value_delimiter = ',')]
+        entity_types: Vec<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
-    // Merge config values if provided (no mutation of cli)
-    let mut model_path = cli.model_path.clone();
-    let mut device = cli.device.clone();
-    let mut tokenizer_path = cli.tokenizer_path.clone();
-    if let Some(cfg_path) = &cli.config {
-        if let Ok(cfg) = forge::config::Config::load(cfg_path) {
-            if model_path.is_none() { model_path = cfg.model_path; }
-            if device == "cpu" { if let Some(d) = cfg.device { device = d; } }
-            if tokenizer_path.is_none() { tokenizer_path = cfg.tokenizer_json; }
-        }
-    }
+    let cfg = effective_config(&cli)?;
+    // setup-model is what creates a configured-but-missing model, so it skips validation.
+    if !matches!(cli.command, Commands::SetupModel { .. }) { cfg.validate()?; }
+    let mut parse_opts = parse_options(&cfg)?;
+    let query_settings = QuerySettings::from_config(&cfg);
+    let model_path = cfg.model_path;
+    let device = cfg.device.unwrap_or_else(|| "cpu".to_string());
+    let tokenizer_path = cfg.tokenizer_json;
+    let index_jobs = cfg.index_jobs;
+    let embedding_dim = cfg.embedding_dim;
+    let prompts = cfg.prompts.unwrap_or_default();
+    let rel_types = cfg.rel_types.as_ref().map(RelTypes::new).unwrap_or_default();
+    let community_alg: CommunityAlg = cfg.community_alg.as_deref().map(str::parse).transpose()?.unwrap_or_default();
+    let chat_template: Option<ChatTemplate> = cfg.chat_template.as_deref().map(str::parse).transpose()?;
+    let cache_dir = (!cli.no_cache).then(|| cfg.cache_dir.unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIR)));
+    let retry = cfg.retry.unwrap_or_default();
+    let timeout = match cfg.llm_timeout_secs {
+        Some(0) => None,
+        Some(secs) => Some(Duration::from_secs(secs)),
+        None => Some(forge::llm::DEFAULT_TIMEOUT),
+    };
+    let mut base = LLMEngine::builder().device(device).retry(retry).retry_hook(RetryHook::new(|r| eprintln!("{}", r))).timeout(timeout);
+    if let Some(path) = tokenizer_path { base = base.tokenizer_path(path); }
+    if let Some(dir) = cache_dir { base = base.cache_dir(dir).cache_sampled(cfg.cache_sampled.unwrap_or(false)); }
+    if let Some(template) = chat_template { base = base.chat_template(template); }
+    let llm = match &model_path {
+        Some(path) => base.clone().model_path(path.clone()).build(),
+        None => base.clone().build(),
+    };
 
     match cli.command {
-        Commands::SetupModel { url, out } => {
-            let default_url = "https://huggingface.co/lmstudio-community/Qwen3-0.6B-GGUF/resolve/main/Qwen3-0.6B-Q3_K_L.gguf?download=true".to_string();
-            let url = url.unwrap_or(default_url);
-            let out = out.unwrap_or(PathBuf::from("models/Qwen3-0.6B-Q3_K_L.gguf"));
-            let path = forge::llm::downloader::ensure_model(&out, &url).await?;
+        Commands::SetupModel { url, out, sha256, hf_token } => {
+            let url = url.unwrap_or_else(|| DEFAULT_MODEL_URL.to_string());
+            let out = out.unwrap_or_else(|| PathBuf::from(DEFAULT_MODEL));
+            let path = forge::llm::downloader::ensure_model(&out, &url, sha256.as_deref(), hf_token.as_deref(), &retry, |r| eprintln!("{}", r)).await?;
             println!("Model downloaded to {}", path.display());
         }
-        Commands::LlmTest { prompt, tokenizer_path: tp_cli, max_tokens, temperature, top_p, top_k } => {
-            let model_path = model_path.clone().unwrap_or_else(|| PathBuf::from("models/Qwen3-0.6B-Q3_K_L.gguf"));
-            let tokenizer_effective = tp_cli.or(tokenizer_path.clone());
-            let engine = LLMEngine::with_candle(model_path, Some(device.clone()), tokenizer_effective, max_tokens, temperature, top_p, top_k);
-            let out = engine.generate(&prompt).await?;
-            println!("{}", out);
+        Commands::LlmTest { prompt, tokenizer_path: tp_cli, max_tokens, temperature, top_p, top_k, repeat_penalty, repeat_last_n, seed, stream, stats } => {
+            let mut builder = base.model_path(model_path.unwrap_or_else(|| PathBuf::from(DEFAULT_MODEL)));
+            if let Some(path) = tp_cli { builder = builder.tokenizer_path(path); }
+            if let Some(n) = max_tokens { builder = builder.max_tokens(n); }
+            if let Some(t) = temperature { builder = builder.temperature(t); }
+            if let Some(p) = top_p { builder = builder.top_p(p); }
+            if let Some(k) = top_k { builder = builder.top_k(k); }
+            if let Some(penalty) = repeat_penalty { builder = builder.repeat_penalty(penalty); }
+            if let Some(n) = repeat_last_n { builder = builder.repeat_last_n(n); }
+            if let Some(seed) = seed { builder = builder.seed(seed); }
+            let engine = builder.build();
+            let generation = if stream {
+                use std::io::Write;
+                let started = std::time::Instant::now();
+                let mut text = String::new();
+                let mut fragments = std::pin::pin!(engine.generate_stream(&prompt).await?);
+                while let Some(fragment) = fragments.next().await {
+                    let fragment = fragment?;
+                    print!("{}", fragment);
+                    std::io::stdout().flush()?;
+                    text.push_str(&fragment);
+                }
+                println!();
+                let elapsed = started.elapsed();
+                Generation { prompt_tokens: engine.count_tokens(&prompt)?, completion_tokens: engine.count_tokens(&text)?, text, elapsed }
+            } else {
+                let generation = engine.generate_with_meta(&prompt).await?;
+                println!("{}", generation.text);
+                generation
+            };
+            if stats {
+                eprintln!(
+                    "{} prompt tokens, {} completion tokens in {:.2}s ({:.1} tokens/s)",
+                    generation.prompt_tokens,
+                    generation.completion_tokens,
+                    generation.elapsed.as_secs_f64(),
+                    generation.tokens_per_sec()
+                );
+            }
+        }
+        Commands::Index { input, output, chunk_mode, jobs, incremental, max_concurrency, quiet, exclude, include, follow_symlinks, min_confidence, dedupe_chunks } => {
+            if let Some(mode) = chunk_mode { parse_opts.chunk_mode = mode.parse()?; }
+            let jobs = jobs.or(index_jobs).unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4));
+            let max_concurrency = max_concurrency.or(cfg.max_concurrency);
+            let min_confidence = min_confidence.or(cfg.min_confidence);
+            let exclude = if exclude.is_empty() { cfg.exclude.clone().unwrap_or_default() } else { exclude };
+            let include = if include.is_empty() { cfg.include.clone().unwrap_or_default() } else { include };
+            let settings = IndexSettings { parse_opts, jobs, incremental, max_concurrency, quiet, exclude, include, follow_symlinks, min_confidence, dedupe_chunks, embedding_dim, detector: CommunityDetector::new(community_alg), prompts, rel_types };
+            index_cmd_with_cfg(&input, &output, &llm, &settings).await?;
+        }
+        Commands::Query { query, index, mode, json, rerank, entity_types, source } => {
+            let settings = QuerySettings { rerank, entity_types, source, ..query_settings };
+            let result = query_cmd_with_cfg(&query, &index, &mode, &llm, &settings).await?;
+            if json { println!("{}", serde_json::to_string_pretty(&result)?); } else { println!("{}", result.answer); }
         }
-        Commands::Index { input, output } => {
-            index_cmd_with_cfg(&input, &output, &model_path, &device).await?;
+        Commands::Merge { inputs, output } => {
+            merge_cmd(&inputs, &output, &llm, embedding_dim, &CommunityDetector::new(community_alg)).await?;
         }
-        Commands::Query { query, index } => {
-            query_cmd_with_cfg(&query, &index, &cli.model_path, &cli.device).await?;
+        Commands::Stats { index, json } => {
+            stats_cmd(&index, json)?;
         }
-        Commands::Export { index, format, output } => {
-            export_cmd(&index, &format, &output).await?;
+        Commands::Diff { old, new, json } => {
+            diff_cmd(&old, &new, json)?;
+        }
+        Commands::Export { index, format, output, entity_types } => {
+            export_cmd(&index, &format, &output, &entity_types).await?;
         }
     }
 
     Ok(())
 }
 
-async fn index_cmd_with_cfg(input: &PathBuf, output: &PathBuf, model_path: &Option<PathBuf>, device: &str) -> anyhow::Result<()> {
-    let mut graph = KnowledgeGraph::default();
-    let mut chunks_all = Vec::new();
-    let llm = match model_path {
-        Some(p) => LLMEngine::with_candle(p.clone(), Some(device.to_string()), None, None, None, None, None),
-        None => LLMEngine::new(),
+/// Layers the global CLI flags over `FORGE_*` environment variables over the `--config` file;
+/// anything still unset falls back to the built-in defaults at the point of use.
+fn effective_config(cli: &Cli) -> anyhow::Result<Config> {
+    let flags = Config { model_path: cli.model_path.clone(), device: cli.device.clone(), tokenizer_json: cli.tokenizer_path.clone(), ..Default::default() };
+    let file = match &cli.config {
+        Some(path) => Config::load(path).with_context(|| format!("loading config {}", path.display()))?,
+        None => Config::default(),
     };
-    let extractor = EntityExtractor::new(llm.clone());
-
-    // Walk directory
-    for entry in walkdir::WalkDir::new(input).into_iter().filter_map(Result::ok) {
-        let path = entry.path();
-        if path.is_file() {
-            let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("").to_ascii_lowercase();
-            if ["txt", "text", "md", "markdown", "pdf"].contains(&ext.as_str()) {
-                match DocumentProcessor::parse_path(path) {
-                    Ok(chunks) => {
-                        for chunk in &chunks {
-                            // Extract entities
-                            let (entities, relationships) = extractor.extract(chunk).await?;
-                            for mut e in entities {
-                                let id = graph.add_entity(e.clone());
-                                // Ensure entity has id set if not
-                                if graph.nodes.get(&id).is_none() {
-                                    e.id = id.clone();
-                                }
-                            }
-                            for r in relationships { graph.add_relationship(r); }
-                        }
-                        chunks_all.extend(chunks);
-                    }
-                    Err(err) => eprintln!("Failed to parse {}: {}", path.display(), err),
-                }
-            }
-        }
-    }
+    Ok(flags.or(Config::from_env()?).or(file))
+}
+
+/// Chunking options from the config, over the built-in defaults.
+fn parse_options(cfg: &Config) -> anyhow::Result<ParseOptions> {
+    let mut opts = ParseOptions::default();
+    if let Some(tokens) = cfg.chunk_tokens { opts.chunk_tokens = tokens; }
+    if let Some(overlap) = cfg.chunk_overlap { opts.overlap = overlap; }
+    if let Some(split) = cfg.split_large_rows { opts.split_rows = split; }
+    if let Some(mode) = &cfg.chunk_mode { opts.chunk_mode = mode.parse()?; }
+    if let Some(min) = cfg.ocr_min_chars { opts.ocr_min_chars = min; }
+    Ok(opts)
+}
 
-    let index = ForgeIndex { graph, chunks: chunks_all };
-    index.save_json(output)?;
+const DEFAULT_CACHE_DIR: &str = ".forge-cache";
+// The Candle backend runs llama-architecture GGUFs only, so the default model is a Llama.
+const DEFAULT_MODEL: &str = "models/Llama-3.2-1B-Instruct-Q4_K_M.gguf";
+const DEFAULT_MODEL_URL: &str = "https://huggingface.co/bartowski/Llama-3.2-1B-Instruct-GGUF/resolve/main/Llama-3.2-1B-Instruct-Q4_K_M.gguf?download=true";
+
+/// Indexing knobs merged from the CLI and config file.
+struct IndexSettings {
+    parse_opts: ParseOptions,
+    jobs: usize,
+    incremental: bool,
+    max_concurrency: Option<usize>,
+    quiet: bool,
+    exclude: Vec<String>,
+    include: Vec<String>,
+    follow_symlinks: bool,
+    min_confidence: Option<f32>,
+    dedupe_chunks: bool,
+    embedding_dim: Option<usize>,
+    detector: CommunityDetector,
+    prompts: Prompts,
+    rel_types: RelTypes,
+}
+
+async fn index_cmd_with_cfg(input: &Path, output: &Path, llm: &LLMEngine, settings: &IndexSettings) -> anyhow::Result<()> {
+    // Incremental runs start from the previous index and only re-process changed files.
+    let previous = if settings.incremental && output.exists() { Some(ForgeIndex::load(output)?) } else { None };
+    let vs_path = ForgeIndex::vectors_path(output);
+    let vectors = if previous.is_some() && vs_path.exists() { Some(InMemoryVectorStore::load(&vs_path)?) } else { None };
+    let mut indexer = Indexer::new(llm.clone())
+        .with_parse_options(settings.parse_opts.clone())
+        .with_jobs(settings.jobs)
+        .with_detector(settings.detector)
+        .with_prompts(settings.prompts.clone())
+        .with_exclude(settings.exclude.clone())
+        .with_include(settings.include.clone())
+        .with_follow_symlinks(settings.follow_symlinks)
+        .with_rel_types(settings.rel_types.clone())
+        .with_observer(Arc::new(IndexProgress::new(settings.quiet, settings.incremental)));
+    if let Some(max) = settings.max_concurrency { indexer = indexer.with_max_concurrency(max); }
+    if let Some(dim) = settings.embedding_dim { indexer = indexer.with_embedding_dim(dim); }
+    if let Some(min) = settings.min_confidence { indexer = indexer.with_min_confidence(min); }
+
+    let (mut index, mut vs, report) = indexer.update(input, previous, vectors).await?;
+    if settings.dedupe_chunks {
+        let removed = index.dedupe_chunks();
+        for id in removed.keys() { vs.remove(id).await; }
+        if !removed.is_empty() { println!("Removed {} duplicate chunks", removed.len()); }
+    }
+    vs.save(&vs_path)?;
+    index.save(output)?;
     println!("Indexed and saved to {}", output.display());
+    println!("{} files processed, {} skipped", report.processed, report.skipped.len());
+    for (path, reason) in &report.skipped { println!("  {}: {}", path.display(), reason); }
     Ok(())
 }
 
-async fn query_cmd_with_cfg(query: &str, index_path: &PathBuf, model_path: &Option<PathBuf>, device: &str) -> anyhow::Result<()> {
-    let index = ForgeIndex::load_json(index_path)?;
-    let llm = match model_path {
-        Some(p) => LLMEngine::with_candle(p.clone(), Some(device.to_string()), None, None, None, None, None),
-        None => LLMEngine::new(),
-    };
-    let mut vs = InMemoryVectorStore::default();
+async fn merge_cmd(inputs: &[PathBuf], output: &Path, llm: &LLMEngine, embedding_dim: Option<usize>, detector: &CommunityDetector) -> anyhow::Result<()> {
+    let mut merged = ForgeIndex::load(&inputs[0])?;
+    for input in &inputs[1..] {
+        merged.merge(ForgeIndex::load(input)?);
+    }
+    merged.communities = build_communities(&merged.graph, detector, &SummaryGenerator::new(llm.clone())).await?;
+
+    let vs = merged.embed_chunks(embedding_dim).await?;
+    merged.embed_entities(vs.embedder());
+    vs.save(&ForgeIndex::vectors_path(output))?;
+
+    merged.save(output)?;
+    println!("Merged {} indexes into {}", inputs.len(), output.display());
+    Ok(())
+}
+
+/// Retrieval knobs merged from the CLI and config file.
+struct QuerySettings {
+    top_k: Option<usize>,
+    embedding_dim: Option<usize>,
+    hybrid_weight: Option<f32>,
+    max_context_tokens: Option<usize>,
+    rerank: bool,
+    /// Restricts the graph to these entity types when non-empty.
+    entity_types: Vec<String>,
+    /// Restricts retrieval to chunks from this path when set.
+    source: Option<PathBuf>,
+    prompts: Prompts,
+}
+
+impl QuerySettings {
+    /// The config's retrieval settings; the per-query flags start unset.
+    fn from_config(cfg: &Config) -> Self {
+        QuerySettings {
+            top_k: cfg.top_k,
+            embedding_dim: cfg.embedding_dim,
+            hybrid_weight: cfg.hybrid_weight,
+            max_context_tokens: cfg.max_context_tokens,
+            rerank: false,
+            entity_types: Vec::new(),
+            source: None,
+            prompts: cfg.prompts.clone().unwrap_or_default(),
+        }
+    }
+}
 
-    // Insert chunk vectors
-    for chunk in &index.chunks {
-        let v = vs.embed_text(&chunk.text);
-        vs.upsert(chunk.id.clone(), v);
+async fn query_cmd_with_cfg(query: &str, index_path: &Path, mode: &str, llm: &LLMEngine, settings: &QuerySettings) -> anyhow::Result<QueryResult> {
+    let mut index = ForgeIndex::load(index_path)?;
+    if !settings.entity_types.is_empty() { index.graph = filter_types(&index.graph, &settings.entity_types); }
+    // Reuse the vectors persisted at index time; rebuild them for indexes saved without one.
+    let vs_path = ForgeIndex::vectors_path(index_path);
+    let vs = if vs_path.exists() { InMemoryVectorStore::load(&vs_path)? } else { index.embed_chunks(settings.embedding_dim).await? };
+    if index.entity_vectors.is_empty() { index.embed_entities(vs.embedder()); }
+
+    let mut engine = index.into_query_engine(llm.clone(), vs).with_prompts(settings.prompts.clone());
+    if let Some(weight) = settings.hybrid_weight { engine.retriever = engine.retriever.map(|r| r.with_vector_weight(weight)); }
+    if let Some(k) = settings.top_k { engine = engine.with_top_k(k); }
+    if let Some(budget) = settings.max_context_tokens { engine = engine.with_context_budget(budget); }
+    if settings.rerank { engine = engine.with_reranker(Arc::new(LlmReranker::new(llm.clone())), 20); }
+    if let Some(source) = &settings.source { engine = engine.with_source(source); }
+    match mode.to_ascii_lowercase().as_str() {
+        "local" => engine.query_with_sources(query).await,
+        "global" => Ok(QueryResult { answer: engine.query_global(query).await?, chunks: Vec::new(), entities: Vec::new() }),
+        other => anyhow::bail!("Unsupported query mode: {}", other),
     }
+}
+
+/// Indexing progress on stderr: a bar on a terminal, a status line every few seconds otherwise.
+#[derive(Debug)]
+struct IndexProgress {
+    bar: ProgressBar,
+    // Time of the last status line when stderr isn't a terminal.
+    last_log: Option<Mutex<Instant>>,
+    // Report how many files were unchanged before indexing starts.
+    incremental: bool,
+}
+
+impl IndexProgress {
+    const LOG_INTERVAL: Duration = Duration::from_secs(5);
 
-    let engine = QueryEngine::new(index.graph, llm, vs);
-    let answer = engine.query(query).await?;
-    println!("{}", answer);
+    fn new(quiet: bool, incremental: bool) -> Self {
+        if quiet { return Self { bar: ProgressBar::hidden(), last_log: None, incremental }; }
+        if !std::io::stderr().is_terminal() { return Self { bar: ProgressBar::hidden(), last_log: Some(Mutex::new(Instant::now())), incremental }; }
+        let bar = ProgressBar::new_spinner();
+        bar.set_style(ProgressStyle::with_template("{spinner} discovered {pos} files").expect("valid template"));
+        Self { bar, last_log: None, incremental }
+    }
+}
+
+impl IndexObserver for IndexProgress {
+    fn discovered(&self) { self.bar.inc(1); }
+
+    fn start(&self, files: usize, unchanged: usize) {
+        if self.incremental { self.bar.suspend(|| println!("{} unchanged, {} to index", unchanged, files)); }
+        self.bar.set_style(ProgressStyle::with_template("{bar:40} {pos}/{len} files, {msg} chunks ({elapsed})").expect("valid template"));
+        self.bar.set_length(files as u64);
+        self.bar.set_position(0);
+        self.bar.set_message("0");
+    }
+
+    fn file_done(&self, chunks: usize) {
+        self.bar.inc(1);
+        self.bar.set_message(chunks.to_string());
+        let Some(last_log) = &self.last_log else { return };
+        let mut last_log = last_log.lock().expect("progress lock");
+        let done = self.bar.position();
+        let total = self.bar.length().unwrap_or(done);
+        if last_log.elapsed() >= Self::LOG_INTERVAL || done == total {
+            eprintln!("indexed {}/{} files, {} chunks", done, total, chunks);
+            *last_log = Instant::now();
+        }
+    }
+
+    // Printed with the bar hidden, so the output doesn't tear it.
+    fn warn(&self, message: &str) { self.bar.suspend(|| eprintln!("{}", message)); }
+
+    fn finish(&self) { self.bar.finish_and_clear(); }
+}
+
+fn stats_cmd(index_path: &Path, json: bool) -> anyhow::Result<()> {
+    let stats = ForgeIndex::load(index_path)?.stats();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+    println!("nodes: {}\nedges: {}\nchunks: {}\ncommunities: {}\nconnected components: {}", stats.nodes, stats.edges, stats.chunks, stats.communities, stats.components);
+    println!("average strength: {:.3}", stats.avg_strength);
+    if stats.invalid_edges > 0 { println!("invalid edges (dangling or self-loops): {}", stats.invalid_edges); }
+    println!("top entities by degree:");
+    for entity in &stats.top_entities { println!("  {} ({})", entity.name, entity.degree); }
+    println!("entity types:");
+    for (ty, count) in &stats.entity_types { println!("  {}: {}", ty, count); }
+    Ok(())
+}
+
+fn diff_cmd(old_path: &Path, new_path: &Path, json: bool) -> anyhow::Result<()> {
+    let (old, new) = (ForgeIndex::load(old_path)?.graph, ForgeIndex::load(new_path)?.graph);
+    let diff = old.diff(&new);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+    let name = |graph: &KnowledgeGraph, id: &String| graph.nodes.get(id).map_or_else(|| id.clone(), |e| e.name.clone());
+    for e in &diff.added_entities { println!("+ {} ({})", e.name, e.entity_type); }
+    for e in &diff.removed_entities { println!("- {} ({})", e.name, e.entity_type); }
+    for r in &diff.added_relationships { println!("+ {} -[{}]-> {}", name(&new, &r.source), r.rel_type, name(&new, &r.target)); }
+    for r in &diff.removed_relationships { println!("- {} -[{}]-> {}", name(&old, &r.source), r.rel_type, name(&old, &r.target)); }
+    for c in &diff.changed_strength { println!("~ {} -[{}]-> {}: {:.3} -> {:.3}", name(&new, &c.source), c.rel_type, name(&new, &c.target), c.old, c.new); }
+    println!(
+        "{} entities added, {} removed; {} relationships added, {} removed, {} re-weighted",
+        diff.added_entities.len(),
+        diff.removed_entities.len(),
+        diff.added_relationships.len(),
+        diff.removed_relationships.len(),
+        diff.changed_strength.len()
+    );
     Ok(())
 }
 
-async fn export_cmd(index_path: &PathBuf, format: &str, output: &PathBuf) -> anyhow::Result<()> {
-    let index = ForgeIndex::load_json(index_path)?;
+fn filter_types(graph: &KnowledgeGraph, types: &[String]) -> KnowledgeGraph {
+    graph.filter_by_types(&types.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+async fn export_cmd(index_path: &Path, format: &str, output: &Path, entity_types: &[String]) -> anyhow::Result<()> {
+    let mut index = ForgeIndex::load(index_path)?;
+    if !entity_types.is_empty() { index.graph = filter_types(&index.graph, entity_types); }
     match format.to_ascii_lowercase().as_str() {
         "graphml" => {
             let xml = index.graph.to_graphml();
             std::fs::write(output, xml)?;
             println!("Exported GraphML to {}", output.display());
         }
+        "dot" => {
+            std::fs::write(output, index.graph.to_dot())?;
+            println!("Exported DOT to {}", output.display());
+        }
+        "gexf" => {
+            std::fs::write(output, index.graph.to_gexf())?;
+            println!("Exported GEXF to {}", output.display());
+        }
+        "cypher" => {
+            std::fs::write(output, index.graph.to_cypher())?;
+            println!("Exported Cypher to {}", output.display());
+        }
+        "json" => {
+            std::fs::write(output, serde_json::to_string_pretty(&index.graph)?)?;
+            println!("Exported JSON to {}", output.display());
+        }
+        "communities" => {
+            std::fs::write(output, index.export_communities())?;
+            println!("Exported {} communities to {}", index.communities.len(), output.display());
+        }
+        "csv" => {
+            let (nodes, edges) = index.graph.to_csv();
+            let edges_path = output.with_extension("edges.csv");
+            std::fs::write(output, nodes)?;
+            std::fs::write(&edges_path, edges)?;
+            println!("Exported CSV nodes to {} and edges to {}", output.display(), edges_path.display());
+        }
         other => {
             anyhow::bail!("Unsupported export format: {}", other);
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use forge::document::ChunkMode;
+
+    use super::*;
+
+    #[test]
+    fn toml_config_reaches_index_and_query_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("forge.toml");
+        std::fs::write(&path, "chunk_tokens = 120\nchunk_overlap = 20\nchunk_mode = \"sentences\"\ntop_k = 7\nembedding_dim = 64\ncommunity_alg = \"louvain\"\n").unwrap();
+        let cfg = Config::load(&path).unwrap();
+
+        let opts = parse_options(&cfg).unwrap();
+        assert_eq!((opts.chunk_tokens, opts.overlap, opts.chunk_mode), (120, 20, ChunkMode::Sentences));
+        let query = QuerySettings::from_config(&cfg);
+        assert_eq!((query.top_k, query.embedding_dim), (Some(7), Some(64)));
+        assert_eq!(cfg.community_alg.as_deref().map(str::parse::<CommunityAlg>).transpose().unwrap(), Some(CommunityAlg::Louvain));
+
+        let bad = Config { community_alg: Some("girvan-newman".into()), chunk_mode: Some("pages".into()), ..cfg };
+        assert!(bad.community_alg.as_deref().unwrap().parse::<CommunityAlg>().unwrap_err().to_string().contains("expected label_propagation or louvain"));
+        assert!(parse_options(&bad).unwrap_err().to_string().contains("expected words or sentences"));
+    }
+
+    // The only test that touches the environment, so it can't race another reader.
+    #[test]
+    fn flags_beat_env_which_beats_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("forge.toml");
+        std::fs::write(&path, "device = \"cpu\"\ntop_k = 3\nembedding_dim = 32\n").unwrap();
+        let config = path.to_str().unwrap();
+        std::env::set_var("FORGE_DEVICE", "metal");
+        std::env::set_var("FORGE_TOP_K", "9");
+
+        let cli = Cli::parse_from(["forge", "--config", config, "--device", "cuda", "stats", "index.json"]);
+        let cfg = effective_config(&cli).unwrap();
+        assert_eq!((cfg.device.as_deref(), cfg.top_k, cfg.embedding_dim), (Some("cuda"), Some(9), Some(32)));
+
+        let cli = Cli::parse_from(["forge", "--config", config, "stats", "index.json"]);
+        assert_eq!(effective_config(&cli).unwrap().device.as_deref(), Some("metal"));
+
+        std::env::remove_var("FORGE_DEVICE");
+        std::env::remove_var("FORGE_TOP_K");
+        let cfg = effective_config(&cli).unwrap();
+        assert_eq!((cfg.device.as_deref(), cfg.top_k), (Some("cpu"), Some(3)));
+    }
+
+    #[tokio::test]
+    async fn indexing_with_progress_builds_the_same_index() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, text) in [("ada.txt", "Ada Lovelace wrote notes on the Analytical Engine."), ("alan.md", "# Alan\nAlan Turing worked at Bletchley Park.")] {
+            std::fs::write(dir.path().join(name), text).unwrap();
+        }
+        let indexer = Indexer::new(LLMEngine::new()).with_jobs(2);
+        let (plain, _) = indexer.index_directory(dir.path()).await.unwrap();
+        let (shown, report) = indexer.with_observer(Arc::new(IndexProgress::new(false, true))).index_directory(dir.path()).await.unwrap();
+        assert_eq!(report.processed, 2);
+        assert_eq!(serde_json::to_value(&shown).unwrap(), serde_json::to_value(&plain).unwrap());
+    }
+}
@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+
+/// Prompt templates for extraction and answering, overridable from the `[prompts]` config table.
+///
+/// `{name}` placeholders are substituted by `render`; any other braces are left as written, so
+/// templates can describe JSON shapes literally. Unset fields keep the built-in defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Prompts {
+    /// System message for extraction calls, describing the task to chat models.
+    pub extract_system: String,
+    /// Single-chunk extraction. Placeholders: `{text}`.
+    pub extract: String,
+    /// Multi-chunk extraction. Placeholders: `{text}` (the numbered chunks), `{chunk_count}`.
+    pub extract_batch: String,
+    /// Local-search answer. Placeholders: `{query}`, `{entities}`, `{entity_count}`,
+    /// `{relationships}`, `{context}`.
+    pub answer: String,
+    /// System message for local-search answers.
+    pub answer_system: String,
+}
+
+impl Default for Prompts {
+    fn default() -> Self {
+        Self {
+            extract_system: "You are an entity extraction system for building a knowledge graph. You read text and \
+                reply only with strict JSON listing the entities it mentions and the relationships between them."
+                .to_string(),
+            extract: "Extract entities and relationships.\n\
+                Return strict JSON with fields: entities, relationships.\n\
+                entities: [{name, entity_type, description, confidence}] with confidence from 0 to 1\n\
+                relationships: [{source, target, rel_type, description, strength}]\n\
+                Text: \n{text}"
+                .to_string(),
+            extract_batch: "Extract entities and relationships from each chunk below.\n\
+                Return a strict JSON array with exactly one object per chunk, in order: \
+                [{chunk, entities, relationships}] where chunk is the chunk number.\n\
+                entities: [{name, entity_type, description, confidence}] with confidence from 0 to 1\n\
+                relationships: [{source, target, rel_type, description, strength}]\n\n\
+                {text}"
+                .to_string(),
+            answer: "Answer the user query using only the context below.\n\
+                Relevant entities: {entities}\n\
+                Relationships:\n{relationships}\n\n\
+                Context:\n{context}\
+                Query: '{query}'\nBe concise."
+                .to_string(),
+            answer_system: "You answer questions about a document collection using only the retrieved passages and \
+                knowledge-graph facts you are given. If they don't contain the answer, say so."
+                .to_string(),
+        }
+    }
+}
+
+/// Replaces each `{name}` in `template` with its value from `vars` in a single pass, so
+/// substituted text is never expanded again. Unknown placeholders are kept verbatim.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| vars.iter().find(|(name, _)| *name == &after[..end]).map(|(_, v)| (end, v)));
+        match value {
+            Some((end, v)) => {
+                out.push_str(v);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{config::Config, document::Chunk, llm::LLMEngine, rag::EntityExtractor};
+
+    #[test]
+    fn render_substitutes_once_and_keeps_other_braces() {
+        let out = render("{a} and {b} as {\"json\": {c}} {unknown}", &[("a", "{b}"), ("b", "two"), ("c", "3")]);
+        assert_eq!(out, "{b} and two as {\"json\": 3} {unknown}");
+    }
+
+    #[tokio::test]
+    async fn custom_template_from_config_reaches_the_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("forge.toml");
+        std::fs::write(&path, "[prompts]\nextract = \"List the people in <<{text}>> as {\\\"entities\\\": [], \\\"relationships\\\": []}\"\n").unwrap();
+        let prompts = Config::load(&path).unwrap().prompts.unwrap();
+        assert_eq!(prompts.answer, Prompts::default().answer);
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let llm = LLMEngine::mock(move |prompt: String| {
+            log.lock().unwrap().push(prompt);
+            async { Ok(r#"{"entities": [], "relationships": []}"#.to_string()) }
+        });
+        let chunk = Chunk { id: "c0".into(), text: "Ada met Alan".into(), ..Chunk::default() };
+        EntityExtractor::new(llm).with_prompts(prompts).extract(&chunk).await.unwrap();
+        let seen = seen.lock().unwrap();
+        assert!(seen[0].contains("List the people in <<Ada met Alan>> as {\"entities\": [], \"relationships\": []}"), "{}", seen[0]);
+    }
+}
@@ -0,0 +1,629 @@
+use std::{collections::{BTreeMap, HashSet}, path::{Path, PathBuf}, sync::Arc};
+
+use futures::StreamExt;
+
+use super::{build_communities, CommunityDetector, EntityExtractor, FileRecord, ForgeIndex, RelTypes, SummaryGenerator};
+pub use crate::document::SUPPORTED_EXTENSIONS;
+use crate::document::{Chunk, DocumentProcessor, ParseOptions};
+use crate::graph::KnowledgeGraph;
+use crate::ids::{IdStrategy, Standard};
+use crate::llm::LLMEngine;
+use crate::prompts::Prompts;
+use crate::vector::{Bm25Index, Embedder, InMemoryVectorStore, VectorStore};
+use crate::Result;
+
+/// Receives progress while an `Indexer` runs; every method defaults to doing nothing. Skipped
+/// files are also listed in the `IndexReport`, so ignoring `warn` loses no information.
+pub trait IndexObserver: Send + Sync + std::fmt::Debug {
+    /// A supported file was found while walking the input.
+    fn discovered(&self) {}
+    /// Walking is done: `to_index` files are new or changed, `unchanged` are reused.
+    fn start(&self, _to_index: usize, _unchanged: usize) {}
+    /// A file finished (or failed); `chunks` is the running total of new chunks.
+    fn file_done(&self, _chunks: usize) {}
+    /// A file was skipped, e.g. because it couldn't be parsed, or its content contradicts its
+    /// extension, or a chunk couldn't be embedded and is left out of vector search.
+    fn warn(&self, _message: &str) {}
+    fn finish(&self) {}
+}
+
+/// What an indexing run did with the files it found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexReport {
+    /// Files parsed and extracted in this run; unchanged files reused by an update don't count.
+    pub processed: usize,
+    /// Files or directories left out, with the reason: unreadable, unparseable, or a walk error.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+#[derive(Debug)]
+struct Silent;
+
+impl IndexObserver for Silent {}
+
+/// Builds a `ForgeIndex` from a directory of documents: parses files concurrently, extracts
+/// entities with the LLM, embeds chunks and detects communities.
+#[derive(Debug, Clone)]
+pub struct Indexer {
+    pub llm: LLMEngine,
+    pub parse_opts: ParseOptions,
+    /// Files parsed and extracted concurrently.
+    pub jobs: usize,
+    /// Most extraction (and community summary) calls in flight at once; `None` leaves it to `jobs`.
+    pub max_concurrency: Option<usize>,
+    pub embedding_dim: Option<usize>,
+    pub detector: CommunityDetector,
+    pub prompts: Prompts,
+    pub observer: Arc<dyn IndexObserver>,
+    /// Gitignore-style globs, relative to the input, for files and directories to leave out.
+    pub exclude: Vec<String>,
+    /// When non-empty, only files matching one of these globs are indexed.
+    pub include: Vec<String>,
+    /// Walk into symlinked files and directories; symlink cycles are detected and reported.
+    pub follow_symlinks: bool,
+    /// Names new chunks and entities, in place of `parse_opts.ids`.
+    pub ids: Arc<dyn IdStrategy>,
+    pub rel_types: RelTypes,
+    /// Entities extracted with a lower confidence are dropped, with the relationships naming
+    /// them; entities without a confidence are kept.
+    pub min_confidence: Option<f32>,
+}
+
+impl Indexer {
+    pub fn new(llm: LLMEngine) -> Self {
+        Self {
+            llm,
+            parse_opts: ParseOptions::default(),
+            jobs: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4),
+            max_concurrency: None,
+            embedding_dim: None,
+            detector: CommunityDetector::default(),
+            prompts: Prompts::default(),
+            observer: Arc::new(Silent),
+            exclude: Vec::new(),
+            include: Vec::new(),
+            follow_symlinks: false,
+            ids: Arc::new(Standard),
+            rel_types: RelTypes::default(),
+            min_confidence: None,
+        }
+    }
+
+    pub fn with_parse_options(mut self, opts: ParseOptions) -> Self { self.parse_opts = opts; self }
+
+    pub fn with_jobs(mut self, jobs: usize) -> Self { self.jobs = jobs.max(1); self }
+
+    pub fn with_max_concurrency(mut self, max: usize) -> Self { self.max_concurrency = Some(max); self }
+
+    pub fn with_embedding_dim(mut self, dim: usize) -> Self { self.embedding_dim = Some(dim); self }
+
+    pub fn with_detector(mut self, detector: CommunityDetector) -> Self { self.detector = detector; self }
+
+    pub fn with_prompts(mut self, prompts: Prompts) -> Self { self.prompts = prompts; self }
+
+    pub fn with_observer(mut self, observer: Arc<dyn IndexObserver>) -> Self { self.observer = observer; self }
+
+    pub fn with_exclude(mut self, globs: Vec<String>) -> Self { self.exclude = globs; self }
+
+    pub fn with_include(mut self, globs: Vec<String>) -> Self { self.include = globs; self }
+
+    pub fn with_follow_symlinks(mut self, follow: bool) -> Self { self.follow_symlinks = follow; self }
+
+    pub fn with_ids(mut self, ids: Arc<dyn IdStrategy>) -> Self { self.ids = ids; self }
+
+    pub fn with_rel_types(mut self, rel_types: RelTypes) -> Self { self.rel_types = rel_types; self }
+
+    pub fn with_min_confidence(mut self, min: f32) -> Self { self.min_confidence = Some(min); self }
+
+    /// Indexes every supported file under `input` from scratch. Hidden files and anything matched by
+    /// `.gitignore` or `.ignore` files are left out, as are paths matching `exclude`. Symlinks are
+    /// skipped unless `follow_symlinks` is set.
+    pub async fn index_directory(&self, input: &Path) -> Result<(ForgeIndex, IndexReport)> {
+        let (index, _, report) = self.update(input, None, None).await?;
+        Ok((index, report))
+    }
+
+    /// Indexes `input` on top of `previous`, re-processing only new or changed files and dropping
+    /// what came from deleted ones. `vectors` are the previous index's chunk vectors, patched in
+    /// place when given; otherwise every chunk is embedded again. Files that can't be read or
+    /// parsed are skipped and listed in the report rather than failing the run.
+    pub async fn update(&self, input: &Path, previous: Option<ForgeIndex>, vectors: Option<InMemoryVectorStore>) -> Result<(ForgeIndex, InMemoryVectorStore, IndexReport)> {
+        let (mut graph, mut chunks_all, old_files, mut bm25) = match previous {
+            Some(index) => (index.graph, index.chunks, index.files, index.bm25),
+            None => (KnowledgeGraph::default(), Vec::new(), Default::default(), Bm25Index::default()),
+        };
+        // Indexes saved before keyword statistics existed get them built from their chunks.
+        if bm25.len() != chunks_all.len() { bm25 = Bm25Index::build(chunks_all.iter().map(|c| (c.id.as_str(), c.text.as_str()))); }
+        let mut extractor = EntityExtractor::new(self.llm.clone()).with_prompts(self.prompts.clone()).with_rel_types(self.rel_types.clone());
+        // The limiter bounds LLM calls across files, so batches within a file may fan out to it too.
+        if let Some(max) = self.max_concurrency { extractor = extractor.with_max_concurrency(max).with_workers(max); }
+
+        // Collect files in a stable order; `buffered` yields results in that order, so the graph is
+        // built identically no matter which files finish first.
+        let mut report = IndexReport::default();
+        let mut paths = Vec::new();
+        // Matched per file rather than as walk overrides, which would also let in hidden and ignored files.
+        let mut include = ignore::overrides::OverrideBuilder::new(input);
+        for glob in &self.include { include.add(glob)?; }
+        let include = include.build()?;
+        // Followed links can reach the same file by several paths; index it once.
+        let mut seen = HashSet::new();
+        for entry in self.walker(input)? {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    let path = error_path(&err).unwrap_or(input).to_path_buf();
+                    let reason = if err.is_io() { format!("can't read: {}", err) } else { err.to_string() };
+                    self.skip(&mut report, path, reason);
+                    continue;
+                }
+            };
+            // Without following, a symlink's own type is reported, so linked files are left out too.
+            if !entry.file_type().is_some_and(|t| t.is_file()) { continue; }
+            let path = entry.into_path();
+            if !include.is_empty() && !include.matched(&path, false).is_whitelist() { continue; }
+            if self.follow_symlinks && !seen.insert(std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone())) { continue; }
+            let ext = path.extension().and_then(|s| s.to_str()).unwrap_or("").to_ascii_lowercase();
+            if SUPPORTED_EXTENSIONS.contains(&ext.as_str()) || DocumentProcessor::sniff_format(&path).is_some() {
+                self.observer.discovered();
+                paths.push(path);
+            }
+        }
+        let mut files = BTreeMap::new();
+        let mut changed = Vec::new();
+        for path in paths {
+            // An unreadable file is treated as gone, so whatever it contributed before is dropped.
+            let record = match FileRecord::from_path(&path, old_files.get(&path)) {
+                Ok(record) => record,
+                Err(err) => {
+                    self.skip(&mut report, path, format!("can't read: {}", err));
+                    continue;
+                }
+            };
+            if old_files.get(&path).map(|old| &old.hash) != Some(&record.hash) { changed.push(path.clone()); }
+            files.insert(path, record);
+        }
+
+        // Forget everything derived from files that changed or no longer exist.
+        let changed_set: HashSet<&PathBuf> = changed.iter().collect();
+        let stale: HashSet<String> = chunks_all
+            .iter()
+            .filter(|c| c.source_path.as_ref().is_none_or(|p| !files.contains_key(p) || changed_set.contains(p)))
+            .map(|c| c.id.clone())
+            .collect();
+        chunks_all.retain(|c| !stale.contains(&c.id));
+        graph.remove_chunks(&stale);
+        for id in &stale { bm25.remove(id); }
+        self.observer.start(changed.len(), files.len() - changed.len());
+
+        let extractor = &extractor;
+        let results = futures::stream::iter(changed)
+            .map(|path| async move {
+                let opts = ParseOptions { ids: self.ids.clone(), ..self.parse_opts.clone() };
+                let parse_target = path.clone();
+                let (mismatch, parsed) = tokio::task::spawn_blocking(move || {
+                    let (_, mismatch) = DocumentProcessor::detect_format(&parse_target);
+                    (mismatch, DocumentProcessor::parse_path_reporting(&parse_target, &opts))
+                })
+                .await?;
+                match parsed {
+                    Ok((chunks, warnings)) => {
+                        for warning in &warnings { self.observer.warn(warning); }
+                        let extracted = extractor.extract_batch(&chunks).await?;
+                        anyhow::Ok((path, mismatch, Ok((chunks, extracted))))
+                    }
+                    Err(err) => Ok((path, mismatch, Err(err))),
+                }
+            })
+            .buffered(self.jobs.max(1));
+        let mut results = std::pin::pin!(results);
+        let mut new_chunks = Vec::new();
+        // Single writer: merging stays sequential so entity dedup sees files in order.
+        while let Some(result) = results.next().await {
+            let (path, mismatch, parsed) = result?;
+            if let Some(mismatch) = mismatch { self.observer.warn(&mismatch.to_string()); }
+            let (chunks, extracted) = match parsed {
+                Ok(parsed) => parsed,
+                Err(err) => {
+                    self.skip(&mut report, path, format!("failed to parse: {}", err));
+                    self.observer.file_done(new_chunks.len());
+                    continue;
+                }
+            };
+            report.processed += 1;
+            for (entities, relationships) in extracted {
+                let mut dropped = HashSet::new();
+                for e in entities {
+                    if self.min_confidence.is_some_and(|min| e.confidence.is_some_and(|c| c < min)) {
+                        dropped.insert(e.name.trim().to_lowercase());
+                        continue;
+                    }
+                    graph.add_entity_with(e, self.ids.as_ref());
+                }
+                // Extractors name endpoints rather than giving ids; point edges at the real nodes.
+                for mut r in relationships {
+                    if r.source.trim().is_empty() || r.target.trim().is_empty() { continue; }
+                    // Resolving a dropped entity's name would bring it back as a placeholder.
+                    let is_dropped = |name: &str| dropped.contains(&name.trim().to_lowercase()) && graph.find_entity(name.trim()).is_none();
+                    if is_dropped(&r.source) || is_dropped(&r.target) { continue; }
+                    r.source = graph.resolve_entity_with(&r.source, &r.source_chunks, self.ids.as_ref());
+                    r.target = graph.resolve_entity_with(&r.target, &r.source_chunks, self.ids.as_ref());
+                    if r.source != r.target { graph.add_relationship(r); }
+                }
+            }
+            new_chunks.extend(chunks);
+            self.observer.file_done(new_chunks.len());
+        }
+        self.observer.finish();
+
+        // Patch the previous vectors in place when there are any; otherwise embed every chunk.
+        let reuse_vectors = vectors.is_some();
+        let mut vs = vectors.unwrap_or_else(|| self.embedding_dim.map(InMemoryVectorStore::new).unwrap_or_default());
+        for id in &stale { vs.remove(id).await; }
+        let to_embed: Vec<&Chunk> = if reuse_vectors { new_chunks.iter().collect() } else { chunks_all.iter().chain(&new_chunks).collect() };
+        let embedded = embed_chunks(vs.embedder(), &to_embed, self.observer.as_ref());
+        for (chunk, _) in &embedded { vs.set_metadata(chunk.id.clone(), chunk.metadata()); }
+        vs.upsert_batch(embedded.into_iter().map(|(chunk, v)| (chunk.id.clone(), v)).collect()).await?;
+        for chunk in &new_chunks { bm25.add(&chunk.id, &chunk.text); }
+        chunks_all.extend(new_chunks);
+
+        let mut summarizer = SummaryGenerator::new(self.llm.clone());
+        if let Some(max) = self.max_concurrency { summarizer = summarizer.with_workers(max); }
+        let communities = build_communities(&graph, &self.detector, &summarizer).await?;
+        let mut index = ForgeIndex { graph, chunks: chunks_all, communities, files, bm25, entity_vectors: Default::default(), chunk_positions: Default::default() };
+        index.embed_entities(vs.embedder());
+        Ok((index, vs, report))
+    }
+
+    fn walker(&self, input: &Path) -> Result<ignore::Walk> {
+        let mut overrides = ignore::overrides::OverrideBuilder::new(input);
+        for glob in &self.exclude { overrides.add(&format!("!{}", glob))?; }
+        // `require_git(false)` applies .gitignore files even when the input isn't a git checkout.
+        Ok(ignore::WalkBuilder::new(input)
+            .overrides(overrides.build()?)
+            .require_git(false)
+            .follow_links(self.follow_symlinks)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build())
+    }
+
+    fn skip(&self, report: &mut IndexReport, path: PathBuf, reason: String) {
+        self.observer.warn(&format!("Skipping {}: {}", path.display(), reason));
+        report.skipped.push((path, reason));
+    }
+}
+
+fn error_path(err: &ignore::Error) -> Option<&Path> {
+    match err {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => error_path(err),
+        _ => None,
+    }
+}
+
+// Embeds each chunk, warning about and leaving out those the embedder fails on; they can still
+// be found by keyword.
+fn embed_chunks<'c>(embedder: &dyn Embedder, chunks: &[&'c Chunk], observer: &dyn IndexObserver) -> Vec<(&'c Chunk, Vec<f32>)> {
+    chunks
+        .iter()
+        .filter_map(|&chunk| match embedder.try_embed(&chunk.text) {
+            Ok(vector) => Some((chunk, vector)),
+            Err(err) => {
+                observer.warn(&format!("Not embedding chunk {}: {:#}", chunk.id, err));
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn indexer() -> Indexer { Indexer::new(LLMEngine::new()).with_jobs(2) }
+
+    #[tokio::test]
+    async fn identical_files_get_distinct_chunk_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = "Ada Lovelace wrote notes on the Analytical Engine. ".repeat(40);
+        fs::write(dir.path().join("a.txt"), &text).unwrap();
+        fs::write(dir.path().join("b.txt"), &text).unwrap();
+        let (index, _) = indexer().index_directory(dir.path()).await.unwrap();
+        let ids: HashSet<&str> = index.chunks.iter().map(|c| c.id.as_str()).collect();
+        assert!(index.chunks.len() >= 2);
+        assert_eq!(ids.len(), index.chunks.len());
+    }
+
+    /// Keeps the warnings an indexer reports.
+    #[derive(Debug, Default)]
+    struct Warnings(std::sync::Mutex<Vec<String>>);
+
+    impl IndexObserver for Warnings {
+        fn warn(&self, message: &str) { self.0.lock().unwrap().push(message.to_string()); }
+    }
+
+    #[test]
+    fn chunks_that_fail_to_embed_are_left_out_with_a_warning() {
+        struct EmptyRefused;
+        impl Embedder for EmptyRefused {
+            fn embed(&self, _: &str) -> Vec<f32> { vec![1.0] }
+            fn dim(&self) -> usize { 1 }
+            fn try_embed(&self, text: &str) -> Result<Vec<f32>> {
+                anyhow::ensure!(!text.is_empty(), "nothing to embed");
+                Ok(self.embed(text))
+            }
+        }
+        let chunks = [Chunk { id: "full".into(), text: "Ada".into(), ..Chunk::default() }, Chunk { id: "empty".into(), ..Chunk::default() }];
+        let warnings = Warnings::default();
+        let embedded = embed_chunks(&EmptyRefused, &chunks.iter().collect::<Vec<_>>(), &warnings);
+        assert_eq!(embedded.iter().map(|(c, v)| (c.id.as_str(), v.clone())).collect::<Vec<_>>(), [("full", vec![1.0])]);
+        assert_eq!(*warnings.0.lock().unwrap(), ["Not embedding chunk empty: nothing to embed"]);
+    }
+
+    #[tokio::test]
+    async fn a_broken_file_is_skipped_and_the_rest_indexed() {
+        let dir = tempfile::tempdir().unwrap();
+        corpus(dir.path());
+        let broken = dir.path().join("broken.jsonl");
+        fs::write(&broken, "{\"name\": \"Ada\"}\n{not json").unwrap();
+        // Unsupported files are passed over without a mention.
+        fs::write(dir.path().join("photo.png"), [0x89, b'P', b'N', b'G']).unwrap();
+        let warnings = Arc::new(Warnings::default());
+        let (index, report) = indexer().with_observer(warnings.clone()).index_directory(dir.path()).await.unwrap();
+        assert_eq!(report.processed, 5);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].0, broken);
+        assert!(report.skipped[0].1.starts_with("failed to parse"), "{}", report.skipped[0].1);
+        assert_eq!(*warnings.0.lock().unwrap(), [format!("Skipping {}: {}", broken.display(), report.skipped[0].1)]);
+        let sources: HashSet<_> = index.chunks.iter().filter_map(|c| c.source_path.as_deref()).collect();
+        assert_eq!(sources.len(), 5);
+        assert!(!sources.contains(broken.as_path()));
+
+        // Without an observer the skip is only in the report.
+        let (_, report) = indexer().index_directory(dir.path()).await.unwrap();
+        assert_eq!(report.skipped.len(), 1);
+    }
+
+    #[cfg(feature = "sniff")]
+    #[tokio::test]
+    async fn misnamed_files_are_reported_to_the_observer() {
+        let dir = tempfile::tempdir().unwrap();
+        corpus(dir.path());
+        let fake = dir.path().join("notes.txt");
+        fs::write(&fake, "%PDF-1.5\n%truncated").unwrap();
+        let warnings = Arc::new(Warnings::default());
+        let (_, report) = indexer().with_observer(warnings.clone()).index_directory(dir.path()).await.unwrap();
+        assert_eq!(report.skipped.iter().map(|(p, _)| p).collect::<Vec<_>>(), [&fake]);
+        let warnings = warnings.0.lock().unwrap();
+        assert_eq!(warnings[0], format!("{} looks like pdf despite its .txt extension; parsing it as pdf", fake.display()));
+        assert!(warnings[1].starts_with(&format!("Skipping {}", fake.display())));
+    }
+
+    /// An indexer whose LLM answers every prompt with `reply`, community summaries included.
+    fn indexer_replying(reply: &'static str) -> Indexer {
+        Indexer::new(LLMEngine::mock(move |_| async move { Ok(reply.to_string()) })).with_jobs(2)
+    }
+
+    const ADA: &str = r#"{"entities": [{"name": "Ada Lovelace", "entity_type": "Person"}, {"name": "Analytical Engine", "entity_type": "Machine"}],
+        "relationships": [{"source": "Ada Lovelace", "target": "Analytical Engine", "rel_type": "wrote_about"}]}"#;
+
+    #[tokio::test]
+    async fn relationships_keep_their_chunk_through_indexing() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("ada.txt"), "Ada Lovelace wrote notes on the Analytical Engine.").unwrap();
+        let (index, _) = indexer_replying(ADA).index_directory(dir.path()).await.unwrap();
+        assert_eq!(index.chunks.len(), 1);
+        assert_eq!(index.graph.edges.len(), 1);
+        assert_eq!(index.graph.edges[0].source_chunks, [index.chunks[0].id.clone()]);
+    }
+
+    #[tokio::test]
+    async fn relationship_names_resolve_to_entity_ids() {
+        const REPLY: &str = r#"{"entities": [{"name": "Ada Lovelace", "entity_type": "Person"}, {"name": "Analytical Engine", "entity_type": "Machine"}],
+            "relationships": [{"source": "ada lovelace", "target": " Analytical Engine", "rel_type": "wrote_about"},
+                              {"source": "Ada Lovelace", "target": "Charles Babbage", "rel_type": "worked_with"}]}"#;
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("ada.txt"), "Ada Lovelace wrote notes on the Analytical Engine with Charles Babbage.").unwrap();
+        let (index, _) = indexer_replying(REPLY).index_directory(dir.path()).await.unwrap();
+        let g = &index.graph;
+        assert!(g.validate().is_empty());
+        let named = |name: &str| g.find_entity(name).unwrap().id.clone();
+        let mut edges: Vec<_> = g.edges.iter().map(|e| (e.source.clone(), e.target.clone())).collect();
+        edges.sort();
+        let mut expected = vec![(named("Ada Lovelace"), named("Analytical Engine")), (named("Ada Lovelace"), named("Charles Babbage"))];
+        expected.sort();
+        assert_eq!(edges, expected);
+        // An endpoint no entity listed becomes a node of its own.
+        assert_eq!(g.nodes.len(), 3);
+        assert_eq!(g.find_entity("Charles Babbage").unwrap().entity_type, "Unknown");
+    }
+
+    #[tokio::test]
+    async fn entities_below_min_confidence_are_left_out() {
+        const REPLY: &str = r#"{"entities": [
+            {"name": "Ada Lovelace", "entity_type": "Person", "confidence": 0.9},
+            {"name": "Charles Babbage", "entity_type": "Person", "confidence": "70%"},
+            {"name": "London", "entity_type": "Place", "confidence": 85},
+            {"name": "Analytical Engine", "entity_type": "Machine", "confidence": "high"},
+            {"name": "Rumor", "entity_type": "Concept", "confidence": 0.2},
+            {"name": "Gossip", "entity_type": "Concept", "confidence": "20%"}],
+            "relationships": [{"source": "Ada Lovelace", "target": "Charles Babbage", "rel_type": "met"},
+                              {"source": "Ada Lovelace", "target": "Rumor", "rel_type": "heard"}]}"#;
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("ada.txt"), "Ada Lovelace met Charles Babbage in London.").unwrap();
+        let (index, _) = indexer_replying(REPLY).with_min_confidence(0.5).index_directory(dir.path()).await.unwrap();
+        let mut kept: Vec<(&str, Option<f32>)> = index.graph.nodes.values().map(|e| (e.name.as_str(), e.confidence)).collect();
+        kept.sort_by_key(|(name, _)| *name);
+        // 85 isn't read as 85%: values over 1 without a `%` are capped at 1.
+        assert_eq!(kept, [("Ada Lovelace", Some(0.9)), ("Analytical Engine", None), ("Charles Babbage", Some(0.7)), ("London", Some(1.0))]);
+        assert_eq!(index.graph.edges.len(), 1);
+        assert_eq!(index.graph.edges[0].rel_type, "met");
+    }
+
+    /// Writes a few small documents naming different people and places.
+    fn corpus(dir: &Path) {
+        let docs = [
+            ("ada.txt", "Ada Lovelace met Charles Babbage in London."),
+            ("alan.txt", "Alan Turing worked at Bletchley Park near London."),
+            ("grace.md", "# Navy\nGrace Hopper wrote COBOL compilers for the Navy."),
+            ("edsger.txt", "Edsger Dijkstra taught in Austin and Eindhoven."),
+            ("barbara.txt", "Barbara Liskov designed CLU at MIT with Alan Turing in mind."),
+        ];
+        for (name, text) in docs { fs::write(dir.join(name), text).unwrap(); }
+    }
+
+    const NAMES: [&str; 8] = ["Ada", "Babbage", "London", "Turing", "Hopper", "Navy", "Dijkstra", "Liskov"];
+
+    /// A mock LLM that extracts the `NAMES` a prompt mentions, linking each to the next. Replies
+    /// take longer for shorter prompts, so concurrent files finish out of order.
+    fn jittery_llm() -> LLMEngine {
+        LLMEngine::mock(|prompt: String| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20 - (prompt.len() % 20) as u64)).await;
+            let found: Vec<&str> = NAMES.into_iter().filter(|n| prompt.contains(n)).collect();
+            let entities: Vec<_> = found.iter().map(|n| serde_json::json!({"name": n, "entity_type": "Thing"})).collect();
+            let relationships: Vec<_> = found.windows(2).map(|w| serde_json::json!({"source": w[0], "target": w[1], "rel_type": "near"})).collect();
+            Ok(serde_json::json!({"entities": entities, "relationships": relationships}).to_string())
+        })
+    }
+
+    #[tokio::test]
+    async fn parallel_and_sequential_indexes_match() {
+        let dir = tempfile::tempdir().unwrap();
+        corpus(dir.path());
+        let (sequential, _) = Indexer::new(jittery_llm()).with_jobs(1).index_directory(dir.path()).await.unwrap();
+        let (parallel, _) = Indexer::new(jittery_llm()).with_jobs(4).index_directory(dir.path()).await.unwrap();
+        assert!(sequential.graph.nodes.len() > 5 && sequential.graph.edges.len() > 3);
+        assert_eq!(serde_json::to_value(&parallel).unwrap(), serde_json::to_value(&sequential).unwrap());
+    }
+
+    /// Like `jittery_llm`, but answers batch prompts with one extraction per `### Chunk` section.
+    fn jittery_batch_llm() -> LLMEngine {
+        LLMEngine::mock(|prompt: String| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20 - (prompt.len() % 20) as u64)).await;
+            let extraction = |text: &str| {
+                let found: Vec<&str> = NAMES.into_iter().filter(|n| text.contains(n)).collect();
+                let entities: Vec<_> = found.iter().map(|n| serde_json::json!({"name": n, "entity_type": "Thing"})).collect();
+                let relationships: Vec<_> = found.windows(2).map(|w| serde_json::json!({"source": w[0], "target": w[1], "rel_type": "near"})).collect();
+                serde_json::json!({"entities": entities, "relationships": relationships})
+            };
+            let sections: Vec<&str> = prompt.split("### Chunk ").skip(1).collect();
+            if sections.is_empty() { return Ok(extraction(&prompt).to_string()); }
+            let batch: Vec<_> = sections.iter().enumerate().map(|(i, text)| { let mut e = extraction(text); e["chunk"] = i.into(); e }).collect();
+            Ok(serde_json::Value::from(batch).to_string())
+        })
+    }
+
+    #[tokio::test]
+    async fn graph_is_the_same_for_any_concurrency() {
+        let dir = tempfile::tempdir().unwrap();
+        corpus(dir.path());
+        // Small chunks so each file yields several batches to extract concurrently.
+        let opts = ParseOptions { chunk_tokens: 4, ..ParseOptions::default() };
+        let build = |jobs, max| Indexer::new(jittery_batch_llm()).with_parse_options(opts.clone()).with_jobs(jobs).with_max_concurrency(max);
+        let (baseline, _) = build(1, 1).index_directory(dir.path()).await.unwrap();
+        assert!(baseline.chunks.len() > 10 && baseline.graph.nodes.len() == NAMES.len() && !baseline.graph.edges.is_empty());
+        for (jobs, max) in [(1, 8), (4, 1), (4, 8)] {
+            let (index, _) = build(jobs, max).index_directory(dir.path()).await.unwrap();
+            assert_eq!(serde_json::to_value(&index.graph).unwrap(), serde_json::to_value(&baseline.graph).unwrap(), "jobs {} max_concurrency {}", jobs, max);
+        }
+    }
+
+    #[tokio::test]
+    async fn update_reextracts_only_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        corpus(dir.path());
+        let (first, vectors, _) = indexer().update(dir.path(), None, None).await.unwrap();
+        let edsger = dir.path().join("edsger.txt");
+        let kept: Vec<String> = first.chunks.iter().filter(|c| c.source_path.as_deref() != Some(edsger.as_path())).map(|c| c.id.clone()).collect();
+
+        fs::write(&edsger, "Edsger Dijkstra wrote about shortest paths in Eindhoven.").unwrap();
+        let prompts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = prompts.clone();
+        let llm = LLMEngine::mock(move |prompt: String| {
+            log.lock().unwrap().push(prompt);
+            async { Ok("no structured output".to_string()) }
+        });
+        let (second, _, report) = Indexer::new(llm).update(dir.path(), Some(first), Some(vectors)).await.unwrap();
+        assert_eq!(report.processed, 1);
+        let prompts = prompts.lock().unwrap();
+        assert!(prompts.iter().any(|p| p.contains("shortest paths")));
+        for unchanged in ["met Charles Babbage", "worked at Bletchley", "wrote COBOL", "designed CLU"] {
+            assert!(!prompts.iter().any(|p| p.contains(unchanged)), "{} was re-extracted", unchanged);
+        }
+        assert!(kept.iter().all(|id| second.chunk(id).is_some()));
+        assert!(second.chunks.iter().any(|c| c.text.contains("shortest paths")));
+        assert!(!second.chunks.iter().any(|c| c.text.contains("taught in Austin")));
+    }
+
+    #[tokio::test]
+    async fn library_api_indexes_saves_and_answers() {
+        let dir = tempfile::tempdir().unwrap();
+        corpus(dir.path());
+        let (index, vectors, report) = Indexer::new(jittery_llm()).with_jobs(2).with_embedding_dim(64).update(dir.path(), None, None).await.unwrap();
+        assert_eq!((report.processed, report.skipped.len()), (5, 0));
+        let path = dir.path().join("out").join("index.json");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        index.save(&path).unwrap();
+        vectors.save(&ForgeIndex::vectors_path(&path)).unwrap();
+
+        let index = ForgeIndex::load(&path).unwrap();
+        let vectors = InMemoryVectorStore::load(&ForgeIndex::vectors_path(&path)).unwrap();
+        assert_eq!(vectors.dim(), 64);
+        let engine = index.into_query_engine(jittery_llm(), vectors).with_top_k(2);
+        let result = engine.query_with_sources("Which compilers did Grace Hopper write?").await.unwrap();
+        let grace = dir.path().join("grace.md");
+        assert!(result.chunks.iter().any(|c| c.source_path.as_deref() == Some(grace.as_path())), "{:?}", result.chunks);
+        assert!(result.entities.iter().any(|id| engine.graph.nodes[id].name == "Hopper"));
+    }
+
+    fn indexed_files(index: &ForgeIndex, root: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<_> = index.files.keys().map(|p| p.strip_prefix(root).unwrap().to_path_buf()).collect();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn ignored_and_excluded_folders_are_left_out() {
+        let dir = tempfile::tempdir().unwrap();
+        for sub in ["docs", "build", "docs/drafts"] { fs::create_dir_all(dir.path().join(sub)).unwrap(); }
+        for file in ["docs/guide.md", "build/out.txt", "build/log.md", "docs/drafts/idea.txt", "top.txt"] {
+            fs::write(dir.path().join(file), format!("Ada Lovelace in {}", file)).unwrap();
+        }
+        fs::write(dir.path().join(".gitignore"), "build/\n").unwrap();
+        let (index, report) = indexer().index_directory(dir.path()).await.unwrap();
+        assert_eq!(indexed_files(&index, dir.path()), [Path::new("docs/drafts/idea.txt"), Path::new("docs/guide.md"), Path::new("top.txt")]);
+        assert!(report.skipped.is_empty());
+        assert!(index.chunks.iter().all(|c| !c.text.contains("build/")));
+
+        let (index, _) = indexer().with_exclude(vec!["drafts".into()]).index_directory(dir.path()).await.unwrap();
+        assert_eq!(indexed_files(&index, dir.path()), [Path::new("docs/guide.md"), Path::new("top.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn following_a_symlink_cycle_terminates_without_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        fs::create_dir_all(docs.join("nested")).unwrap();
+        fs::write(docs.join("a.txt"), "Ada Lovelace wrote notes.").unwrap();
+        fs::write(docs.join("nested/b.txt"), "Alan Turing broke codes.").unwrap();
+        // nested/loop points back up at docs, and alias.txt at a file already indexed.
+        std::os::unix::fs::symlink(&docs, docs.join("nested/loop")).unwrap();
+        std::os::unix::fs::symlink(docs.join("a.txt"), docs.join("alias.txt")).unwrap();
+
+        let (index, report) = indexer().with_follow_symlinks(true).index_directory(&docs).await.unwrap();
+        assert_eq!(index.chunks.len(), 2);
+        let mut texts: Vec<_> = index.chunks.iter().map(|c| c.text.as_str()).collect();
+        texts.sort();
+        assert_eq!(texts, ["Ada Lovelace wrote notes.", "Alan Turing broke codes."]);
+        assert_eq!(report.skipped.len(), 1, "{:?}", report.skipped);
+        assert!(report.skipped[0].0.starts_with(docs.join("nested/loop")), "{:?}", report.skipped);
+
+        // Without following, links are passed over entirely.
+        let (index, report) = indexer().index_directory(&docs).await.unwrap();
+        assert_eq!((index.chunks.len(), report.skipped.len()), (2, 0));
+    }
+}
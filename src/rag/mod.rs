@@ -1,13 +1,30 @@
-use std::{fs, path::Path};
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs, io::{BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}, sync::{Arc, OnceLock}, time::UNIX_EPOCH};
 
+use anyhow::Context;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
 
-use crate::{document::Chunk, graph::{Entity, KnowledgeGraph, Relationship}, llm::LLMEngine, Result};
-use crate::vector::VectorStore;
+use crate::{document::Chunk, graph::{Entity, EntityId, GraphIssue, KnowledgeGraph, Relationship}, llm::{ChatMessage, LLMEngine}, Result};
+use crate::prompts::{render, Prompts};
+use crate::vector::{cosine_similarity, Bm25Index, Embedder, HybridRetriever, InMemoryVectorStore, VectorStore};
+
+mod indexer;
+pub use indexer::{IndexObserver, IndexReport, Indexer, SUPPORTED_EXTENSIONS};
 
 #[derive(Debug, Clone)]
 pub struct EntityExtractor {
     pub llm: LLMEngine,
+    /// Number of chunks packed into one LLM call by `extract_batch`.
+    pub batch_size: usize,
+    pub prompts: Prompts,
+    /// Caps LLM calls in flight across all clones of this extractor.
+    pub limiter: Option<Arc<Semaphore>>,
+    /// Batches `extract_batch` works on at once; results keep chunk order regardless.
+    pub workers: usize,
+    /// Maps extracted relationship types onto a canonical vocabulary.
+    pub rel_types: RelTypes,
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -15,6 +32,27 @@ pub struct ExtractedEntity {
     pub name: String,
     pub entity_type: String,
     pub description: Option<String>,
+    /// 0 to 1; a numeric string or a `"85%"` percentage is accepted, values above 1 are capped
+    /// at 1, and anything else is ignored.
+    #[serde(default, deserialize_with = "lenient_confidence")]
+    pub confidence: Option<f32>,
+}
+
+/// Confidence given to entities found by the capitalized-term fallback rather than the model.
+pub const HEURISTIC_CONFIDENCE: f32 = 0.3;
+
+fn lenient_confidence<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<f32>, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    // Only an explicit `%` marks a percentage; any other value above 1 is taken as certainty.
+    let n = match &value {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => match s.trim().strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>().ok().map(|n| n / 100.0),
+            None => s.trim().parse().ok(),
+        },
+        _ => None,
+    };
+    Ok(n.filter(|n| n.is_finite() && *n >= 0.0).map(|n| n.min(1.0) as f32))
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -27,71 +65,262 @@ pub struct ExtractedRelationship {
 }
 
 impl EntityExtractor {
-    pub fn new(llm: LLMEngine) -> Self { Self { llm } }
+    pub fn new(llm: LLMEngine) -> Self { Self { llm, batch_size: 4, prompts: Prompts::default(), limiter: None, workers: 1, rel_types: RelTypes::default() } }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self { self.batch_size = batch_size.max(1); self }
+
+    pub fn with_prompts(mut self, prompts: Prompts) -> Self { self.prompts = prompts; self }
+
+    pub fn with_max_concurrency(mut self, max: usize) -> Self { self.limiter = Some(Arc::new(Semaphore::new(max.max(1)))); self }
+
+    pub fn with_workers(mut self, workers: usize) -> Self { self.workers = workers.max(1); self }
+
+    pub fn with_rel_types(mut self, rel_types: RelTypes) -> Self { self.rel_types = rel_types; self }
+
+    async fn generate(&self, prompt: &str) -> Result<String> {
+        let _permit = match &self.limiter {
+            Some(limiter) => Some(limiter.acquire().await?),
+            None => None,
+        };
+        self.llm.generate_chat(&[ChatMessage::system(&self.prompts.extract_system), ChatMessage::user(prompt)]).await
+    }
 
     pub async fn extract(&self, chunk: &Chunk) -> Result<(Vec<Entity>, Vec<Relationship>)> {
         // Try LLM-powered extraction; fallback to heuristic if LLM not active or parsing fails.
-        let prompt = format!(
-            "You are an entity extraction system. Extract entities and relationships.\n\
-            Return strict JSON with fields: entities, relationships.\n\
-            entities: [{{name, entity_type, description}}]\n\
-            relationships: [{{source, target, rel_type, description, strength}}]\n\
-            Text: \n{}",
-            chunk.text
-        );
-        if let Ok(text) = self.llm.generate(&prompt).await {
+        let prompt = render(&self.prompts.extract, &[("text", &chunk.text)]);
+        if let Ok(text) = self.generate(&prompt).await {
             if let Some((ents, rels)) = parse_extraction_json(&text) {
-                let entities: Vec<Entity> = ents.into_iter().map(|e| Entity{
-                    id: String::new(),
-                    name: e.name,
-                    entity_type: e.entity_type,
-                    description: e.description.unwrap_or_default(),
-                    source_chunks: vec![chunk.id.clone()],
-                }).collect();
-                let relationships: Vec<Relationship> = rels.into_iter().map(|r| Relationship{
-                    source: r.source,
-                    target: r.target,
-                    rel_type: r.rel_type,
-                    description: r.description.unwrap_or_default(),
-                    strength: r.strength.unwrap_or(1.0),
-                }).collect();
-                return Ok((entities, relationships));
-            }
-        }
-        // Heuristic fallback
-        let mut names = collect_capitalized_terms(&chunk.text);
-        names.truncate(16);
-        let entities: Vec<Entity> = names.iter().map(|name| Entity {
-            id: String::new(),
-            name: name.clone(),
-            entity_type: "Concept".to_string(),
-            description: String::new(),
-            source_chunks: vec![chunk.id.clone()],
-        }).collect();
-        let relationships: Vec<Relationship> = Vec::new();
-        Ok((entities, relationships))
-    }
-}
-
-fn parse_extraction_json(text: &str) -> Option<(Vec<ExtractedEntity>, Vec<ExtractedRelationship>)> {
-    // Find first JSON block in response and parse
-    let start = text.find('{')?;
-    let end = text.rfind('}')?;
-    let json = &text[start..=end];
-    let v: serde_json::Value = serde_json::from_str(json).ok()?;
-    let ents: Vec<ExtractedEntity> = serde_json::from_value(v.get("entities")?.clone()).ok()?;
-    let rels: Vec<ExtractedRelationship> = serde_json::from_value(v.get("relationships")?.clone()).ok()?;
-    Some((ents, rels))
+                return Ok(to_graph_items(ents, rels, chunk, &self.rel_types));
+            }
+        }
+        Ok(heuristic_extract(chunk))
+    }
+
+    /// Extracts from several chunks per LLM call, returning one result per input chunk in order.
+    ///
+    /// Up to `workers` batches run concurrently and may finish in any order; results are put back
+    /// in chunk order, so the graph built from them doesn't depend on scheduling. A batch whose
+    /// response can't be parsed is retried chunk by chunk with `extract`.
+    pub async fn extract_batch(&self, chunks: &[Chunk]) -> Result<Vec<(Vec<Entity>, Vec<Relationship>)>> {
+        let mut batches: Vec<_> = futures::stream::iter(chunks.chunks(self.batch_size.max(1)).enumerate())
+            .map(|(i, batch)| async move { anyhow::Ok((i, self.extract_one_batch(batch).await?)) })
+            .buffer_unordered(self.workers.max(1))
+            .try_collect()
+            .await?;
+        batches.sort_by_key(|(i, _)| *i);
+        Ok(batches.into_iter().flat_map(|(_, results)| results).collect())
+    }
+
+    async fn extract_one_batch(&self, batch: &[Chunk]) -> Result<Vec<(Vec<Entity>, Vec<Relationship>)>> {
+        if batch.len() == 1 { return Ok(vec![self.extract(&batch[0]).await?]); }
+        let mut texts = String::new();
+        for (i, chunk) in batch.iter().enumerate() {
+            texts.push_str(&format!("### Chunk {}\n{}\n\n", i, chunk.text));
+        }
+        let prompt = render(&self.prompts.extract_batch, &[("text", &texts), ("chunk_count", &batch.len().to_string())]);
+        let parsed = match self.generate(&prompt).await {
+            Ok(text) => parse_batch_extraction_json(&text, batch.len()),
+            Err(_) => None,
+        };
+        match parsed {
+            Some(results) => Ok(batch.iter().zip(results).map(|(chunk, (ents, rels))| to_graph_items(ents, rels, chunk, &self.rel_types)).collect()),
+            None => {
+                let mut out = Vec::with_capacity(batch.len());
+                for chunk in batch { out.push(self.extract(chunk).await?); }
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// Canonical relationship types and the phrasings extraction may use for them. Types are compared
+/// after `normalize_rel_type`, so "Employed by" matches a listed `employed_by`; types not listed
+/// are only normalized.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelTypes {
+    // Normalized synonym (or canonical type) -> normalized canonical type.
+    canonical: HashMap<String, String>,
+}
+
+impl RelTypes {
+    /// From canonical types to their synonyms, the shape of the config's `[rel_types]` table.
+    pub fn new(synonyms: &BTreeMap<String, Vec<String>>) -> Self {
+        let mut canonical = HashMap::new();
+        for (ty, words) in synonyms {
+            let ty = normalize_rel_type(ty);
+            for word in words { canonical.insert(normalize_rel_type(word), ty.clone()); }
+            canonical.insert(ty.clone(), ty);
+        }
+        Self { canonical }
+    }
+
+    pub fn canonicalize(&self, raw: &str) -> String {
+        let normalized = normalize_rel_type(raw);
+        self.canonical.get(&normalized).cloned().unwrap_or(normalized)
+    }
+}
+
+/// Lowercase snake_case: "Works For", "works-for" and "WorksFor" all become `works_for`.
+pub fn normalize_rel_type(raw: &str) -> String {
+    let mut out = String::new();
+    let mut prev_lower = false;
+    for c in raw.trim().chars() {
+        if !c.is_alphanumeric() {
+            if !out.is_empty() && !out.ends_with('_') { out.push('_'); }
+            prev_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && prev_lower { out.push('_'); }
+        prev_lower = c.is_lowercase() || c.is_numeric();
+        out.extend(c.to_lowercase());
+    }
+    out.trim_end_matches('_').to_string()
+}
+
+fn to_graph_items(ents: Vec<ExtractedEntity>, rels: Vec<ExtractedRelationship>, chunk: &Chunk, rel_types: &RelTypes) -> (Vec<Entity>, Vec<Relationship>) {
+    let entities: Vec<Entity> = ents.into_iter().map(|e| Entity{
+        id: String::new(),
+        name: e.name,
+        entity_type: e.entity_type,
+        description: e.description.unwrap_or_default(),
+        source_chunks: vec![chunk.id.clone()],
+        confidence: e.confidence,
+    }).collect();
+    let relationships: Vec<Relationship> = rels.into_iter().map(|r| Relationship{
+        source: r.source,
+        target: r.target,
+        rel_type: rel_types.canonicalize(&r.rel_type),
+        description: r.description.unwrap_or_default(),
+        strength: r.strength.unwrap_or(1.0),
+        source_chunks: vec![chunk.id.clone()],
+    }).collect();
+    (entities, relationships)
 }
 
+fn heuristic_extract(chunk: &Chunk) -> (Vec<Entity>, Vec<Relationship>) {
+    let mut names = collect_capitalized_terms(&chunk.text);
+    names.truncate(16);
+    let entities: Vec<Entity> = names.iter().map(|name| Entity {
+        id: String::new(),
+        name: name.clone(),
+        entity_type: "Concept".to_string(),
+        description: String::new(),
+        source_chunks: vec![chunk.id.clone()],
+        confidence: Some(HEURISTIC_CONFIDENCE),
+    }).collect();
+    (entities, Vec::new())
+}
+
+fn parse_extraction_json(text: &str) -> Option<Extraction> {
+    json_candidates(text, '{', '}').into_iter().find_map(|candidate| {
+        let v: serde_json::Value = serde_json::from_str(candidate).ok()?;
+        let ents: Vec<ExtractedEntity> = serde_json::from_value(v.get("entities")?.clone()).ok()?;
+        let rels: Vec<ExtractedRelationship> = serde_json::from_value(v.get("relationships")?.clone()).ok()?;
+        Some((ents, rels))
+    })
+}
+
+type Extraction = (Vec<ExtractedEntity>, Vec<ExtractedRelationship>);
+
+fn parse_batch_extraction_json(text: &str, expected: usize) -> Option<Vec<Extraction>> {
+    json_candidates(text, '[', ']').into_iter().find_map(|candidate| {
+        let items: Vec<serde_json::Value> = serde_json::from_str(candidate).ok()?;
+        if items.len() != expected { return None; }
+        let mut out: Vec<Option<Extraction>> = vec![None; expected];
+        for (pos, item) in items.iter().enumerate() {
+            // Trust the model's chunk number when present, otherwise fall back to array position.
+            let idx = item.get("chunk").and_then(|c| c.as_u64()).map(|c| c as usize).unwrap_or(pos);
+            let ents: Vec<ExtractedEntity> = serde_json::from_value(item.get("entities")?.clone()).ok()?;
+            let rels: Vec<ExtractedRelationship> = serde_json::from_value(item.get("relationships")?.clone()).ok()?;
+            *out.get_mut(idx)? = Some((ents, rels));
+        }
+        out.into_iter().collect()
+    })
+}
+
+/// Possible JSON values in noisy model output, most likely first: the bodies of markdown
+/// code fences, then every balanced `open`..`close` span in the raw text.
+fn json_candidates(text: &str, open: char, close: char) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("```") {
+        let after = &rest[start + 3..];
+        // Skip the info string (e.g. `json`) on the opening fence line.
+        let body_start = after.find('\n').map(|i| i + 1).unwrap_or(0);
+        let Some(len) = after[body_start..].find("```") else { break };
+        out.push(after[body_start..body_start + len].trim());
+        rest = &after[body_start + len + 3..];
+    }
+    for (i, c) in text.char_indices() {
+        if c == open {
+            if let Some(len) = balanced_len(&text[i..], open, close) { out.push(&text[i..i + len]); }
+        }
+    }
+    out
+}
+
+// Byte length of the balanced span at the start of `s`, ignoring delimiters inside JSON strings.
+fn balanced_len(s: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        if c == '"' { in_string = true; }
+        else if c == open { depth += 1; }
+        else if c == close {
+            depth = depth.checked_sub(1)?;
+            if depth == 0 { return Some(i + c.len_utf8()); }
+        }
+    }
+    None
+}
+
+// Capitalized words that usually just start a sentence rather than name something.
+const STOPWORDS: &[&str] = &[
+    "a", "about", "after", "all", "also", "an", "and", "any", "as", "at", "because", "before", "but", "by", "each", "every",
+    "for", "from", "he", "her", "here", "his", "how", "however", "i", "if", "in", "into", "is", "it", "its", "many", "most",
+    "my", "no", "not", "of", "on", "once", "one", "or", "our", "she", "since", "so", "some", "such", "than", "that", "the",
+    "their", "then", "there", "these", "they", "this", "those", "though", "thus", "to", "under", "until", "we", "what",
+    "when", "where", "whether", "which", "while", "who", "why", "with", "yes", "yet", "you", "your",
+];
+
+// Shorter names are kept only as all-caps acronyms such as "UN".
+const MIN_TERM_CHARS: usize = 3;
+
+/// Runs of capitalized words, so "United Nations" is one term. A leading stopword is dropped
+/// ("The United Nations" -> "United Nations") and punctuation splits runs ("Paris, France").
 fn collect_capitalized_terms(text: &str) -> Vec<String> {
     use std::collections::BTreeSet;
     let mut set = BTreeSet::new();
-    for token in text.split(|c: char| !c.is_alphanumeric() && c != '-' ) {
-        if token.len() > 1 && token.chars().next().unwrap().is_uppercase() {
-            set.insert(token.to_string());
+    let mut run: Vec<&str> = Vec::new();
+    let mut flush = |run: &mut Vec<&str>| {
+        let term = run.join(" ");
+        let chars = term.chars().count();
+        if chars >= MIN_TERM_CHARS || (chars > 1 && term.chars().all(char::is_uppercase)) { set.insert(term); }
+        run.clear();
+    };
+    for word in text.split_whitespace() {
+        if !run.is_empty() && word.starts_with(|c: char| !c.is_alphanumeric()) { flush(&mut run); }
+        let token = word.trim_matches(|c: char| !c.is_alphanumeric());
+        let token = token.strip_suffix("'s").or_else(|| token.strip_suffix("\u{2019}s")).unwrap_or(token);
+        let capitalized = token.chars().next().is_some_and(char::is_uppercase);
+        if capitalized && !(run.is_empty() && STOPWORDS.contains(&token.to_lowercase().as_str())) {
+            run.push(token);
+        } else if !run.is_empty() {
+            flush(&mut run);
         }
+        if !run.is_empty() && word.ends_with(|c: char| !c.is_alphanumeric()) { flush(&mut run); }
     }
+    if !run.is_empty() { flush(&mut run); }
     set.into_iter().collect()
 }
 
@@ -99,97 +328,1305 @@ fn collect_capitalized_terms(text: &str) -> Vec<String> {
 pub struct ForgeIndex {
     pub graph: KnowledgeGraph,
     pub chunks: Vec<Chunk>,
+    #[serde(default)]
+    pub communities: Vec<Community>,
+    /// Content fingerprints of the indexed files, used by incremental re-indexing.
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, FileRecord>,
+    /// Keyword statistics over `chunks` for hybrid retrieval; empty in indexes saved before it existed.
+    #[serde(default)]
+    pub bm25: Bm25Index,
+    /// Embeddings of each entity's name, type and description, made with the chunk embedder so
+    /// queries can find graph nodes directly; empty in indexes saved before they existed.
+    #[serde(default)]
+    pub entity_vectors: BTreeMap<EntityId, Vec<f32>>,
+    // Position of each chunk by id, built on the first `chunk` lookup.
+    #[serde(skip)]
+    chunk_positions: OnceLock<HashMap<String, usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileRecord {
+    /// FNV-1a hash of the file contents, hex-encoded.
+    pub hash: String,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub mtime: u64,
+    pub size: u64,
+}
+
+impl FileRecord {
+    /// Fingerprints `path`, reusing `previous` without reading the file when mtime and size match.
+    pub fn from_path(path: &Path, previous: Option<&FileRecord>) -> Result<Self> {
+        let meta = fs::metadata(path)?;
+        let mtime = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_nanos() as u64);
+        let size = meta.len();
+        if let Some(prev) = previous.filter(|p| p.mtime == mtime && p.size == size) { return Ok(prev.clone()); }
+        Ok(Self { hash: format!("{:016x}", crate::fnv1a(&fs::read(path)?)), mtime, size })
+    }
+}
+
+/// Summary numbers for an index, as printed by `forge stats`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexStats {
+    pub nodes: usize,
+    pub edges: usize,
+    pub chunks: usize,
+    pub communities: usize,
+    /// Highest-degree entities, ties broken by id.
+    pub top_entities: Vec<EntityDegree>,
+    pub entity_types: BTreeMap<String, usize>,
+    /// Mean `Relationship::strength`, or 0 without edges.
+    pub avg_strength: f32,
+    /// Edges `KnowledgeGraph::validate` flags: dangling or self-loops.
+    #[serde(default)]
+    pub invalid_edges: usize,
+    /// Connected components of the graph, isolated entities included.
+    #[serde(default)]
+    pub components: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityDegree {
+    pub id: EntityId,
+    pub name: String,
+    pub degree: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Community {
+    pub id: String,
+    pub members: Vec<EntityId>,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommunityAlg {
+    #[default]
+    LabelPropagation,
+    /// Modularity-optimizing Louvain, weighting edges by `Relationship::strength`.
+    Louvain,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum CommunityAlg { LabelPropagation }
+impl std::str::FromStr for CommunityAlg {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "label_propagation" | "labelprop" | "lpa" => Ok(CommunityAlg::LabelPropagation),
+            "louvain" => Ok(CommunityAlg::Louvain),
+            other => anyhow::bail!("unknown community algorithm '{}' (expected label_propagation or louvain)", other),
+        }
+    }
+}
 
-pub struct CommunityDetector;
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CommunityDetector {
+    pub alg: CommunityAlg,
+}
 
 impl CommunityDetector {
+    pub fn new(alg: CommunityAlg) -> Self { Self { alg } }
+
+    /// Returns communities of entity ids, largest first; members are sorted within each community.
     pub fn detect(&self, graph: &KnowledgeGraph) -> Vec<Vec<String>> {
-        // Simple label propagation over entity-id space.
-        use std::collections::HashMap;
-        let mut label: HashMap<&str, String> = graph.nodes.keys().map(|id| (id.as_str(), id.clone())).collect();
-        let mut changed = true;
-        let edges = &graph.edges;
-        let neighbors = |id: &str| -> Vec<&str> {
-            let mut v = Vec::new();
-            for e in edges {
-                if e.source == id { v.push(e.target.as_str()); }
-                else if e.target == id { v.push(e.source.as_str()); }
-            }
-            v
+        let mut communities = match self.alg {
+            CommunityAlg::LabelPropagation => label_propagation(graph),
+            CommunityAlg::Louvain => louvain(graph),
         };
-        let mut iters = 0;
-        while changed && iters < 20 {
-            changed = false;
-            iters += 1;
-            for id in graph.nodes.keys() {
-                let mut counts: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
-                for n in neighbors(id) {
-                    let l = label.get(n).map(|s| s.as_str()).unwrap_or(n);
-                    *counts.entry(l).or_default() += 1;
-                }
-                if let Some((best, _)) = counts.into_iter().max_by_key(|(_, c)| *c) {
-                    if best != label.get(id.as_str()).map(|s| s.as_str()).unwrap_or(id) {
-                        label.insert(id, best.to_string());
-                        changed = true;
-                    }
+        for c in communities.iter_mut() { c.sort(); }
+        communities.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        communities
+    }
+}
+
+fn label_propagation(graph: &KnowledgeGraph) -> Vec<Vec<String>> {
+    // Label propagation over entity-id space; neighbors vote with their edge strength. Labels are
+    // updated in place, so the visiting order shapes the result: walk nodes in sorted id order.
+    let mut ids: Vec<&String> = graph.nodes.keys().collect();
+    ids.sort();
+    let mut label: HashMap<&str, String> = ids.iter().map(|id| (id.as_str(), id.to_string())).collect();
+    let mut changed = true;
+    let edges = &graph.edges;
+    let neighbors = |id: &str| -> Vec<(&str, f32)> {
+        let mut v = Vec::new();
+        for e in edges {
+            if e.source == id { v.push((e.target.as_str(), e.strength)); }
+            else if e.target == id { v.push((e.source.as_str(), e.strength)); }
+        }
+        v
+    };
+    let mut iters = 0;
+    while changed && iters < 20 {
+        changed = false;
+        iters += 1;
+        for &id in &ids {
+            let mut weights: HashMap<&str, f32> = HashMap::new();
+            for (n, strength) in neighbors(id) {
+                let l = label.get(n).map(|s| s.as_str()).unwrap_or(n);
+                *weights.entry(l).or_default() += strength;
+            }
+            // Ties go to the lexicographically smallest label.
+            if let Some((best, _)) = weights.into_iter().max_by(|a, b| a.1.total_cmp(&b.1).then_with(|| b.0.cmp(a.0))) {
+                if best != label.get(id.as_str()).map(|s| s.as_str()).unwrap_or(id) {
+                    label.insert(id, best.to_string());
+                    changed = true;
                 }
             }
         }
-        let mut groups: std::collections::HashMap<String, Vec<String>> = std::collections::HashMap::new();
-        for (id, l) in label.into_iter() { groups.entry(l).or_default().push(id.to_string()); }
-        groups.into_values().collect()
     }
+    let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+    for (id, l) in label.into_iter() { groups.entry(l).or_default().push(id.to_string()); }
+    groups.into_values().collect()
+}
+
+fn louvain(graph: &KnowledgeGraph) -> Vec<Vec<String>> {
+    let mut ids: Vec<&String> = graph.nodes.keys().collect();
+    ids.sort();
+    let index: HashMap<&str, usize> = ids.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+
+    // Symmetric weighted adjacency; a node's self-loop weight counts both directions.
+    let mut adj: Vec<HashMap<usize, f64>> = vec![HashMap::new(); ids.len()];
+    for e in &graph.edges {
+        let (Some(&a), Some(&b)) = (index.get(e.source.as_str()), index.get(e.target.as_str())) else { continue };
+        let w = e.strength.max(0.0) as f64;
+        if a == b || w == 0.0 { continue; }
+        *adj[a].entry(b).or_default() += w;
+        *adj[b].entry(a).or_default() += w;
+    }
+
+    // membership[original node] = node in the current aggregated graph.
+    let mut membership: Vec<usize> = (0..ids.len()).collect();
+    while let Some(comm) = louvain_level(&adj) {
+        let n_comms = comm.iter().max().map_or(0, |m| m + 1);
+        for m in membership.iter_mut() { *m = comm[*m]; }
+        let mut next: Vec<HashMap<usize, f64>> = vec![HashMap::new(); n_comms];
+        for (i, row) in adj.iter().enumerate() {
+            for (&j, &w) in row { *next[comm[i]].entry(comm[j]).or_default() += w; }
+        }
+        adj = next;
+    }
+
+    let mut groups: HashMap<usize, Vec<String>> = HashMap::new();
+    for (i, c) in membership.into_iter().enumerate() { groups.entry(c).or_default().push(ids[i].clone()); }
+    groups.into_values().collect()
+}
+
+// One pass of local moves. Returns compacted community labels, or None if no node moved.
+fn louvain_level(adj: &[HashMap<usize, f64>]) -> Option<Vec<usize>> {
+    let n = adj.len();
+    let degree: Vec<f64> = adj.iter().map(|row| row.values().sum()).collect();
+    let m2: f64 = degree.iter().sum();
+    if m2 == 0.0 { return None; }
+    let mut comm: Vec<usize> = (0..n).collect();
+    let mut total: Vec<f64> = degree.clone();
+    let mut improved = false;
+    for _ in 0..100 {
+        let mut moved = false;
+        for i in 0..n {
+            let old = comm[i];
+            let mut links: HashMap<usize, f64> = HashMap::new();
+            for (&j, &w) in &adj[i] {
+                if j != i { *links.entry(comm[j]).or_default() += w; }
+            }
+            total[old] -= degree[i];
+            let gain = |c: usize, w: f64| w - total[c] * degree[i] / m2;
+            let mut best = old;
+            let mut best_gain = gain(old, links.get(&old).copied().unwrap_or(0.0));
+            let mut candidates: Vec<(usize, f64)> = links.into_iter().collect();
+            candidates.sort_by_key(|(c, _)| *c);
+            for (c, w) in candidates {
+                let g = gain(c, w);
+                if g > best_gain + 1e-12 { best = c; best_gain = g; }
+            }
+            total[best] += degree[i];
+            if best != old { comm[i] = best; moved = true; }
+        }
+        if !moved { break; }
+        improved = true;
+    }
+    if !improved { return None; }
+    let mut relabel: HashMap<usize, usize> = HashMap::new();
+    for c in comm.iter_mut() {
+        let next = relabel.len();
+        *c = *relabel.entry(*c).or_insert(next);
+    }
+    Some(comm)
 }
 
-pub struct SummaryGenerator { pub llm: LLMEngine }
+/// Tokens of context `SummaryGenerator` puts in a summary prompt unless told otherwise.
+pub const DEFAULT_SUMMARY_CONTEXT_TOKENS: usize = 2048;
+
+pub struct SummaryGenerator {
+    pub llm: LLMEngine,
+    /// Summaries `generate_all` requests at once.
+    pub workers: usize,
+    /// Tokens of entity and relationship context a summary prompt may carry; what doesn't fit
+    /// is left out. `None` includes everything.
+    pub max_context_tokens: Option<usize>,
+}
 
 impl SummaryGenerator {
-    pub fn new(llm: LLMEngine) -> Self { Self { llm } }
+    pub fn new(llm: LLMEngine) -> Self { Self { llm, workers: 4, max_context_tokens: Some(DEFAULT_SUMMARY_CONTEXT_TOKENS) } }
+
+    pub fn with_workers(mut self, workers: usize) -> Self { self.workers = workers.max(1); self }
+
+    pub fn with_context_budget(mut self, max_tokens: Option<usize>) -> Self { self.max_context_tokens = max_tokens; self }
+
     pub async fn generate(&self, community: &[String], graph: &KnowledgeGraph) -> Result<String> {
-        let names: Vec<String> = community.iter().filter_map(|id| graph.nodes.get(id).map(|e| e.name.clone())).collect();
-        let prompt = format!("Summarize the theme connecting these entities: {}", names.join(", "));
+        let prompt = self.prompt(community, graph)?;
         self.llm.generate(&prompt).await
     }
+
+    /// The prompt `generate` sends: each member's type and description, then the relationships
+    /// between members, strongest first, for as many as fit the context budget.
+    pub fn prompt(&self, community: &[String], graph: &KnowledgeGraph) -> Result<String> {
+        let mut budget = self.max_context_tokens;
+        let mut entities = String::new();
+        for entity in community.iter().filter_map(|id| graph.nodes.get(id)) {
+            let line = match entity.description.trim() {
+                "" => format!("- {} ({})\n", entity.name, entity.entity_type),
+                description => format!("- {} ({}): {}\n", entity.name, entity.entity_type, description),
+            };
+            if !spend(&self.llm, &mut budget, &line)? { break; }
+            entities.push_str(&line);
+        }
+        let members: HashSet<&String> = community.iter().collect();
+        let mut edges: Vec<&Relationship> = graph.edges.iter().filter(|r| members.contains(&r.source) && members.contains(&r.target)).collect();
+        edges.sort_by(|a, b| b.strength.total_cmp(&a.strength));
+        let mut relations = String::new();
+        for r in edges {
+            let (Some(source), Some(target)) = (graph.nodes.get(&r.source), graph.nodes.get(&r.target)) else { continue };
+            let line = match r.description.trim() {
+                "" => format!("- {} -[{}]-> {}\n", source.name, r.rel_type, target.name),
+                description => format!("- {} -[{}]-> {}: {}\n", source.name, r.rel_type, target.name, description),
+            };
+            if !spend(&self.llm, &mut budget, &line)? { break; }
+            relations.push_str(&line);
+        }
+        let mut prompt = format!("Summarize the theme connecting these entities in a few sentences.\nEntities:\n{}", entities);
+        if !relations.is_empty() { prompt.push_str(&format!("Relationships:\n{}", relations)); }
+        Ok(prompt)
+    }
+
+    /// Summarizes every community, up to `workers` at a time; summaries come back in input order.
+    pub async fn generate_all(&self, communities: &[Vec<String>], graph: &KnowledgeGraph) -> Result<Vec<String>> {
+        futures::stream::iter(communities)
+            .map(|community| self.generate(community, graph))
+            .buffered(self.workers.max(1))
+            .try_collect()
+            .await
+    }
+}
+
+/// Detects communities in `graph` and summarizes each one.
+pub async fn build_communities(graph: &KnowledgeGraph, detector: &CommunityDetector, summarizer: &SummaryGenerator) -> Result<Vec<Community>> {
+    let detected = detector.detect(graph);
+    let summaries = summarizer.generate_all(&detected, graph).await?;
+    Ok(detected
+        .into_iter()
+        .zip(summaries)
+        .enumerate()
+        .map(|(i, (members, summary))| Community { id: format!("community-{}", i), members, summary })
+        .collect())
 }
 
 impl ForgeIndex {
+    /// Writes the index in the format its extension names: bincode for `.bin`, otherwise JSON
+    /// (see `save_json`).
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if is_bincode(path) { self.save_bincode(path) } else { self.save_json(path) }
+    }
+
+    /// Reads an index written by `save`, choosing the format by extension.
+    pub fn load(path: &Path) -> Result<Self> {
+        if is_bincode(path) { Self::load_bincode(path) } else { Self::load_json(path) }
+    }
+
+    /// Writes the index in bincode: smaller and much faster to load than JSON, but not readable
+    /// and tied to this version's field layout.
+    pub fn save_bincode(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(fs::File::create(path).with_context(|| format!("creating {}", path.display()))?);
+        bincode::serialize_into(&mut file, self)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    pub fn load_bincode(path: &Path) -> Result<Self> {
+        use bincode::Options;
+        let file = fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        // `save_bincode`'s encoding, limited to the file's size so a corrupt length prefix fails
+        // instead of allocating whatever it claims.
+        let options = bincode::DefaultOptions::new().with_fixint_encoding().allow_trailing_bytes().with_limit(file.metadata()?.len());
+        options.deserialize_from(BufReader::new(file)).with_context(|| format!("reading bincode index {}", path.display()))
+    }
+
+    /// Writes the index as pretty JSON, or gzip-compressed JSON when `path` ends in `.gz`.
+    /// The JSON is streamed to the file rather than built in memory first.
     pub fn save_json(&self, path: &Path) -> Result<()> {
-        let data = serde_json::to_string_pretty(self)?;
-        fs::write(path, data)?;
+        let file = BufWriter::new(fs::File::create(path).with_context(|| format!("creating {}", path.display()))?);
+        if is_gzip(path) {
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            self.write_json(&mut encoder, false)?;
+            encoder.finish()?.flush()?;
+        } else {
+            let mut file = file;
+            self.write_json(&mut file, true)?;
+            file.flush()?;
+        }
         Ok(())
     }
 
+    /// Reads an index written by `save_json`, decompressing it when `path` ends in `.gz`.
     pub fn load_json(path: &Path) -> Result<Self> {
-        let data = fs::read_to_string(path)?;
-        let idx: ForgeIndex = serde_json::from_str(&data)?;
-        Ok(idx)
+        let file = BufReader::new(fs::File::open(path).with_context(|| format!("opening {}", path.display()))?);
+        if is_gzip(path) { Self::read_json(BufReader::new(GzDecoder::new(file))) } else { Self::read_json(file) }
+    }
+
+    /// Serializes the index straight into `writer`.
+    pub fn write_json(&self, writer: impl Write, pretty: bool) -> Result<()> {
+        if pretty { serde_json::to_writer_pretty(writer, self)? } else { serde_json::to_writer(writer, self)? }
+        Ok(())
+    }
+
+    /// Deserializes an index straight from `reader`; pass a buffered reader.
+    pub fn read_json(reader: impl Read) -> Result<Self> { Ok(serde_json::from_reader(reader)?) }
+
+    /// The chunk with this id. Lookups go through an id map built on first use; chunks changed
+    /// since then are still found, by a scan.
+    pub fn chunk(&self, id: &str) -> Option<&Chunk> {
+        let positions = self.chunk_positions.get_or_init(|| self.chunks.iter().enumerate().map(|(i, c)| (c.id.clone(), i)).collect());
+        positions
+            .get(id)
+            .and_then(|&i| self.chunks.get(i))
+            .filter(|c| c.id == id)
+            .or_else(|| self.chunks.iter().find(|c| c.id == id))
+    }
+
+    /// The chunks `id` was extracted from, in the entity's `source_chunks` order; ids no longer
+    /// in the index are skipped.
+    pub fn chunks_for_entity(&self, id: &EntityId) -> Vec<&Chunk> {
+        let Some(entity) = self.graph.nodes.get(id) else { return Vec::new() };
+        entity.source_chunks.iter().filter_map(|chunk_id| self.chunk(chunk_id)).collect()
+    }
+
+    /// Drops chunks whose text repeats an earlier chunk's, ignoring case and whitespace, and points
+    /// entity and relationship `source_chunks` at the kept chunk. Returns each removed id with the
+    /// id that replaced it, so vectors stored for the removed chunks can be dropped too.
+    pub fn dedupe_chunks(&mut self) -> BTreeMap<String, String> {
+        let mut first: HashMap<String, String> = HashMap::new();
+        let mut replaced = BTreeMap::new();
+        self.chunks.retain(|c| {
+            let key = c.text.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+            match first.get(&key) {
+                Some(kept) => {
+                    replaced.insert(c.id.clone(), kept.clone());
+                    false
+                }
+                None => {
+                    first.insert(key, c.id.clone());
+                    true
+                }
+            }
+        });
+        if replaced.is_empty() { return replaced; }
+        let rewire = |ids: &mut Vec<String>| {
+            for id in ids.iter_mut() {
+                if let Some(kept) = replaced.get(id) { *id = kept.clone(); }
+            }
+            let mut seen = HashSet::new();
+            ids.retain(|id| seen.insert(id.clone()));
+        };
+        for entity in self.graph.nodes.values_mut() { rewire(&mut entity.source_chunks); }
+        for rel in &mut self.graph.edges { rewire(&mut rel.source_chunks); }
+        for id in replaced.keys() { self.bm25.remove(id); }
+        self.chunk_positions = OnceLock::new();
+        replaced
+    }
+
+    /// Communities as a JSON array of `{id, members: [{id, name}], summary}`; the summary is empty
+    /// for communities that weren't summarized, and members no longer in the graph are left out.
+    pub fn export_communities(&self) -> String {
+        let communities: Vec<serde_json::Value> = self
+            .communities
+            .iter()
+            .map(|c| {
+                let members: Vec<serde_json::Value> = c
+                    .members
+                    .iter()
+                    .filter_map(|id| self.graph.nodes.get(id))
+                    .map(|e| serde_json::json!({ "id": e.id, "name": e.name }))
+                    .collect();
+                serde_json::json!({ "id": c.id, "members": members, "summary": c.summary })
+            })
+            .collect();
+        serde_json::to_string_pretty(&communities).expect("JSON values always serialize")
+    }
+
+    pub fn stats(&self) -> IndexStats {
+        let mut top_entities: Vec<EntityDegree> = self
+            .graph
+            .degrees()
+            .into_iter()
+            .map(|(id, degree)| EntityDegree { id: id.clone(), name: self.graph.nodes[id].name.clone(), degree })
+            .collect();
+        top_entities.sort_by(|a, b| b.degree.cmp(&a.degree).then_with(|| a.id.cmp(&b.id)));
+        top_entities.truncate(10);
+        let mut entity_types = BTreeMap::new();
+        for entity in self.graph.nodes.values() { *entity_types.entry(entity.entity_type.clone()).or_insert(0) += 1; }
+        let edges = self.graph.edges.len();
+        let avg_strength = if edges == 0 { 0.0 } else { self.graph.edges.iter().map(|e| e.strength).sum::<f32>() / edges as f32 };
+        let mut invalid: Vec<usize> = self.graph.validate().iter().map(|issue| match issue { GraphIssue::DanglingEdge { edge, .. } | GraphIssue::SelfLoop { edge, .. } => *edge }).collect();
+        invalid.dedup();
+        IndexStats { nodes: self.graph.nodes.len(), edges, chunks: self.chunks.len(), communities: self.communities.len(), top_entities, entity_types, avg_strength, invalid_edges: invalid.len(), components: self.graph.connected_components().len() }
+    }
+
+    /// Folds `other` into this index. Chunks are unioned by id (a colliding id with different
+    /// text is renamed), entities go through `add_entity` so same-named ones collapse, and edges
+    /// through `add_relationship`. Communities and entity vectors are cleared since they no longer
+    /// cover the graph.
+    pub fn merge(&mut self, other: ForgeIndex) {
+        let mut chunk_ids: HashMap<String, String> = HashMap::new();
+        let existing: HashMap<String, usize> = self.chunks.iter().enumerate().map(|(i, c)| (c.id.clone(), i)).collect();
+        for mut chunk in other.chunks {
+            match existing.get(&chunk.id) {
+                Some(&i) if self.chunks[i].text == chunk.text => {}
+                Some(_) => {
+                    let mut n = 1;
+                    let mut id = format!("{}-merged-{}", chunk.id, n);
+                    while existing.contains_key(&id) || chunk_ids.values().any(|v| v == &id) {
+                        n += 1;
+                        id = format!("{}-merged-{}", chunk.id, n);
+                    }
+                    chunk_ids.insert(chunk.id.clone(), id.clone());
+                    chunk.id = id;
+                    self.chunks.push(chunk);
+                }
+                None => self.chunks.push(chunk),
+            }
+        }
+        let rename_chunks = |ids: Vec<String>| -> Vec<String> { ids.into_iter().map(|c| chunk_ids.get(&c).cloned().unwrap_or(c)).collect() };
+
+        let mut entity_ids: HashMap<EntityId, EntityId> = HashMap::new();
+        let mut others: Vec<Entity> = other.graph.nodes.into_values().collect();
+        others.sort_by(|a, b| a.id.cmp(&b.id));
+        for mut entity in others {
+            let old_id = entity.id.clone();
+            entity.source_chunks = rename_chunks(entity.source_chunks);
+            let sources = entity.source_chunks.clone();
+            let new_id = self.graph.add_entity(entity);
+            // `add_entity` returns the existing node for a known name; carry the provenance over.
+            if let Some(node) = self.graph.nodes.get_mut(&new_id) {
+                for chunk in sources {
+                    if !node.source_chunks.contains(&chunk) { node.source_chunks.push(chunk); }
+                }
+            }
+            entity_ids.insert(old_id, new_id);
+        }
+        for mut rel in other.graph.edges {
+            if let Some(id) = entity_ids.get(&rel.source) { rel.source = id.clone(); }
+            if let Some(id) = entity_ids.get(&rel.target) { rel.target = id.clone(); }
+            rel.source_chunks = rename_chunks(rel.source_chunks);
+            self.graph.add_relationship(rel);
+        }
+        self.graph.directed |= other.graph.directed;
+        self.files.extend(other.files);
+        self.communities.clear();
+        self.entity_vectors.clear();
+        self.rebuild_bm25();
     }
+
+    pub fn rebuild_bm25(&mut self) {
+        self.bm25 = Bm25Index::build(self.chunks.iter().map(|c| (c.id.as_str(), c.text.as_str())));
+    }
+
+    /// Sidecar file holding the chunk vectors next to an index, e.g. `forge_index.vectors.json`.
+    pub fn vectors_path(index_path: &Path) -> PathBuf {
+        index_path.with_extension("vectors.json")
+    }
+
+    /// Embeds every chunk into a new store; `embedding_dim` defaults to the store's own.
+    pub async fn embed_chunks(&self, embedding_dim: Option<usize>) -> Result<InMemoryVectorStore> {
+        let mut vs: InMemoryVectorStore = embedding_dim.map(InMemoryVectorStore::new).unwrap_or_default();
+        let vectors = self.chunks.iter().map(|c| Ok((c.id.clone(), vs.embedder().try_embed(&c.text).with_context(|| format!("embedding chunk {}", c.id))?))).collect::<Result<_>>()?;
+        vs.upsert_batch(vectors).await?;
+        for chunk in &self.chunks { vs.set_metadata(chunk.id.clone(), chunk.metadata()); }
+        Ok(vs)
+    }
+
+    /// Replaces `entity_vectors` with fresh embeddings of every entity; use the embedder the
+    /// chunks were embedded with, since queries are embedded once for both.
+    pub fn embed_entities(&mut self, embedder: &dyn Embedder) {
+        self.entity_vectors = self.graph.nodes.values().map(|e| (e.id.clone(), embedder.embed(&entity_text(e)))).collect();
+    }
+
+    /// A query engine over this index with hybrid retrieval, given the chunk vectors.
+    pub fn into_query_engine(mut self, llm: LLMEngine, vectors: InMemoryVectorStore) -> QueryEngine<InMemoryVectorStore> {
+        if self.bm25.len() != self.chunks.len() { self.rebuild_bm25(); }
+        QueryEngine::new(self.graph, self.chunks, llm, vectors)
+            .with_communities(self.communities)
+            .with_hybrid(HybridRetriever::new(self.bm25))
+            .with_entity_vectors(self.entity_vectors)
+    }
+
+    /// Answers `query` by local search with default settings, embedding the chunks on the fly.
+    pub async fn answer(&self, query: &str, llm: &LLMEngine) -> Result<String> {
+        let vectors = self.embed_chunks(None).await?;
+        self.clone().into_query_engine(llm.clone(), vectors).query(query).await
+    }
+}
+
+// What an entity's vector is made from.
+fn entity_text(entity: &Entity) -> String {
+    if entity.description.is_empty() { format!("{} ({})", entity.name, entity.entity_type) } else { format!("{} ({}): {}", entity.name, entity.entity_type, entity.description) }
+}
+
+fn is_bincode(path: &Path) -> bool { path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bin")) }
+
+fn is_gzip(path: &Path) -> bool { path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gz")) }
+
+/// Second-stage scorer that reorders retrieved `(id, text)` candidates by relevance to the query.
+#[async_trait::async_trait]
+pub trait Reranker: Send + Sync + std::fmt::Debug {
+    /// Returns every candidate id with its score, most relevant first.
+    async fn rerank(&self, query: &str, candidates: Vec<(String, String)>) -> Vec<(String, f32)>;
+}
+
+/// Asks the LLM to rate each candidate 0-10; one call per candidate, so enable it deliberately.
+#[derive(Debug, Clone)]
+pub struct LlmReranker { pub llm: LLMEngine }
+
+impl LlmReranker {
+    pub fn new(llm: LLMEngine) -> Self { Self { llm } }
+}
+
+#[async_trait::async_trait]
+impl Reranker for LlmReranker {
+    async fn rerank(&self, query: &str, candidates: Vec<(String, String)>) -> Vec<(String, f32)> {
+        let mut scored = Vec::with_capacity(candidates.len());
+        for (id, text) in candidates {
+            let prompt = format!(
+                "Rate how relevant the passage is to the query on a scale from 0 (unrelated) to 10 (answers it). \
+                Reply with only the number.\nQuery: {}\nPassage:\n{}",
+                query, text
+            );
+            // A failed call or an unusable reply ranks the candidate last rather than failing the query.
+            let score = self
+                .llm
+                .generate(&prompt)
+                .await
+                .ok()
+                .and_then(|reply| parse_score(&reply))
+                .filter(|s| (0.0..=10.0).contains(s))
+                .unwrap_or(0.0);
+            scored.push((id, score));
+        }
+        // Stable sort keeps retrieval order among equal scores.
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored
+    }
+}
+
+// First number in a model reply, e.g. `8`, `7.5/10` or `Score: 9`.
+fn parse_score(reply: &str) -> Option<f32> {
+    let start = reply.find(|c: char| c.is_ascii_digit())?;
+    let len = reply[start..].find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(reply.len() - start);
+    reply[start..start + len].trim_end_matches('.').parse().ok()
+}
+
+// Deducts `text` from a remaining token budget if it fits; no budget means everything fits.
+fn spend(llm: &LLMEngine, budget: &mut Option<usize>, text: &str) -> Result<bool> {
+    let Some(left) = budget else { return Ok(true) };
+    let tokens = llm.count_tokens(text)?;
+    if tokens > *left { return Ok(false); }
+    *left -= tokens;
+    Ok(true)
+}
+
+/// A retrieved chunk that was placed in the prompt, with its retrieval (or rerank) score.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    pub id: String,
+    pub score: f32,
+    pub source_path: Option<PathBuf>,
+    pub page: Option<usize>,
+}
+
+/// An answer plus the chunks and entities it was grounded in, for showing citations.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryResult {
+    pub answer: String,
+    /// In retrieval order; `[n]` markers in the prompt refer to `chunks[n - 1]`.
+    pub chunks: Vec<ChunkRef>,
+    pub entities: Vec<EntityId>,
 }
 
 #[derive(Debug, Clone)]
 pub struct QueryEngine<VS: VectorStore> {
     pub graph: KnowledgeGraph,
+    pub chunks: HashMap<String, Chunk>,
     pub llm: LLMEngine,
     pub vector_store: VS,
+    pub top_k: usize,
+    /// Graph hops around matched entities included in the prompt.
+    pub hops: usize,
+    pub communities: Vec<Community>,
+    /// Fuses keyword and vector rankings when set; plain vector search otherwise.
+    pub retriever: Option<HybridRetriever>,
+    /// When set, `rerank_pool` candidates are retrieved and the reranker picks the final `top_k`.
+    pub reranker: Option<Arc<dyn Reranker>>,
+    pub rerank_pool: usize,
+    pub prompts: Prompts,
+    /// Context window to fit the answer prompt into; lower-ranked chunks, entities and relationships
+    /// that don't fit are dropped.
+    pub max_context_tokens: Option<usize>,
+    /// Tokens of `max_context_tokens` kept free for the generated answer.
+    pub answer_reserve: usize,
+    /// Entity embeddings (see `ForgeIndex::entity_vectors`); the `entity_top_k` nearest to the
+    /// query seed the graph context alongside entities found by name and in retrieved chunks.
+    pub entity_vectors: BTreeMap<EntityId, Vec<f32>>,
+    pub entity_top_k: usize,
+    /// Restricts retrieval to chunks from this file, or from files under this directory.
+    pub source: Option<PathBuf>,
 }
 
 impl<VS: VectorStore> QueryEngine<VS> {
-    pub fn new(graph: KnowledgeGraph, llm: LLMEngine, vector_store: VS) -> Self {
-        Self { graph, llm, vector_store }
-        }
+    pub fn new(graph: KnowledgeGraph, chunks: Vec<Chunk>, llm: LLMEngine, vector_store: VS) -> Self {
+        let chunks = chunks.into_iter().map(|c| (c.id.clone(), c)).collect();
+        Self { graph, chunks, llm, vector_store, top_k: 5, hops: 1, communities: Vec::new(), retriever: None, reranker: None, rerank_pool: 20, prompts: Prompts::default(), max_context_tokens: None, answer_reserve: 256, entity_vectors: BTreeMap::new(), entity_top_k: 3, source: None }
+    }
+
+    pub fn with_top_k(mut self, k: usize) -> Self { self.top_k = k; self }
+
+    pub fn with_hops(mut self, hops: usize) -> Self { self.hops = hops; self }
+
+    pub fn with_communities(mut self, communities: Vec<Community>) -> Self { self.communities = communities; self }
+
+    pub fn with_hybrid(mut self, retriever: HybridRetriever) -> Self { self.retriever = Some(retriever); self }
+
+    pub fn with_prompts(mut self, prompts: Prompts) -> Self { self.prompts = prompts; self }
+
+    pub fn with_context_budget(mut self, max_tokens: usize) -> Self { self.max_context_tokens = Some(max_tokens); self }
+
+    pub fn with_answer_reserve(mut self, tokens: usize) -> Self { self.answer_reserve = tokens; self }
+
+    pub fn with_entity_vectors(mut self, vectors: BTreeMap<EntityId, Vec<f32>>) -> Self { self.entity_vectors = vectors; self }
+
+    /// How many entities nearest the query in embedding space seed the graph context; 0 disables it.
+    pub fn with_entity_top_k(mut self, k: usize) -> Self { self.entity_top_k = k; self }
+
+    pub fn with_source(mut self, source: impl Into<PathBuf>) -> Self { self.source = Some(source.into()); self }
+
+    pub fn with_reranker(mut self, reranker: Arc<dyn Reranker>, pool: usize) -> Self {
+        self.reranker = Some(reranker);
+        self.rerank_pool = pool;
+        self
+    }
 
     pub async fn query(&self, query: &str) -> Result<String> {
-        // Stub: synthesize answer using available context sizes.
-        let entity_count = self.graph.nodes.len();
-        let edge_count = self.graph.edges.len();
+        Ok(self.query_with_sources(query).await?.answer)
+    }
+
+    /// Like `query`, but also reports which chunks and entities went into the prompt.
+    pub async fn query_with_sources(&self, query: &str) -> Result<QueryResult> {
+        // Retrieve the top-k chunks closest to the query and ground the prompt in them.
+        let k = if self.reranker.is_some() { self.rerank_pool.max(self.top_k) } else { self.top_k };
+        let mut hits = match (&self.retriever, &self.source) {
+            (Some(retriever), None) => retriever.retrieve(&self.vector_store, query, k).await,
+            (None, None) => self.vector_store.search(&self.vector_store.embedder().embed(query), k).await,
+            (retriever, Some(source)) => {
+                let chunks = &self.chunks;
+                let keep = |id: &str| chunks.get(id).and_then(|c| c.source_path.as_deref()).is_some_and(|path| path.starts_with(source));
+                match retriever {
+                    Some(retriever) => retriever.retrieve_where(&self.vector_store, query, k, &keep).await,
+                    None => self.vector_store.search_where(&self.vector_store.embedder().embed(query), k, &keep).await,
+                }
+            }
+        };
+        if let Some(reranker) = &self.reranker {
+            let candidates = hits.iter().filter_map(|(id, _)| self.chunks.get(id).map(|c| (id.clone(), c.text.clone()))).collect();
+            hits = reranker.rerank(query, candidates).await;
+            hits.truncate(self.top_k);
+        }
+        let retrieved: Vec<(&Chunk, f32)> = hits.iter().filter_map(|(id, score)| self.chunks.get(id).map(|c| (c, *score))).collect();
+
+        // The instructions, query and answer come off the top of the budget; what's left is filled
+        // greedily in priority order: chunks by score, then entities, then relationships.
+        let mut budget = match self.max_context_tokens {
+            Some(max) => {
+                let frame = self.answer_messages(render(&self.prompts.answer, &[("query", query)]));
+                Some(max.saturating_sub(self.llm.count_tokens(&self.llm.chat_template().render(&frame))? + self.answer_reserve))
+            }
+            None => None,
+        };
+        let mut context = String::new();
+        let mut included = Vec::new();
+        for (chunk, score) in retrieved {
+            let entry = format!("[{}] ({})\n{}\n\n", included.len() + 1, chunk.id, chunk.text);
+            if !spend(&self.llm, &mut budget, &entry)? { break; }
+            context.push_str(&entry);
+            included.push((chunk, score));
+        }
+
+        // Seed entities: those named in the query, closely matching one of its capitalized terms or
+        // its embedding, or mentioned in the included chunks.
+        let query_lc = query.to_lowercase();
+        let mut seeds: Vec<EntityId> = self
+            .graph
+            .nodes
+            .values()
+            .filter(|e| query_lc.contains(&e.name.to_lowercase()) || e.source_chunks.iter().any(|c| included.iter().any(|(r, _)| &r.id == c)))
+            .map(|e| e.id.clone())
+            .collect();
+        for term in collect_capitalized_terms(query) {
+            seeds.extend(self.graph.search_entities(&term, 1).into_iter().map(|(e, _)| e.id.clone()));
+        }
+        seeds.extend(self.nearest_entities(query));
+        seeds.sort_unstable();
+        seeds.dedup();
+        let sub = self.graph.subgraph(&seeds, self.hops);
+
+        // Seeds before their neighbours, then by name.
+        let mut candidates: Vec<&Entity> = sub.nodes.values().collect();
+        candidates.sort_by(|a, b| seeds.binary_search(&b.id).is_ok().cmp(&seeds.binary_search(&a.id).is_ok()).then_with(|| a.name.cmp(&b.name)).then_with(|| a.id.cmp(&b.id)));
+        let mut entities = Vec::new();
+        let mut entity_names: Vec<&str> = Vec::new();
+        for entity in candidates {
+            if !entity_names.contains(&entity.name.as_str()) {
+                if !spend(&self.llm, &mut budget, &format!("{}, ", entity.name))? { break; }
+                entity_names.push(&entity.name);
+            }
+            entities.push(entity.id.clone());
+        }
+        let mut edges: Vec<&Relationship> = sub.edges.iter().collect();
+        edges.sort_by(|a, b| b.strength.total_cmp(&a.strength));
+        let mut relations: Vec<String> = Vec::new();
+        for r in edges {
+            let line = format!("{} -[{}]-> {}", sub.nodes[&r.source].name, r.rel_type, sub.nodes[&r.target].name);
+            if relations.contains(&line) { continue; }
+            if !spend(&self.llm, &mut budget, &format!("{}\n", line))? { break; }
+            relations.push(line);
+        }
+
+        let prompt = render(
+            &self.prompts.answer,
+            &[
+                ("query", query),
+                ("entities", &if entity_names.is_empty() { "(none)".to_string() } else { entity_names.join(", ") }),
+                ("entity_count", &entity_names.len().to_string()),
+                ("relationships", &if relations.is_empty() { "(none)".to_string() } else { relations.join("\n") }),
+                ("context", &context),
+            ],
+        );
+        let answer = self.llm.generate_chat(&self.answer_messages(prompt)).await?;
+        let chunks = included
+            .iter()
+            .map(|(c, score)| ChunkRef { id: c.id.clone(), score: *score, source_path: c.source_path.clone(), page: c.page })
+            .collect();
+        entities.sort_unstable();
+        Ok(QueryResult { answer, chunks, entities })
+    }
+
+    // The `entity_top_k` entities whose vectors are most similar to the query's.
+    fn nearest_entities(&self, query: &str) -> Vec<EntityId> {
+        if self.entity_top_k == 0 || self.entity_vectors.is_empty() { return Vec::new(); }
+        let q = self.vector_store.embedder().embed(query);
+        let mut scored: Vec<(&EntityId, f32)> = self
+            .entity_vectors
+            .iter()
+            .filter(|(id, v)| v.len() == q.len() && self.graph.nodes.contains_key(*id))
+            .map(|(id, v)| (id, cosine_similarity(&q, v)))
+            .collect();
+        // Stable sort over the id-ordered map keeps ties deterministic.
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.into_iter().take(self.entity_top_k).map(|(id, _)| id.clone()).collect()
+    }
+
+    fn answer_messages(&self, prompt: String) -> [ChatMessage; 2] {
+        [ChatMessage::system(&self.prompts.answer_system), ChatMessage::user(prompt)]
+    }
+
+    /// Global search: map-reduce over community summaries rather than raw chunks.
+    ///
+    /// Costs one LLM call per community with a non-empty summary plus one reduce call, so
+    /// token usage grows with the number of communities rather than with `top_k`.
+    pub async fn query_global(&self, query: &str) -> Result<String> {
+        let mut partials = Vec::new();
+        for community in self.communities.iter().filter(|c| !c.summary.trim().is_empty()) {
+            let prompt = format!(
+                "Using only this community summary, give a partial answer to the user query. \
+                If the summary is irrelevant, reply with 'N/A'.\n\
+                Community {}:\n{}\n\nQuery: '{}'",
+                community.id, community.summary, query
+            );
+            partials.push((community.id.as_str(), self.llm.generate(&prompt).await?));
+        }
+        if partials.is_empty() {
+            anyhow::bail!("index has no community summaries; re-index to enable global search");
+        }
+        let mut combined = String::new();
+        for (id, partial) in &partials {
+            combined.push_str(&format!("[{}]\n{}\n\n", id, partial.trim()));
+        }
         let prompt = format!(
-            "Given a knowledge graph with {} entities and {} relationships, answer the user query: '{}'\nBe concise.",
-            entity_count, edge_count, query
+            "Combine these partial answers into one final answer to the user query, ignoring any marked 'N/A'.\n\n\
+            {}Query: '{}'\nBe concise.",
+            combined, query
         );
         self.llm.generate(&prompt).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &str, text: &str) -> Chunk {
+        Chunk { id: id.into(), text: text.into(), token_estimate: text.split_whitespace().count(), ..Chunk::default() }
+    }
+
+    async fn engine(chunks: Vec<Chunk>) -> QueryEngine<InMemoryVectorStore> {
+        let mut vs = InMemoryVectorStore::default();
+        for c in &chunks { let v = vs.embedder().embed(&c.text); vs.upsert(c.id.clone(), v).await.unwrap(); }
+        QueryEngine::new(KnowledgeGraph::default(), chunks, LLMEngine::new(), vs)
+    }
+
+    #[tokio::test]
+    async fn query_grounds_prompt_in_closest_chunk() {
+        let engine = engine(vec![chunk("rust", "the rust compiler checks borrows"), chunk("zoo", "zebras yawn in the zoo")]).await.with_top_k(1);
+        let result = engine.query_with_sources("how does the rust compiler check borrows").await.unwrap();
+        assert_eq!(result.chunks.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(), ["rust"]);
+    }
+
+    #[tokio::test]
+    async fn cited_chunks_are_the_ones_vector_search_selected() {
+        let texts = ["borrow checking in the rust compiler", "rust compiler error messages", "zebras at the zoo", "baking sourdough bread"];
+        let mut engine = engine(texts.iter().enumerate().map(|(i, t)| chunk(&format!("c{}", i), t)).collect()).await.with_top_k(2);
+        let (llm, prompts) = recording_llm(|_| "answer".to_string());
+        engine.llm = llm;
+        let query = "what does the rust compiler check";
+        let selected = engine.vector_store.search(&engine.vector_store.embedder().embed(query), 2).await;
+
+        let result = engine.query_with_sources(query).await.unwrap();
+        let cited: Vec<(String, f32)> = result.chunks.iter().map(|c| (c.id.clone(), c.score)).collect();
+        assert_eq!(cited, selected);
+        let prompt = prompts.lock().unwrap().concat();
+        for (n, (id, _)) in selected.iter().enumerate() { assert!(prompt.contains(&format!("[{}] ({})", n + 1, id))); }
+    }
+
+    // Ranks candidates by text length, shortest first, remembering how many it was offered.
+    #[derive(Debug, Default)]
+    struct ShortestFirst { offered: std::sync::Mutex<usize> }
+
+    #[async_trait::async_trait]
+    impl Reranker for ShortestFirst {
+        async fn rerank(&self, _query: &str, candidates: Vec<(String, String)>) -> Vec<(String, f32)> {
+            *self.offered.lock().unwrap() = candidates.len();
+            let mut scored: Vec<(String, f32)> = candidates.into_iter().map(|(id, text)| (id, 1.0 / text.len() as f32)).collect();
+            scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+            scored
+        }
+    }
+
+    #[tokio::test]
+    async fn reranker_picks_the_final_chunks() {
+        let texts = ["the rust compiler checks borrows before codegen", "rust compiler", "the rust compiler", "a rust compiler pass"];
+        let reranker = Arc::new(ShortestFirst::default());
+        let engine = engine(texts.iter().enumerate().map(|(i, t)| chunk(&format!("c{}", i), t)).collect()).await.with_top_k(2).with_reranker(reranker.clone(), 4);
+        let result = engine.query_with_sources("how does the rust compiler check borrows").await.unwrap();
+        assert_eq!(*reranker.offered.lock().unwrap(), 4);
+        let picked: Vec<(&str, f32)> = result.chunks.iter().map(|c| (c.id.as_str(), c.score)).collect();
+        assert_eq!(picked, [("c1", 1.0 / 13.0), ("c2", 1.0 / 17.0)]);
+    }
+
+    #[tokio::test]
+    async fn extraction_calls_stay_within_max_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let (in_flight, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (now, max) = (in_flight.clone(), peak.clone());
+        let llm = LLMEngine::mock(move |_| {
+            let (now, max) = (now.clone(), max.clone());
+            async move {
+                max.fetch_max(now.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                now.fetch_sub(1, Ordering::SeqCst);
+                Ok(r#"{"entities": [], "relationships": []}"#.to_string())
+            }
+        });
+        let chunks: Vec<Chunk> = (0..12).map(|i| chunk(&format!("c{}", i), "text")).collect();
+        let extractor = EntityExtractor::new(llm).with_batch_size(1).with_workers(8).with_max_concurrency(2);
+        assert_eq!(extractor.extract_batch(&chunks).await.unwrap().len(), 12);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn tiny_budget_keeps_only_the_top_chunk() {
+        let texts = ["the rust compiler checks borrows", "rust compiler error messages", "rust compiler passes"];
+        let engine = engine(texts.iter().enumerate().map(|(i, t)| chunk(&format!("c{}", i), t)).collect()).await.with_top_k(3).with_answer_reserve(64);
+        let query = "how does the rust compiler check borrows";
+        let unbounded = engine.query_with_sources(query).await.unwrap();
+        assert_eq!(unbounded.chunks.len(), 3);
+
+        // Room for the prompt frame, the answer reserve and the first chunk's entry, nothing more.
+        let top = &engine.chunks[&unbounded.chunks[0].id];
+        let frame = engine.answer_messages(render(&engine.prompts.answer, &[("query", query)]));
+        let frame = engine.llm.count_tokens(&engine.llm.chat_template().render(&frame)).unwrap();
+        let entry = engine.llm.count_tokens(&format!("[1] ({})\n{}\n\n", top.id, top.text)).unwrap();
+        let engine = engine.with_context_budget(frame + 64 + entry);
+        let result = engine.query_with_sources(query).await.unwrap();
+        assert_eq!(result.chunks, unbounded.chunks[..1]);
+    }
+
+    #[test]
+    fn heuristic_skips_sentence_stopwords_and_keeps_full_names() {
+        let text = "The United Nations met in New York. However, Marie Curie's lab in Paris, France stayed shut. It rained.";
+        assert_eq!(collect_capitalized_terms(text), ["France", "Marie Curie", "New York", "Paris", "United Nations"]);
+        let (entities, _) = heuristic_extract(&chunk("c0", text));
+        assert!(entities.iter().all(|e| !["The", "However", "It"].contains(&e.name.as_str())));
+        assert!(entities.iter().any(|e| e.name == "Marie Curie" && e.source_chunks == ["c0"]));
+    }
+
+    fn graph_of(edges: &[(&str, &str, f32)]) -> KnowledgeGraph {
+        let mut g = KnowledgeGraph::default();
+        for &(a, b, strength) in edges {
+            for id in [a, b] {
+                g.nodes.entry(id.into()).or_insert_with(|| Entity { id: id.into(), name: id.into(), entity_type: "thing".into(), description: String::new(), source_chunks: Vec::new(), confidence: None });
+            }
+            g.edges.push(Relationship { source: a.into(), target: b.into(), rel_type: "related".into(), description: String::new(), strength, source_chunks: Vec::new() });
+        }
+        g
+    }
+
+    // Two 4-cliques joined by a single d-w bridge.
+    const TWO_CLUSTERS: [(&str, &str, f32); 13] = [
+        ("a", "b", 1.0), ("a", "c", 1.0), ("a", "d", 1.0), ("b", "c", 1.0), ("b", "d", 1.0), ("c", "d", 1.0), ("d", "w", 1.0),
+        ("w", "x", 1.0), ("w", "y", 1.0), ("w", "z", 1.0), ("x", "y", 1.0), ("x", "z", 1.0), ("y", "z", 1.0),
+    ];
+
+    #[test]
+    fn louvain_separates_clusters_label_propagation_merges() {
+        // Visited in id order, `a` breaks its tie towards `b`, the a-d clique follows, and `w` then
+        // ties `b` against its own clique's labels and takes the smaller, dragging x, y, z along.
+        let g = graph_of(&TWO_CLUSTERS);
+        assert_eq!(CommunityDetector::new(CommunityAlg::Louvain).detect(&g), [["a", "b", "c", "d"], ["w", "x", "y", "z"]]);
+        assert_eq!(CommunityDetector::new(CommunityAlg::LabelPropagation).detect(&g), [["a", "b", "c", "d", "w", "x", "y", "z"]]);
+    }
+
+    #[test]
+    fn weak_edges_do_not_outvote_strong_ties() {
+        // `m` has two strong ties into the a-b-c triangle and three weak ones into x-y-z-v: a
+        // one-vote-per-neighbor count would pull it across.
+        let edges = [
+            ("a", "b", 1.0), ("b", "c", 1.0), ("a", "c", 1.0), ("m", "a", 3.0), ("m", "b", 3.0),
+            ("x", "y", 1.0), ("y", "z", 1.0), ("x", "z", 1.0), ("v", "x", 1.0), ("v", "y", 1.0), ("v", "z", 1.0),
+            ("m", "x", 0.1), ("m", "y", 0.1), ("m", "z", 0.1),
+        ];
+        let communities = CommunityDetector::new(CommunityAlg::LabelPropagation).detect(&graph_of(&edges));
+        assert_eq!(communities, [["a", "b", "c", "m"], ["v", "x", "y", "z"]]);
+    }
+
+    fn small_index() -> ForgeIndex {
+        let graph = graph_of(&TWO_CLUSTERS);
+        let communities = CommunityDetector::new(CommunityAlg::Louvain)
+            .detect(&graph)
+            .into_iter()
+            .enumerate()
+            .map(|(i, members)| Community { id: format!("community-{}", i), summary: format!("members {}", members.join(" & ")), members })
+            .collect();
+        ForgeIndex { communities, ..index(graph, vec![chunk("c0", "a knows \"b\""), chunk("c1", "w <-> x")]) }
+    }
+
+    fn index(graph: KnowledgeGraph, chunks: Vec<Chunk>) -> ForgeIndex {
+        ForgeIndex { graph, chunks, communities: Vec::new(), files: BTreeMap::new(), bm25: Bm25Index::default(), entity_vectors: BTreeMap::new(), chunk_positions: OnceLock::new() }
+    }
+
+    #[test]
+    fn stats_count_a_small_index() {
+        let mut graph = graph_of(&[("a", "b", 1.0), ("b", "c", 2.0)]);
+        graph.nodes.insert("d".into(), Entity { id: "d".into(), name: "D".into(), entity_type: "person".into(), description: String::new(), source_chunks: Vec::new(), confidence: None });
+        let stats = index(graph, vec![chunk("c0", "a b"), chunk("c1", "b c")]).stats();
+        let degree = |id: &str, name: &str, degree| EntityDegree { id: id.into(), name: name.into(), degree };
+        assert_eq!(stats, IndexStats {
+            nodes: 4,
+            edges: 2,
+            chunks: 2,
+            communities: 0,
+            top_entities: vec![degree("b", "b", 2), degree("a", "a", 1), degree("c", "c", 1), degree("d", "D", 0)],
+            entity_types: BTreeMap::from([("person".to_string(), 1), ("thing".to_string(), 3)]),
+            avg_strength: 1.5,
+            invalid_edges: 0,
+            components: 2,
+        });
+        assert_eq!(small_index().stats().communities, 2);
+    }
+
+    #[test]
+    fn chunks_for_entity_follow_its_sources() {
+        let mut graph = graph_of(&[("a", "b", 1.0)]);
+        graph.nodes.get_mut("a").unwrap().source_chunks = vec!["c2".into(), "gone".into(), "c0".into()];
+        graph.nodes.get_mut("b").unwrap().source_chunks = vec!["c1".into()];
+        let index = index(graph, vec![chunk("c0", "zero"), chunk("c1", "one"), chunk("c2", "two")]);
+        let ids = |id: &str| index.chunks_for_entity(&id.to_string()).iter().map(|c| c.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids("a"), ["c2", "c0"]);
+        assert_eq!(ids("b"), ["c1"]);
+        assert!(ids("missing").is_empty());
+        assert_eq!(index.chunk("c1").map(|c| c.text.as_str()), Some("one"));
+        assert!(index.chunk("gone").is_none());
+    }
+
+    /// A few thousand chunks, a ring of entities and every optional section filled in.
+    fn large_index() -> ForgeIndex {
+        let chunks: Vec<Chunk> = (0..3000).map(|i| chunk(&format!("c{}", i), &format!("chunk {} mentions e{} and \"e{}\"\nover two lines", i, i % 500, (i + 1) % 500))).collect();
+        let names: Vec<String> = (0..500).map(|i| format!("e{}", i)).collect();
+        let ring: Vec<(&str, &str, f32)> = (0..500).map(|i| (names[i].as_str(), names[(i + 1) % 500].as_str(), (i % 7) as f32 / 7.0)).collect();
+        let mut index = index(graph_of(&ring), chunks);
+        index.bm25 = Bm25Index::build(index.chunks.iter().map(|c| (c.id.as_str(), c.text.as_str())));
+        index.entity_vectors = names.iter().enumerate().map(|(i, n)| (n.clone(), vec![i as f32 / 3.0, -1.5, 0.1])).collect();
+        index.files = (0..50).map(|i| (PathBuf::from(format!("docs/{}.md", i)), FileRecord { hash: format!("{:016x}", i), mtime: i, size: i * 10 })).collect();
+        index
+    }
+
+    #[test]
+    fn indexes_stream_through_plain_and_gzip_json() {
+        // Compression is slow in debug builds, so the gzip path gets the small index.
+        let dir = tempfile::tempdir().unwrap();
+        for (name, index) in [("index.json", large_index()), ("index.json.gz", small_index())] {
+            let path = dir.path().join(name);
+            index.save_json(&path).unwrap();
+            assert_eq!(serde_json::to_value(ForgeIndex::load_json(&path).unwrap()).unwrap(), serde_json::to_value(&index).unwrap(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn json_and_bincode_load_the_same_index() {
+        let mut index = large_index();
+        index.communities = small_index().communities;
+        let dir = tempfile::tempdir().unwrap();
+        let (json, bin) = (dir.path().join("index.json"), dir.path().join("index.BIN"));
+        index.save(&json).unwrap();
+        index.save(&bin).unwrap();
+        assert!(fs::metadata(&bin).unwrap().len() < fs::metadata(&json).unwrap().len());
+        let expected = serde_json::to_value(&index).unwrap();
+        assert_eq!(serde_json::to_value(ForgeIndex::load(&json).unwrap()).unwrap(), expected);
+        assert_eq!(serde_json::to_value(ForgeIndex::load(&bin).unwrap()).unwrap(), expected);
+        // Reading JSON as bincode is an error rather than an allocation of whatever length it implies.
+        assert!(ForgeIndex::load_bincode(&json).is_err());
+    }
+
+    #[tokio::test]
+    async fn entity_vectors_surface_a_concept_the_query_never_names() {
+        let mut graph = KnowledgeGraph::default();
+        let describe = |name: &str, description: &str| Entity { id: String::new(), name: name.into(), entity_type: "concept".into(), description: description.into(), source_chunks: Vec::new(), confidence: None };
+        let plants = graph.add_entity(describe("Photosynthesis", "how green plants turn sunlight, water and air into sugar"));
+        graph.add_entity(describe("Bond Yields", "1.5% 2.75% 3.1% 4.0% 5.25% in 2019-2024"));
+        let mut index = index(graph, Vec::new());
+        let engine = engine(Vec::new()).await;
+        index.embed_entities(engine.vector_store.embedder());
+        let engine = QueryEngine { graph: index.graph, ..engine }.with_entity_top_k(1);
+        let query = "how do green plants turn sunlight and water into sugar?";
+
+        assert!(engine.query_with_sources(query).await.unwrap().entities.is_empty());
+        let engine = engine.with_entity_vectors(index.entity_vectors);
+        assert_eq!(engine.query_with_sources(query).await.unwrap().entities, [plants]);
+    }
+
+    #[test]
+    fn exported_communities_list_member_names() {
+        let mut index = small_index();
+        for e in index.graph.nodes.values_mut() { e.name = e.id.to_uppercase(); }
+        let exported: serde_json::Value = serde_json::from_str(&index.export_communities()).unwrap();
+        let exported = exported.as_array().unwrap();
+        assert_eq!(exported.len(), index.communities.len());
+        for (entry, community) in exported.iter().zip(&index.communities) {
+            assert_eq!(entry["id"], community.id.as_str());
+            assert_eq!(entry["summary"], community.summary.as_str());
+            let names: Vec<&str> = entry["members"].as_array().unwrap().iter().map(|m| m["name"].as_str().unwrap()).collect();
+            assert_eq!(names, community.members.iter().map(|id| id.to_uppercase()).collect::<Vec<_>>());
+        }
+        assert_eq!(exported[1]["members"][0], serde_json::json!({ "id": "w", "name": "W" }));
+    }
+
+    #[tokio::test]
+    async fn synonymous_relationship_types_collapse() {
+        let (llm, _) = recording_llm(|_| {
+            let rel = |ty: &str| serde_json::json!({ "source": "Ada", "target": "Acme", "rel_type": ty });
+            serde_json::json!({ "entities": [], "relationships": [rel("Works For"), rel("works-at"), rel("EmployedBy")] }).to_string()
+        });
+        let synonyms = BTreeMap::from([("Employed By".to_string(), vec!["works for".to_string(), "Works At".to_string()])]);
+        let extractor = EntityExtractor::new(llm).with_rel_types(RelTypes::new(&synonyms));
+        let (_, rels) = extractor.extract(&chunk("c0", "Ada works for Acme.")).await.unwrap();
+        assert_eq!(rels.iter().map(|r| r.rel_type.as_str()).collect::<Vec<_>>(), ["employed_by"; 3]);
+
+        let mut graph = KnowledgeGraph::default();
+        for r in rels { graph.add_relationship(r); }
+        assert_eq!((graph.edges.len(), graph.edges[0].strength), (1, 3.0));
+    }
+
+    #[test]
+    fn duplicate_chunks_collapse_and_references_follow() {
+        let mut graph = graph_of(&[("a", "b", 1.0)]);
+        graph.nodes.get_mut("a").unwrap().source_chunks = vec!["c2".into(), "c0".into()];
+        graph.nodes.get_mut("b").unwrap().source_chunks = vec!["c3".into()];
+        graph.edges[0].source_chunks = vec!["c3".into(), "c1".into()];
+        let chunks = vec![chunk("c0", "License: MIT"), chunk("c1", "Ada wrote notes"), chunk("c2", "license:   mit"), chunk("c3", "LICENSE:\nMIT ")];
+        let mut index = index(graph, chunks);
+        index.rebuild_bm25();
+        assert!(index.chunk("c2").is_some());
+
+        let replaced = index.dedupe_chunks();
+        assert_eq!(replaced, BTreeMap::from([("c2".to_string(), "c0".to_string()), ("c3".to_string(), "c0".to_string())]));
+        assert_eq!(index.chunks.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(), ["c0", "c1"]);
+        assert_eq!(index.graph.nodes["a"].source_chunks, ["c0"]);
+        assert_eq!(index.graph.nodes["b"].source_chunks, ["c0"]);
+        assert_eq!(index.graph.edges[0].source_chunks, ["c0", "c1"]);
+        assert_eq!(index.bm25.len(), 2);
+        assert!(index.chunk("c2").is_none());
+        assert!(index.dedupe_chunks().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_summaries_come_back_in_community_order() {
+        // Earlier communities answer more slowly, so replies finish in reverse.
+        let llm = LLMEngine::mock(|prompt: String| async move {
+            let first = prompt.split("\n- ").nth(1).and_then(|l| l.split(' ').next()).unwrap_or("").to_string();
+            let delay = 30 - first.strip_prefix('n').and_then(|i| i.parse::<u64>().ok()).unwrap_or(0) * 5;
+            tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+            Ok(format!("about {}", first))
+        });
+        let names: Vec<String> = (0..6).map(|i| format!("n{}", i)).collect();
+        let edges: Vec<(&str, &str, f32)> = names.windows(2).map(|w| (w[0].as_str(), w[1].as_str(), 1.0)).collect();
+        let graph = graph_of(&edges);
+        let communities: Vec<Vec<String>> = names.iter().map(|n| vec![n.clone()]).collect();
+        let summarizer = SummaryGenerator::new(llm).with_workers(3);
+        let summaries = summarizer.generate_all(&communities, &graph).await.unwrap();
+        assert_eq!(summaries, names.iter().map(|n| format!("about {}", n)).collect::<Vec<_>>());
+        assert_eq!(summarizer.llm.mock_calls(), 6);
+
+        let built = build_communities(&graph_of(&TWO_CLUSTERS), &CommunityDetector::new(CommunityAlg::Louvain), &summarizer).await.unwrap();
+        assert_eq!(built.iter().map(|c| (c.id.as_str(), c.summary.as_str())).collect::<Vec<_>>(), [("community-0", "about a"), ("community-1", "about w")]);
+    }
+
+    #[test]
+    fn summary_prompt_describes_members_and_their_relationships() {
+        let mut graph = graph_of(&[("ada", "babbage", 0.5), ("ada", "engine", 2.0), ("babbage", "outsider", 3.0)]);
+        for (id, name, description) in [("ada", "Ada Lovelace", "wrote the first program"), ("babbage", "Charles Babbage", ""), ("engine", "Analytical Engine", "a mechanical computer")] {
+            let e = graph.nodes.get_mut(id).unwrap();
+            e.name = name.into();
+            e.description = description.into();
+        }
+        graph.edges[1].description = "programmed it".into();
+        let community = ["ada".to_string(), "babbage".to_string(), "engine".to_string()];
+        let prompt = SummaryGenerator::new(LLMEngine::new()).prompt(&community, &graph).unwrap();
+        assert!(prompt.contains("- Ada Lovelace (thing): wrote the first program\n"), "{}", prompt);
+        assert!(prompt.contains("- Charles Babbage (thing)\n") && prompt.contains("- Analytical Engine (thing): a mechanical computer\n"));
+        // Strongest first, and the edge leaving the community isn't listed.
+        assert!(prompt.ends_with("Relationships:\n- Ada Lovelace -[related]-> Analytical Engine: programmed it\n- Ada Lovelace -[related]-> Charles Babbage\n"), "{}", prompt);
+        assert!(!prompt.contains("outsider"));
+
+        // A budget too small for the relationships drops them before any member.
+        let tight = SummaryGenerator::new(LLMEngine::new()).with_context_budget(Some(40)).prompt(&community, &graph).unwrap();
+        assert!(tight.contains("Analytical Engine (thing)") && !tight.contains("Relationships"), "{}", tight);
+    }
+
+    #[test]
+    fn communities_survive_save_and_load() {
+        let index = small_index();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        index.save(&path).unwrap();
+        let loaded = ForgeIndex::load(&path).unwrap();
+        assert_eq!(loaded.communities.len(), 2);
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&index).unwrap());
+
+        // Indexes written before communities existed still load, with none.
+        let mut old = serde_json::to_value(&index).unwrap();
+        old.as_object_mut().unwrap().remove("communities");
+        fs::write(&path, old.to_string()).unwrap();
+        assert!(ForgeIndex::load(&path).unwrap().communities.is_empty());
+    }
+
+    /// A mock LLM that records every prompt and answers with `reply(prompt)`.
+    fn recording_llm(reply: fn(&str) -> String) -> (LLMEngine, Arc<std::sync::Mutex<Vec<String>>>) {
+        let prompts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = prompts.clone();
+        let llm = LLMEngine::mock(move |prompt: String| {
+            log.lock().unwrap().push(prompt.clone());
+            async move { Ok(reply(&prompt)) }
+        });
+        (llm, prompts)
+    }
+
+    #[tokio::test]
+    async fn global_search_consults_every_community() {
+        let (llm, prompts) = recording_llm(|prompt| {
+            if prompt.starts_with("Combine") { "final".into() } else if prompt.contains("alpha") { "partial alpha".into() } else { "partial beta".into() }
+        });
+        let mut engine = engine(Vec::new()).await.with_communities(vec![
+            Community { id: "community-0".into(), members: vec!["a".into()], summary: "alpha team ships compilers".into() },
+            Community { id: "community-1".into(), members: vec!["w".into()], summary: "beta team runs databases".into() },
+            Community { id: "community-2".into(), members: vec!["z".into()], summary: " ".into() },
+        ]);
+        engine.llm = llm;
+        assert_eq!(engine.query_global("what do the teams do?").await.unwrap(), "final");
+        let prompts = prompts.lock().unwrap();
+        // One map call per summarized community, then the reduce call over both partials.
+        assert_eq!(prompts.len(), 3);
+        assert!(prompts[0].contains("alpha team ships compilers") && prompts[1].contains("beta team runs databases"));
+        assert!(prompts[2].contains("partial alpha") && prompts[2].contains("partial beta"));
+    }
+
+    #[tokio::test]
+    async fn batch_extraction_attributes_each_chunk() {
+        let (llm, prompts) = recording_llm(|_| {
+            r#"Here you go:
+            [{"chunk": 1, "entities": [{"name": "Ferris", "entity_type": "Mascot"}], "relationships": []},
+             {"chunk": 0, "entities": [{"name": "Rust", "entity_type": "Language"}, {"name": "Mozilla", "entity_type": "Org"}],
+              "relationships": [{"source": "Mozilla", "target": "Rust", "rel_type": "Created By", "strength": 0.9}]}]"#.into()
+        });
+        let extractor = EntityExtractor::new(llm).with_batch_size(2);
+        let results = extractor.extract_batch(&[chunk("c0", "Mozilla made Rust"), chunk("c1", "Ferris is a crab")]).await.unwrap();
+        assert_eq!(prompts.lock().unwrap().len(), 1);
+        let names = |i: usize| results[i].0.iter().map(|e| (e.name.as_str(), e.source_chunks.clone())).collect::<Vec<_>>();
+        assert_eq!(names(0), [("Rust", vec!["c0".to_string()]), ("Mozilla", vec!["c0".to_string()])]);
+        assert_eq!(names(1), [("Ferris", vec!["c1".to_string()])]);
+        let rel = &results[0].1[0];
+        assert_eq!((rel.rel_type.as_str(), rel.source_chunks.as_slice()), ("created_by", ["c0".to_string()].as_slice()));
+        assert!(results[1].1.is_empty());
+    }
+
+    #[test]
+    fn extraction_json_survives_noisy_output() {
+        let body = r#"{"entities": [{"name": "Rust", "entity_type": "Language"}], "relationships": []}"#;
+        let fenced = format!("Sure!\n```json\n{}\n```\nLet me know if you need more.", body);
+        let prose = format!("The set {{a, b}} is unrelated; the answer is {} as requested.", body);
+        let trailing = format!("{}\n\nNote: I skipped {{minor}} names.", body);
+        for text in [fenced, prose, trailing] {
+            let (ents, rels) = parse_extraction_json(&text).unwrap_or_else(|| panic!("no extraction in {:?}", text));
+            assert_eq!(ents.iter().map(|e| e.name.as_str()).collect::<Vec<_>>(), ["Rust"]);
+            assert!(rels.is_empty());
+        }
+        assert!(parse_extraction_json("no json {here} at all").is_none());
+    }
+
+    #[test]
+    fn merging_indexes_collapses_the_shared_entity() {
+        let entity = |name: &str, chunk: &str| Entity { id: String::new(), name: name.into(), entity_type: "Person".into(), description: String::new(), source_chunks: vec![chunk.into()], confidence: None };
+        let build = |names: [&str; 2], chunk_id: &str, text: &str| {
+            let mut graph = KnowledgeGraph::default();
+            let a = graph.add_entity(entity(names[0], chunk_id));
+            let b = graph.add_entity(entity(names[1], chunk_id));
+            graph.add_relationship(Relationship { source: a, target: b, rel_type: "met".into(), description: String::new(), strength: 1.0, source_chunks: vec![chunk_id.into()] });
+            index(graph, vec![chunk(chunk_id, text)])
+        };
+        let mut left = build(["Ada Lovelace", "Charles Babbage"], "c0", "Ada met Charles");
+        let right = build(["ada lovelace", "Mary Somerville"], "c0", "Ada met Mary");
+        left.merge(right);
+
+        assert_eq!(left.graph.nodes.len(), 3);
+        let ada = left.graph.find_entity("Ada Lovelace").unwrap();
+        assert_eq!(ada.source_chunks, ["c0", "c0-merged-1"]);
+        assert_eq!(left.chunks.iter().map(|c| c.id.as_str()).collect::<Vec<_>>(), ["c0", "c0-merged-1"]);
+        let mary = &left.graph.find_entity("Mary Somerville").unwrap().id;
+        assert!(left.graph.edges.iter().any(|e| e.source == ada.id && &e.target == mary && e.source_chunks == ["c0-merged-1"]));
+        assert!(left.graph.validate().is_empty());
+    }
+}
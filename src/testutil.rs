@@ -0,0 +1,89 @@
+//! HTTP fixtures for tests; not every feature set uses every helper.
+#![allow(dead_code)]
+
+use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener, sync::{Arc, Mutex}, thread};
+
+/// A request received by a `MockServer`; header names are lowercased.
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value { serde_json::from_slice(&self.body).expect("JSON request body") }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(value: serde_json::Value) -> Self {
+        Self { status: 200, headers: vec![("Content-Type".into(), "application/json".into())], body: value.to_string().into_bytes() }
+    }
+
+    pub fn status(status: u16, body: &str) -> Self { Self { status, headers: Vec::new(), body: body.as_bytes().to_vec() } }
+}
+
+/// An HTTP/1.1 server on a free local port that answers every request with `handler` and keeps
+/// the requests for inspection. It runs on plain threads, so it works with any test runtime.
+pub(crate) struct MockServer {
+    pub url: String,
+    pub requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    pub fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let url = format!("http://{}", listener.local_addr().expect("local addr"));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (handler, log) = (Arc::new(handler), requests.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let (handler, log) = (handler.clone(), log.clone());
+                thread::spawn(move || {
+                    let Some(request) = read_request(&mut stream) else { return };
+                    let response = handler(&request);
+                    log.lock().unwrap().push(request);
+                    let mut head = format!("HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, response.body.len());
+                    for (k, v) in &response.headers { head.push_str(&format!("{}: {}\r\n", k, v)); }
+                    head.push_str("\r\n");
+                    let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(&response.body));
+                });
+            }
+        });
+        Self { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> { self.requests.lock().unwrap().clone() }
+}
+
+fn read_request(stream: &mut std::net::TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = (parts.next()?.to_string(), parts.next()?.to_string());
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let Some((k, v)) = line.trim_end().split_once(':') else { break };
+        headers.push((k.trim().to_ascii_lowercase(), v.trim().to_string()));
+    }
+    let len = headers.iter().find(|(k, _)| k == "content-length").and_then(|(_, v)| v.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).ok()?;
+    Some(Request { method, path, headers, body })
+}
@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use super::VectorStore;
+
+/// Okapi BM25 keyword index over chunk texts.
+///
+/// Term statistics are kept per document so chunks can be added and removed incrementally;
+/// the index serializes with `ForgeIndex` so queries don't need to re-tokenize the corpus.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bm25Index {
+    pub k1: f32,
+    pub b: f32,
+    // term -> (doc id -> term frequency)
+    postings: BTreeMap<String, BTreeMap<String, u32>>,
+    doc_len: BTreeMap<String, u32>,
+    total_len: u64,
+}
+
+impl Default for Bm25Index {
+    fn default() -> Self { Self { k1: 1.2, b: 0.75, postings: BTreeMap::new(), doc_len: BTreeMap::new(), total_len: 0 } }
+}
+
+impl Bm25Index {
+    pub fn build<'a>(docs: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut index = Self::default();
+        for (id, text) in docs { index.add(id, text); }
+        index
+    }
+
+    pub fn len(&self) -> usize { self.doc_len.len() }
+
+    pub fn is_empty(&self) -> bool { self.doc_len.is_empty() }
+
+    /// Indexes `text` under `id`, replacing any previous text for that id.
+    pub fn add(&mut self, id: &str, text: &str) {
+        self.remove(id);
+        let terms = tokenize(text);
+        let mut tf: HashMap<String, u32> = HashMap::new();
+        for term in &terms { *tf.entry(term.clone()).or_insert(0) += 1; }
+        for (term, n) in tf { self.postings.entry(term).or_default().insert(id.to_string(), n); }
+        self.doc_len.insert(id.to_string(), terms.len() as u32);
+        self.total_len += terms.len() as u64;
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(len) = self.doc_len.remove(id) else { return };
+        self.total_len -= len as u64;
+        self.postings.retain(|_, docs| {
+            docs.remove(id);
+            !docs.is_empty()
+        });
+    }
+
+    /// Top `k` documents by BM25 score for `query`, best first; documents sharing no term are omitted.
+    pub fn search(&self, query: &str, k: usize) -> Vec<(String, f32)> {
+        let n = self.doc_len.len() as f32;
+        if n == 0.0 { return Vec::new(); }
+        let avg_len = self.total_len as f32 / n;
+        let mut terms = tokenize(query);
+        terms.sort_unstable();
+        terms.dedup();
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+        for term in &terms {
+            let Some(docs) = self.postings.get(term) else { continue };
+            let df = docs.len() as f32;
+            let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+            for (id, &tf) in docs {
+                let tf = tf as f32;
+                let len = self.doc_len[id] as f32;
+                let norm = tf * (self.k1 + 1.0) / (tf + self.k1 * (1.0 - self.b + self.b * len / avg_len.max(1.0)));
+                *scores.entry(id).or_insert(0.0) += idf * norm;
+            }
+        }
+        let mut hits: Vec<(String, f32)> = scores.into_iter().map(|(id, s)| (id.to_string(), s)).collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        hits.truncate(k);
+        hits
+    }
+}
+
+/// Lowercased alphanumeric runs; the term unit for BM25 statistics.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|t| !t.is_empty()).map(str::to_lowercase).collect()
+}
+
+/// Combines vector search with BM25 using weighted reciprocal-rank fusion, so exact keyword
+/// matches surface even when their embeddings are unremarkable.
+#[derive(Debug, Clone)]
+pub struct HybridRetriever {
+    pub bm25: Bm25Index,
+    /// Share of the fused score given to the vector ranking (0 = keywords only, 1 = vectors only).
+    pub vector_weight: f32,
+    /// RRF damping constant; larger values flatten the advantage of top ranks.
+    pub rrf_k: f32,
+    /// Candidates taken from each ranking per requested result.
+    pub oversample: usize,
+}
+
+impl HybridRetriever {
+    pub fn new(bm25: Bm25Index) -> Self { Self { bm25, vector_weight: 0.5, rrf_k: 60.0, oversample: 4 } }
+
+    pub fn with_vector_weight(mut self, weight: f32) -> Self { self.vector_weight = weight.clamp(0.0, 1.0); self }
+
+    /// Top `k` ids with their fused scores, best first.
+    pub async fn retrieve<VS: VectorStore>(&self, store: &VS, query: &str, k: usize) -> Vec<(String, f32)> {
+        let candidates = k.saturating_mul(self.oversample.max(1));
+        let vector_hits = store.search(&store.embedder().embed(query), candidates).await;
+        let keyword_hits = self.bm25.search(query, candidates);
+        self.fuse(&vector_hits, &keyword_hits, k)
+    }
+
+    /// Like `retrieve`, but only ids that `keep` accepts are ranked.
+    pub async fn retrieve_where<VS: VectorStore>(&self, store: &VS, query: &str, k: usize, keep: &(dyn for<'s> Fn(&'s str) -> bool + Sync)) -> Vec<(String, f32)> {
+        let candidates = k.saturating_mul(self.oversample.max(1));
+        let vector_hits = store.search_where(&store.embedder().embed(query), candidates, keep).await;
+        let keyword_hits: Vec<(String, f32)> = self.bm25.search(query, usize::MAX).into_iter().filter(|(id, _)| keep(id)).take(candidates).collect();
+        self.fuse(&vector_hits, &keyword_hits, k)
+    }
+
+    pub fn fuse(&self, vector_hits: &[(String, f32)], keyword_hits: &[(String, f32)], k: usize) -> Vec<(String, f32)> {
+        let mut scores: HashMap<&str, f32> = HashMap::new();
+        for (hits, weight) in [(vector_hits, self.vector_weight), (keyword_hits, 1.0 - self.vector_weight)] {
+            for (rank, (id, _)) in hits.iter().enumerate() {
+                *scores.entry(id).or_insert(0.0) += weight / (self.rrf_k + rank as f32 + 1.0);
+            }
+        }
+        let mut fused: Vec<(String, f32)> = scores.into_iter().map(|(id, s)| (id.to_string(), s)).collect();
+        fused.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        fused.truncate(k);
+        fused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::InMemoryVectorStore;
+
+    #[tokio::test]
+    async fn rare_keyword_beats_a_closer_embedding() {
+        // The decoy spells out the query's letters without containing the word, so its byte
+        // histogram wins vector search while BM25 only matches the target.
+        let docs = [
+            ("decoy", "xylophones lyophone phonex"),
+            ("target", "tuning a xylophone takes a mallet, a quiet room and patience"),
+            ("graphs", "knowledge graphs link entities through typed relationships"),
+            ("rust", "the borrow checker rejects aliasing mutable references"),
+        ];
+        let mut store = InMemoryVectorStore::default();
+        for (id, text) in docs {
+            let v = store.embedder().embed(text);
+            store.upsert(id.to_string(), v).await.unwrap();
+        }
+        let query = "xylophone";
+        assert_eq!(store.search(&store.embedder().embed(query), 1).await[0].0, "decoy");
+
+        let retriever = HybridRetriever::new(Bm25Index::build(docs));
+        assert_eq!(retriever.bm25.search(query, 4).iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), ["target"]);
+        assert_eq!(retriever.retrieve(&store, query, 1).await[0].0, "target");
+    }
+}
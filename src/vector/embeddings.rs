@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use anyhow::Context;
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use tokenizers::Tokenizer;
+
+use super::Embedder;
+use crate::Result;
+
+// BERT position embeddings cap the sequence length.
+const MAX_TOKENS: usize = 512;
+
+/// Sentence-transformer embeddings (e.g. all-MiniLM-L6-v2) computed with Candle.
+///
+/// `model_dir` must contain `config.json`, `tokenizer.json` and `model.safetensors`.
+pub struct SentenceEmbedder {
+    model: BertModel,
+    tokenizer: Tokenizer,
+    device: Device,
+    dim: usize,
+}
+
+impl SentenceEmbedder {
+    pub fn load(model_dir: &Path, device: Device) -> Result<Self> {
+        let config_path = model_dir.join("config.json");
+        let config_data = std::fs::read_to_string(&config_path).with_context(|| format!("reading {}", config_path.display()))?;
+        let config: Config = serde_json::from_str(&config_data).with_context(|| format!("parsing {}", config_path.display()))?;
+        let dim = serde_json::from_str::<serde_json::Value>(&config_data)?
+            .get("hidden_size")
+            .and_then(|v| v.as_u64())
+            .with_context(|| format!("{} has no hidden_size", config_path.display()))? as usize;
+
+        let tokenizer_path = model_dir.join("tokenizer.json");
+        let tokenizer = Tokenizer::from_file(&tokenizer_path).map_err(|e| anyhow::anyhow!("loading tokenizer {}: {}", tokenizer_path.display(), e))?;
+
+        let weights = model_dir.join("model.safetensors");
+        if !weights.exists() { anyhow::bail!("model weights not found: {}", weights.display()); }
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], DTYPE, &device)? };
+        let model = BertModel::load(vb, &config)?;
+        Ok(Self { model, tokenizer, device, dim })
+    }
+}
+
+impl Embedder for SentenceEmbedder {
+    /// `try_embed`, or an empty vector when it fails, which stores refuse and searches with it
+    /// match nothing.
+    fn embed(&self, text: &str) -> Vec<f32> { self.try_embed(text).unwrap_or_default() }
+
+    fn dim(&self) -> usize { self.dim }
+
+    /// Mean-pooled, L2-normalized sentence embedding.
+    fn try_embed(&self, text: &str) -> Result<Vec<f32>> {
+        let enc = self.tokenizer.encode(text, true).map_err(anyhow::Error::msg)?;
+        let mut ids = enc.get_ids().to_vec();
+        ids.truncate(MAX_TOKENS);
+        let input_ids = Tensor::new(ids.as_slice(), &self.device)?.unsqueeze(0)?;
+        let token_type_ids = input_ids.zeros_like()?;
+        let hidden = self.model.forward(&input_ids, &token_type_ids)?;
+        let (_, n_tokens, _) = hidden.dims3()?;
+        let pooled = (hidden.sum(1)? / n_tokens as f64)?.squeeze(0)?;
+        Ok(super::l2_normalize(pooled.to_vec1::<f32>()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::cosine_similarity;
+
+    #[test]
+    #[ignore = "needs a sentence-transformer: set FORGE_TEST_EMBEDDER to a directory with all-MiniLM-L6-v2"]
+    fn similar_sentences_score_higher() {
+        let dir = std::env::var("FORGE_TEST_EMBEDDER").expect("FORGE_TEST_EMBEDDER");
+        let embedder = SentenceEmbedder::load(Path::new(&dir), Device::Cpu).unwrap();
+        let cat = embedder.try_embed("A cat is sleeping on the sofa.").unwrap();
+        let kitten = embedder.try_embed("A kitten naps on the couch.").unwrap();
+        let tax = embedder.try_embed("Quarterly tax filings are due in April.").unwrap();
+        assert_eq!(cat.len(), embedder.dim());
+        assert!(cosine_similarity(&cat, &kitten) > cosine_similarity(&cat, &tax));
+    }
+}
@@ -0,0 +1,307 @@
+use std::{cmp::Ordering, collections::{BinaryHeap, HashMap, HashSet}};
+
+use async_trait::async_trait;
+
+use super::{l2_normalize, ByteHistogramEmbedder, Embedder, VectorStore};
+use crate::Result;
+
+/// Approximate nearest-neighbor store using a Hierarchical Navigable Small World graph.
+///
+/// Vectors are L2-normalized and compared by cosine similarity, matching `InMemoryVectorStore`'s
+/// default metric. The graph is built incrementally on `upsert`; re-upserting an existing id
+/// replaces its vector but keeps its links, so heavy rewrites degrade recall. Removed nodes stay
+/// in the graph as waypoints until they outnumber half the live ones, when it is rebuilt.
+#[derive(Debug, Clone)]
+pub struct HnswVectorStore<E: Embedder = ByteHistogramEmbedder> {
+    embedder: E,
+    m: usize,
+    m_max0: usize,
+    ef_construction: usize,
+    ef_search: usize,
+    level_mult: f64,
+    ids: Vec<String>,
+    vectors: Vec<Vec<f32>>,
+    // links[node][level] holds the neighbors of `node` on `level`.
+    links: Vec<Vec<Vec<usize>>>,
+    index_of: HashMap<String, usize>,
+    // Removed nodes stay in the graph for navigation but are never returned, until `compact`.
+    deleted: HashSet<usize>,
+    entry: Option<usize>,
+    max_level: usize,
+    rng: u64,
+}
+
+impl Default for HnswVectorStore {
+    fn default() -> Self { Self::new(ByteHistogramEmbedder::default(), 16, 200) }
+}
+
+impl<E: Embedder> HnswVectorStore<E> {
+    /// `m` is the number of links per node on upper layers (twice that on layer 0);
+    /// `ef_construction` is the candidate list size used while inserting.
+    pub fn new(embedder: E, m: usize, ef_construction: usize) -> Self {
+        let m = m.max(2);
+        Self {
+            embedder,
+            m,
+            m_max0: m * 2,
+            ef_construction: ef_construction.max(m),
+            ef_search: 64,
+            level_mult: 1.0 / (m as f64).ln(),
+            ids: Vec::new(),
+            vectors: Vec::new(),
+            links: Vec::new(),
+            index_of: HashMap::new(),
+            deleted: HashSet::new(),
+            entry: None,
+            max_level: 0,
+            rng: 0x2545F4914F6CDD1D,
+        }
+    }
+
+    /// Candidate list size at query time; larger trades latency for recall.
+    pub fn with_ef_search(mut self, ef_search: usize) -> Self { self.ef_search = ef_search.max(1); self }
+
+    pub fn len(&self) -> usize { self.index_of.len() }
+
+    pub fn is_empty(&self) -> bool { self.index_of.is_empty() }
+
+    fn distance(&self, q: &[f32], node: usize) -> f32 {
+        1.0 - q.iter().zip(&self.vectors[node]).map(|(a, b)| a * b).sum::<f32>()
+    }
+
+    fn random_level(&mut self) -> usize {
+        // xorshift64*; deterministic so that identical inserts build identical graphs.
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let r = self.rng.wrapping_mul(0x2545F4914F6CDD1D);
+        let u = ((r >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        (-u.ln() * self.level_mult).floor() as usize
+    }
+
+    /// Best-first search on one layer, returning up to `ef` nodes sorted by ascending distance.
+    /// With `live_only`, removed nodes are walked through but neither returned nor counted
+    /// against `ef`, so tombstones don't crowd out results.
+    fn search_layer(&self, q: &[f32], entry_points: &[usize], ef: usize, level: usize, live_only: bool) -> Vec<(f32, usize)> {
+        let keep = |node: &usize| !live_only || !self.deleted.contains(node);
+        let mut visited: HashSet<usize> = entry_points.iter().copied().collect();
+        let mut candidates: BinaryHeap<Near> = BinaryHeap::new();
+        let mut found: BinaryHeap<Far> = BinaryHeap::new();
+        for &ep in entry_points {
+            let d = self.distance(q, ep);
+            candidates.push(Near(d, ep));
+            if keep(&ep) { found.push(Far(d, ep)); }
+        }
+        while let Some(Near(d, node)) = candidates.pop() {
+            if found.len() >= ef && found.peek().is_some_and(|f| d > f.0) { break; }
+            for &nb in self.links[node].get(level).map(Vec::as_slice).unwrap_or_default() {
+                if !visited.insert(nb) { continue; }
+                let dn = self.distance(q, nb);
+                if found.len() < ef || found.peek().is_some_and(|f| dn < f.0) {
+                    candidates.push(Near(dn, nb));
+                    if keep(&nb) { found.push(Far(dn, nb)); }
+                    if found.len() > ef { found.pop(); }
+                }
+            }
+        }
+        let mut out: Vec<(f32, usize)> = found.into_iter().map(|Far(d, n)| (d, n)).collect();
+        out.sort_by(|a, b| a.0.total_cmp(&b.0));
+        out
+    }
+
+    fn greedy_descend(&self, q: &[f32], mut ep: usize, from: usize, to: usize) -> usize {
+        for level in (to..=from).rev() {
+            if let Some(&(_, best)) = self.search_layer(q, &[ep], 1, level, false).first() { ep = best; }
+        }
+        ep
+    }
+
+    fn insert_node(&mut self, node: usize) {
+        let level = self.random_level();
+        self.links.push(vec![Vec::new(); level + 1]);
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            self.max_level = level;
+            return;
+        };
+        let q = self.vectors[node].clone();
+        let mut eps = vec![if level < self.max_level { self.greedy_descend(&q, entry, self.max_level, level + 1) } else { entry }];
+        for l in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(&q, &eps, self.ef_construction, l, false);
+            let max_links = if l == 0 { self.m_max0 } else { self.m };
+            let neighbors: Vec<usize> = candidates.iter().take(max_links).map(|&(_, n)| n).collect();
+            for &nb in &neighbors {
+                self.links[nb][l].push(node);
+                if self.links[nb][l].len() > max_links { self.prune(nb, l, max_links); }
+            }
+            self.links[node][l] = neighbors;
+            eps = candidates.into_iter().map(|(_, n)| n).collect();
+        }
+        if level > self.max_level {
+            self.entry = Some(node);
+            self.max_level = level;
+        }
+    }
+
+    /// Rebuilds the graph from the live vectors, dropping removed nodes for good. `remove` calls
+    /// it once they outnumber half the live ones.
+    pub fn compact(&mut self) {
+        if self.deleted.is_empty() { return; }
+        let mut live: Vec<(usize, String)> = self.index_of.drain().map(|(id, node)| (node, id)).collect();
+        live.sort_unstable();
+        let mut vectors = std::mem::take(&mut self.vectors);
+        self.ids.clear();
+        self.links.clear();
+        self.deleted.clear();
+        self.entry = None;
+        self.max_level = 0;
+        for (node, id) in live {
+            let fresh = self.vectors.len();
+            self.vectors.push(std::mem::take(&mut vectors[node]));
+            self.ids.push(id.clone());
+            self.index_of.insert(id, fresh);
+            self.insert_node(fresh);
+        }
+    }
+
+    // Keep only the `max_links` closest neighbors of `node` on `level`.
+    fn prune(&mut self, node: usize, level: usize, max_links: usize) {
+        let base = self.vectors[node].clone();
+        let mut scored: Vec<(f32, usize)> = self.links[node][level].iter().map(|&nb| (self.distance(&base, nb), nb)).collect();
+        scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+        scored.truncate(max_links);
+        self.links[node][level] = scored.into_iter().map(|(_, n)| n).collect();
+    }
+}
+
+#[async_trait]
+impl<E: Embedder> VectorStore for HnswVectorStore<E> {
+    async fn upsert(&mut self, id: String, vector: Vec<f32>) -> Result<()> {
+        let vector = l2_normalize(vector);
+        if let Some(&node) = self.index_of.get(&id) {
+            self.vectors[node] = vector;
+            return Ok(());
+        }
+        let node = self.vectors.len();
+        self.vectors.push(vector);
+        self.ids.push(id.clone());
+        self.index_of.insert(id, node);
+        self.insert_node(node);
+        Ok(())
+    }
+
+    async fn search(&self, query: &[f32], k: usize) -> Vec<(String, f32)> {
+        let Some(entry) = self.entry else { return vec![] };
+        if k == 0 { return vec![]; }
+        let q = l2_normalize(query.to_vec());
+        let ep = self.greedy_descend(&q, entry, self.max_level, 1);
+        self.search_layer(&q, &[ep], self.ef_search.max(k), 0, true)
+            .into_iter()
+            .take(k)
+            .map(|(d, n)| (self.ids[n].clone(), 1.0 - d))
+            .collect()
+    }
+
+    async fn remove(&mut self, id: &str) -> bool {
+        match self.index_of.remove(id) {
+            Some(node) => {
+                self.deleted.insert(node);
+                if self.deleted.len() > self.index_of.len() / 2 { self.compact(); }
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn clear(&mut self) {
+        self.ids.clear();
+        self.vectors.clear();
+        self.links.clear();
+        self.index_of.clear();
+        self.deleted.clear();
+        self.entry = None;
+        self.max_level = 0;
+    }
+
+    fn embedder(&self) -> &dyn Embedder { &self.embedder }
+}
+
+// Min-heap entry by distance (closest first).
+struct Near(f32, usize);
+// Max-heap entry by distance (farthest first).
+struct Far(f32, usize);
+
+impl PartialEq for Near { fn eq(&self, o: &Self) -> bool { self.0.total_cmp(&o.0) == Ordering::Equal } }
+impl Eq for Near {}
+impl PartialOrd for Near { fn partial_cmp(&self, o: &Self) -> Option<Ordering> { Some(self.cmp(o)) } }
+impl Ord for Near { fn cmp(&self, o: &Self) -> Ordering { o.0.total_cmp(&self.0) } }
+
+impl PartialEq for Far { fn eq(&self, o: &Self) -> bool { self.0.total_cmp(&o.0) == Ordering::Equal } }
+impl Eq for Far {}
+impl PartialOrd for Far { fn partial_cmp(&self, o: &Self) -> Option<Ordering> { Some(self.cmp(o)) } }
+impl Ord for Far { fn cmp(&self, o: &Self) -> Ordering { self.0.total_cmp(&o.0) } }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::vector::InMemoryVectorStore;
+
+    const DIM: usize = 32;
+
+    fn random_vectors(n: usize, mut seed: u64) -> Vec<Vec<f32>> {
+        let mut next = move || {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            ((z ^ (z >> 31)) >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+        };
+        (0..n).map(|_| (0..DIM).map(|_| next()).collect()).collect()
+    }
+
+    /// Mean fraction of the exact top `k` that `hnsw` also returns.
+    async fn recall(hnsw: &HnswVectorStore, exact: &InMemoryVectorStore, queries: &[Vec<f32>], k: usize) -> f32 {
+        let mut hits = 0;
+        for q in queries {
+            let truth: HashSet<String> = exact.search(q, k).await.into_iter().map(|(id, _)| id).collect();
+            hits += hnsw.search(q, k).await.into_iter().filter(|(id, _)| truth.contains(id)).count();
+        }
+        hits as f32 / (queries.len() * k) as f32
+    }
+
+    async fn stores(n: usize) -> (HnswVectorStore, InMemoryVectorStore) {
+        let mut hnsw = HnswVectorStore::new(ByteHistogramEmbedder::new(DIM), 12, 64);
+        let mut exact = InMemoryVectorStore::new(DIM);
+        for (i, v) in random_vectors(n, 1).into_iter().enumerate() {
+            hnsw.upsert(format!("v{i}"), v.clone()).await.unwrap();
+            exact.upsert(format!("v{i}"), v).await.unwrap();
+        }
+        (hnsw, exact)
+    }
+
+    #[tokio::test]
+    async fn recall_tracks_exact_search() {
+        let (hnsw, exact) = stores(1500).await;
+        let r = recall(&hnsw, &exact, &random_vectors(50, 2), 10).await;
+        assert!(r >= 0.9, "recall@10 was {r}");
+    }
+
+    #[tokio::test]
+    async fn removals_are_compacted_without_hurting_recall() {
+        let (mut hnsw, mut exact) = stores(1000).await;
+        for i in (0..1000).step_by(3) {
+            assert!(hnsw.remove(&format!("v{i}")).await);
+            exact.remove(&format!("v{i}")).await;
+        }
+        assert!(hnsw.deleted.len() * 2 <= hnsw.len(), "{} tombstones for {} live nodes", hnsw.deleted.len(), hnsw.len());
+        assert_eq!(hnsw.vectors.len(), hnsw.len() + hnsw.deleted.len());
+        let queries = random_vectors(50, 3);
+        for q in &queries {
+            assert!(hnsw.search(q, 10).await.iter().all(|(id, _)| id[1..].parse::<usize>().unwrap() % 3 != 0));
+        }
+        let r = recall(&hnsw, &exact, &queries, 10).await;
+        assert!(r >= 0.9, "recall@10 after removals was {r}");
+    }
+}
@@ -1,50 +1,287 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap, fs, path::Path};
 
-pub trait VectorStore {
-    fn upsert(&mut self, id: String, vector: Vec<f32>);
-    fn search(&self, query: &[f32], k: usize) -> Vec<(String, f32)>;
-    fn embed_text(&self, text: &str) -> Vec<f32>;
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+#[cfg(feature = "embeddings")]
+pub mod embeddings;
+mod bm25;
+mod hnsw;
+#[cfg(feature = "vector-qdrant")]
+pub mod qdrant;
+
+pub use bm25::{tokenize, Bm25Index, HybridRetriever};
+pub use hnsw::HnswVectorStore;
+
+#[async_trait]
+pub trait VectorStore: Send + Sync {
+    /// Stores `vector` under `id`; fails if the store refuses it, e.g. for having the wrong
+    /// dimension, or can't be reached.
+    async fn upsert(&mut self, id: String, vector: Vec<f32>) -> Result<()>;
+    async fn search(&self, query: &[f32], k: usize) -> Vec<(String, f32)>;
+    /// Results `offset..offset + k` of the ranking `search` returns the head of, for paging through
+    /// hits. The default searches for `offset + k` and drops the first `offset`.
+    async fn search_paged(&self, query: &[f32], k: usize, offset: usize) -> Vec<(String, f32)> {
+        let mut hits = self.search(query, offset + k).await;
+        hits.drain(..offset.min(hits.len()));
+        hits
+    }
+    /// The top `k` hits among ids that `keep` accepts. The default pages through `search_paged`
+    /// until it has `k`, so a filter matching few ids in a large store costs many searches.
+    async fn search_where(&self, query: &[f32], k: usize, keep: &(dyn for<'s> Fn(&'s str) -> bool + Sync)) -> Vec<(String, f32)> {
+        let page = k.max(16);
+        let mut hits = Vec::new();
+        let mut offset = 0;
+        while hits.len() < k {
+            let batch = self.search_paged(query, page, offset).await;
+            let done = batch.len() < page;
+            hits.extend(batch.into_iter().filter(|(id, _)| keep(id)));
+            if done { break; }
+            offset += page;
+        }
+        hits.truncate(k);
+        hits
+    }
+    /// Inserts many vectors at once; stores with a bulk API should override this.
+    async fn upsert_batch(&mut self, items: Vec<(String, Vec<f32>)>) -> Result<()> {
+        for (id, vector) in items { self.upsert(id, vector).await?; }
+        Ok(())
+    }
+    /// Removes `id`, returning whether it was present.
+    async fn remove(&mut self, id: &str) -> bool;
+    async fn clear(&mut self);
+    /// The embedder whose vectors this store holds; queries must be embedded with it too.
+    fn embedder(&self) -> &dyn Embedder;
 }
 
-pub struct InMemoryVectorStore {
+/// Key-value facts about a stored vector, such as the `source_path` of the chunk it embeds.
+pub type Metadata = HashMap<String, String>;
+
+pub trait Embedder: Send + Sync {
+    fn embed(&self, text: &str) -> Vec<f32>;
+    fn dim(&self) -> usize;
+    /// `embed`, for embedders that can fail; indexing skips the texts this refuses rather than
+    /// storing a placeholder vector.
+    fn try_embed(&self, text: &str) -> Result<Vec<f32>> { Ok(self.embed(text)) }
+}
+
+/// Byte histogram embedding; deterministic and fast, but carries little semantic signal.
+#[derive(Debug, Clone)]
+pub struct ByteHistogramEmbedder {
+    dim: usize,
+}
+
+impl Default for ByteHistogramEmbedder {
+    fn default() -> Self { Self { dim: 256 } }
+}
+
+impl ByteHistogramEmbedder {
+    pub fn new(dim: usize) -> Self { Self { dim } }
+}
+
+impl Embedder for ByteHistogramEmbedder {
+    fn embed(&self, text: &str) -> Vec<f32> {
+        let mut v = vec![0f32; self.dim];
+        for &b in text.as_bytes() { v[(b as usize) % self.dim] += 1.0; }
+        l2_normalize(v)
+    }
+
+    fn dim(&self) -> usize { self.dim }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Metric {
+    /// Cosine similarity over L2-normalized vectors; higher is better.
+    #[default]
+    Cosine,
+    /// Raw dot product; vectors keep their magnitudes. Higher is better.
+    DotProduct,
+    /// Euclidean distance; scores are distances, so lower is better.
+    Euclidean,
+}
+
+/// What an `InMemoryVectorStore` does with a vector whose length isn't its dimension, which
+/// usually means vectors from two different embedders are being mixed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DimPolicy {
+    /// Refuse it: upserts and `try_search` fail; `VectorStore`'s searches, which can't fail, find
+    /// nothing.
+    #[default]
+    Reject,
+    /// Zero-pad short vectors and truncate long ones to the store's dimension.
+    Fit,
+}
+
+#[derive(Debug, Clone)]
+pub struct InMemoryVectorStore<E: Embedder = ByteHistogramEmbedder> {
     dim: usize,
     store: HashMap<String, Vec<f32>>,
+    metadata: HashMap<String, Metadata>,
+    embedder: E,
+    metric: Metric,
+    dim_policy: DimPolicy,
+}
+
+// On-disk form of an `InMemoryVectorStore`; the embedder itself is not persisted.
+#[derive(Serialize, Deserialize)]
+struct PersistedStore<'a> {
+    dim: usize,
+    store: Cow<'a, HashMap<String, Vec<f32>>>,
+    #[serde(default)]
+    metric: Metric,
+    #[serde(default)]
+    metadata: Cow<'a, HashMap<String, Metadata>>,
 }
 
 impl Default for InMemoryVectorStore {
-    fn default() -> Self { Self { dim: 256, store: HashMap::new() } }
+    fn default() -> Self { Self::with_embedder(ByteHistogramEmbedder::default()) }
 }
 
 impl InMemoryVectorStore {
-    pub fn new(dim: usize) -> Self { Self { dim, store: HashMap::new() } }
+    pub fn new(dim: usize) -> Self { Self::with_embedder(ByteHistogramEmbedder::new(dim)) }
+
+    /// Loads a store saved with the byte-histogram embedder, keeping its saved dimension.
+    pub fn load(path: &Path) -> Result<Self> {
+        let persisted = read_persisted(path)?;
+        let embedder = ByteHistogramEmbedder::new(persisted.dim);
+        Self::from_persisted(path, persisted, embedder)
+    }
 }
 
-impl VectorStore for InMemoryVectorStore {
-    fn upsert(&mut self, id: String, vector: Vec<f32>) {
-        self.store.insert(id, l2_normalize(vector));
+impl<E: Embedder> InMemoryVectorStore<E> {
+    pub fn with_embedder(embedder: E) -> Self { Self { dim: embedder.dim(), store: HashMap::new(), metadata: HashMap::new(), embedder, metric: Metric::default(), dim_policy: DimPolicy::default() } }
+
+    /// Selects the distance metric; set it before inserting, since cosine normalizes on upsert.
+    pub fn with_metric(mut self, metric: Metric) -> Self { self.metric = metric; self }
+
+    pub fn metric(&self) -> Metric { self.metric }
+
+    pub fn with_dim_policy(mut self, policy: DimPolicy) -> Self { self.dim_policy = policy; self }
+
+    pub fn dim(&self) -> usize { self.dim }
+
+    /// Inserts `vector` under `id`, applying the dimension policy.
+    pub fn try_upsert(&mut self, id: String, vector: Vec<f32>) -> Result<()> {
+        let vector = self.check_dim(vector).with_context(|| format!("upserting '{}'", id))?;
+        let vector = if self.metric == Metric::Cosine { l2_normalize(vector) } else { vector };
+        self.store.insert(id, vector);
+        Ok(())
     }
 
-    fn search(&self, query: &[f32], k: usize) -> Vec<(String, f32)> {
-        let q = l2_normalize(query.to_vec());
-        let mut scores: Vec<(String, f32)> = self
-            .store
-            .iter()
-            .map(|(id, v)| (id.clone(), cosine_similarity(&q, v)))
-            .collect();
-        scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-        scores.truncate(k);
-        scores
+    // Brings `vector` to the store's dimension, or refuses it, per `dim_policy`.
+    fn check_dim(&self, mut vector: Vec<f32>) -> Result<Vec<f32>> {
+        if vector.len() == self.dim { return Ok(vector); }
+        match self.dim_policy {
+            DimPolicy::Reject => anyhow::bail!("vector has {} components but the store's dimension is {}", vector.len(), self.dim),
+            DimPolicy::Fit => {
+                vector.resize(self.dim, 0.0);
+                Ok(vector)
+            }
+        }
     }
 
-    fn embed_text(&self, text: &str) -> Vec<f32> {
-        // Simple 256-dim byte histogram embedding; deterministic and fast.
-        let mut v = vec![0f32; self.dim];
-        for &b in text.as_bytes() { v[(b as usize) % self.dim] += 1.0; }
-        l2_normalize(v)
+    /// Attaches `metadata` to `id`, replacing what it had; it is dropped when `id` is removed.
+    pub fn set_metadata(&mut self, id: impl Into<String>, metadata: Metadata) { self.metadata.insert(id.into(), metadata); }
+
+    pub fn metadata(&self, id: &str) -> Option<&Metadata> { self.metadata.get(id) }
+
+    /// `VectorStore::search`, but a query the dimension policy refuses is an error rather than no hits.
+    pub fn try_search(&self, query: &[f32], k: usize) -> Result<Vec<(String, f32)>> {
+        Ok(self.rank(query)?.into_iter().take(k).collect())
+    }
+
+    /// The top `k` hits among vectors whose metadata satisfies `predicate`; vectors without
+    /// metadata are tested against an empty map.
+    pub fn search_filtered(&self, query: &[f32], k: usize, predicate: impl Fn(&Metadata) -> bool) -> Vec<(String, f32)> {
+        let empty = Metadata::new();
+        self.rank(query).unwrap_or_default().into_iter().filter(|(id, _)| predicate(self.metadata.get(id).unwrap_or(&empty))).take(k).collect()
+    }
+
+    // Every stored id with its score, best first; ties are broken by id so the ranking, and so
+    // every page, is the same on each call.
+    fn rank(&self, query: &[f32]) -> Result<Vec<(String, f32)>> {
+        let query = self.check_dim(query.to_vec()).context("searching")?;
+        let query = query.as_slice();
+        let mut scores: Vec<(String, f32)> = match self.metric {
+            Metric::Cosine => {
+                let q = l2_normalize(query.to_vec());
+                self.store.iter().map(|(id, v)| (id.clone(), cosine_similarity(&q, v))).collect()
+            }
+            Metric::DotProduct => self.store.iter().map(|(id, v)| (id.clone(), dot(query, v))).collect(),
+            Metric::Euclidean => self.store.iter().map(|(id, v)| (id.clone(), euclidean_distance(query, v))).collect(),
+        };
+        if self.metric == Metric::Euclidean {
+            scores.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        } else {
+            scores.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        }
+        Ok(scores)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_string(&PersistedStore { dim: self.dim, store: Cow::Borrowed(&self.store), metric: self.metric, metadata: Cow::Borrowed(&self.metadata) })?;
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Loads a saved store, checking that `embedder` produces vectors of the saved dimension.
+    pub fn load_with(path: &Path, embedder: E) -> Result<Self> {
+        let persisted = read_persisted(path)?;
+        if persisted.dim != embedder.dim() {
+            anyhow::bail!("vector store {} has dim {} but the embedder produces {}", path.display(), persisted.dim, embedder.dim());
+        }
+        Self::from_persisted(path, persisted, embedder)
+    }
+
+    fn from_persisted(path: &Path, persisted: PersistedStore<'static>, embedder: E) -> Result<Self> {
+        let dim = persisted.dim;
+        let metric = persisted.metric;
+        let store = persisted.store.into_owned();
+        let metadata = persisted.metadata.into_owned();
+        if let Some((id, v)) = store.iter().find(|(_, v)| v.len() != dim) {
+            anyhow::bail!("vector store {} declares dim {} but '{}' has {} components", path.display(), dim, id, v.len());
+        }
+        Ok(Self { dim, store, metadata, embedder, metric, dim_policy: DimPolicy::default() })
+    }
+}
+
+fn read_persisted(path: &Path) -> Result<PersistedStore<'static>> {
+    let data = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+// Purely in-memory work completes immediately; the async surface is for network-backed stores.
+#[async_trait]
+impl<E: Embedder> VectorStore for InMemoryVectorStore<E> {
+    async fn upsert(&mut self, id: String, vector: Vec<f32>) -> Result<()> { self.try_upsert(id, vector) }
+
+    async fn search(&self, query: &[f32], k: usize) -> Vec<(String, f32)> { self.search_paged(query, k, 0).await }
+
+    async fn search_paged(&self, query: &[f32], k: usize, offset: usize) -> Vec<(String, f32)> {
+        self.rank(query).unwrap_or_default().into_iter().skip(offset).take(k).collect()
+    }
+
+    async fn search_where(&self, query: &[f32], k: usize, keep: &(dyn for<'s> Fn(&'s str) -> bool + Sync)) -> Vec<(String, f32)> {
+        self.rank(query).unwrap_or_default().into_iter().filter(|(id, _)| keep(id)).take(k).collect()
+    }
+
+    async fn remove(&mut self, id: &str) -> bool {
+        self.metadata.remove(id);
+        self.store.remove(id).is_some()
+    }
+
+    async fn clear(&mut self) {
+        self.store.clear();
+        self.metadata.clear();
     }
+
+    fn embedder(&self) -> &dyn Embedder { &self.embedder }
 }
 
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+pub(crate) fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     let mut dot = 0.0f32;
     let mut na = 0.0f32;
     let mut nb = 0.0f32;
@@ -53,8 +290,191 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     if na == 0.0 || nb == 0.0 { 0.0 } else { dot / (na.sqrt() * nb.sqrt()) }
 }
 
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum::<f32>().sqrt()
+}
+
 fn l2_normalize(mut v: Vec<f32>) -> Vec<f32> {
     let n2: f32 = v.iter().map(|x| x * x).sum();
     if n2 > 0.0 { let norm = n2.sqrt(); for x in &mut v { *x /= norm; } }
     v
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXTS: [&str; 6] = ["graph databases", "vector search", "rust ownership", "python typing", "knowledge graphs", "sparse retrieval"];
+
+    async fn store_of<E: Embedder>(mut store: InMemoryVectorStore<E>) -> InMemoryVectorStore<E> {
+        for (i, text) in TEXTS.iter().enumerate() {
+            let v = store.embedder().embed(text);
+            store.upsert(format!("doc-{}", i), v).await.unwrap();
+        }
+        store
+    }
+
+    #[tokio::test]
+    async fn search_is_unchanged_by_save_and_load() {
+        let store = store_of(InMemoryVectorStore::default().with_metric(Metric::Euclidean)).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vectors.json");
+        store.save(&path).unwrap();
+        let loaded = InMemoryVectorStore::load(&path).unwrap();
+        assert_eq!(loaded.metric(), Metric::Euclidean);
+        for text in ["graph search", "rust", "typing"] {
+            let q = store.embedder().embed(text);
+            assert_eq!(loaded.search(&q, 4).await, store.search(&q, 4).await);
+        }
+    }
+
+    // Counts of three animal words: a tiny embedder whose geometry is easy to reason about.
+    struct Animals;
+
+    impl Embedder for Animals {
+        fn embed(&self, text: &str) -> Vec<f32> {
+            ["cat", "dog", "fish"].iter().map(|w| text.split_whitespace().filter(|t| t == w).count() as f32).collect()
+        }
+
+        fn dim(&self) -> usize { 3 }
+    }
+
+    #[tokio::test]
+    async fn custom_embedder_drives_the_store() {
+        let mut store = InMemoryVectorStore::with_embedder(Animals);
+        assert_eq!(store.dim(), 3);
+        for (id, text) in [("cats", "cat cat"), ("dogs", "dog dog dog"), ("pond", "fish")] {
+            let v = store.embedder().embed(text);
+            store.upsert(id.into(), v).await.unwrap();
+        }
+        let hits = store.search(&store.embedder().embed("a dog"), 1).await;
+        assert_eq!(hits[0].0, "dogs");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("animals.json");
+        store.save(&path).unwrap();
+        assert_eq!(InMemoryVectorStore::load_with(&path, Animals).unwrap().search(&[0.0, 0.0, 1.0], 1).await[0].0, "pond");
+        assert!(InMemoryVectorStore::load_with(&path, ByteHistogramEmbedder::new(8)).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn concurrent_searches_share_the_store() {
+        let store = std::sync::Arc::new(store_of(InMemoryVectorStore::default()).await);
+        let q = store.embedder().embed("graph search");
+        let expected = store.search(&q, 3).await;
+        let tasks: Vec<_> = (0..16)
+            .map(|_| {
+                let (store, q) = (store.clone(), q.clone());
+                tokio::spawn(async move { store.search(&q, 3).await })
+            })
+            .collect();
+        // A timer firing while the searches run shows none of them holds up the runtime.
+        let ticked = tokio::time::timeout(std::time::Duration::from_secs(5), tokio::time::sleep(std::time::Duration::from_millis(1))).await;
+        assert!(ticked.is_ok());
+        for task in tasks { assert_eq!(task.await.unwrap(), expected); }
+    }
+
+    #[tokio::test]
+    async fn ranking_follows_the_metric() {
+        // `big` points the query's way but is long, `near` is close but at an angle, `mid` in between.
+        let vectors = [("big", vec![10.0, 0.0]), ("near", vec![1.0, 0.5]), ("mid", vec![3.0, 2.0])];
+        let mut orders = Vec::new();
+        for metric in [Metric::Cosine, Metric::DotProduct, Metric::Euclidean] {
+            let mut store = InMemoryVectorStore::new(2).with_metric(metric);
+            for (id, v) in &vectors { store.upsert(id.to_string(), v.clone()).await.unwrap(); }
+            orders.push(store.search(&[1.0, 0.0], 3).await.into_iter().map(|(id, _)| id).collect::<Vec<_>>());
+        }
+        assert_eq!(orders, [["big", "near", "mid"], ["big", "mid", "near"], ["near", "mid", "big"]]);
+    }
+
+    #[tokio::test]
+    async fn batch_upsert_matches_one_by_one() {
+        let single = store_of(InMemoryVectorStore::default()).await;
+        let mut batched = InMemoryVectorStore::default();
+        let items = TEXTS.iter().enumerate().map(|(i, text)| (format!("doc-{}", i), batched.embedder().embed(text))).collect();
+        batched.upsert_batch(items).await.unwrap();
+        for text in ["graph search", "rust", "retrieval"] {
+            let q = single.embedder().embed(text);
+            assert_eq!(batched.search(&q, 6).await, single.search(&q, 6).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn removed_ids_leave_search_and_clear_empties() {
+        let mut store = store_of(InMemoryVectorStore::default()).await;
+        let q = store.embedder().embed("vector search");
+        let top = store.search(&q, 1).await[0].0.clone();
+        assert!(store.remove(&top).await);
+        assert!(!store.remove(&top).await);
+        let hits = store.search(&q, 6).await;
+        assert_eq!(hits.len(), 5);
+        assert!(hits.iter().all(|(id, _)| *id != top));
+        store.clear().await;
+        assert!(store.search(&q, 6).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pages_are_disjoint_and_keep_descending_order() {
+        let mut store = store_of(InMemoryVectorStore::default()).await;
+        // Two copies of one text tie, which the id tie-break has to keep on consistent pages.
+        let v = store.embedder().embed(TEXTS[0]);
+        store.upsert("doc-copy".into(), v).await.unwrap();
+        let q = store.embedder().embed("graph search");
+        let all = store.search(&q, 10).await;
+        assert_eq!(all.len(), 7);
+        let mut paged = Vec::new();
+        for offset in (0..8).step_by(2) {
+            let page = store.search_paged(&q, 2, offset).await;
+            assert_eq!(page, store.search_paged(&q, 2, offset).await);
+            paged.extend(page);
+        }
+        assert_eq!(paged, all);
+        assert_eq!(paged.iter().map(|(id, _)| id).collect::<std::collections::HashSet<_>>().len(), paged.len());
+        // Scores never rise, within a page or from one page to the next.
+        assert!(paged.windows(2).all(|w| w[0].1 >= w[1].1));
+        assert!(store.search_paged(&q, 2, 7).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn source_filter_excludes_better_hits_from_other_files() {
+        let mut store = store_of(InMemoryVectorStore::default()).await;
+        for i in 0..TEXTS.len() {
+            let source = if i == 0 { "a.md" } else { "b.md" };
+            store.set_metadata(format!("doc-{}", i), Metadata::from([("source_path".to_string(), source.to_string())]));
+        }
+        // The query is doc-0's own text, so unfiltered it ranks first.
+        let q = store.embedder().embed(TEXTS[0]);
+        assert_eq!(store.search(&q, 1).await[0].0, "doc-0");
+        let from_b = store.search_filtered(&q, 3, |m| m.get("source_path").map(String::as_str) == Some("b.md"));
+        assert_eq!(from_b.len(), 3);
+        assert!(from_b.iter().all(|(id, _)| id != "doc-0"));
+        assert_eq!(from_b, store.search(&q, 4).await[1..]);
+        assert_eq!(store.search_where(&q, 3, &|id: &str| id != "doc-0").await, from_b);
+        assert_eq!(store.search_filtered(&q, 3, |m| m.get("source_path").map(String::as_str) == Some("a.md")).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn mismatched_dimensions_are_rejected_or_fitted() {
+        let mut strict = InMemoryVectorStore::new(3).with_metric(Metric::DotProduct);
+        assert_eq!(strict.try_upsert("short".into(), vec![1.0, 2.0]).unwrap_err().to_string(), "upserting 'short'");
+        assert!(strict.upsert("long".into(), vec![1.0, 2.0, 3.0, 4.0]).await.is_err());
+        strict.upsert("ok".into(), vec![1.0, 0.0, 0.0]).await.unwrap();
+        assert_eq!(strict.search(&[1.0, 0.0, 0.0], 5).await, [("ok".to_string(), 1.0)]);
+        let err = strict.try_search(&[1.0, 0.0], 5).unwrap_err();
+        assert_eq!(format!("{:#}", err), "searching: vector has 2 components but the store's dimension is 3");
+        assert!(strict.search(&[1.0, 0.0], 5).await.is_empty());
+        let batch = vec![("fine".to_string(), vec![0.0, 1.0, 0.0]), ("short".to_string(), vec![1.0])];
+        assert_eq!(format!("{:#}", strict.upsert_batch(batch).await.unwrap_err()), "upserting 'short': vector has 1 components but the store's dimension is 3");
+
+        let mut fit = InMemoryVectorStore::new(3).with_metric(Metric::DotProduct).with_dim_policy(DimPolicy::Fit);
+        fit.try_upsert("short".into(), vec![1.0, 2.0]).unwrap();
+        fit.try_upsert("long".into(), vec![0.0, 1.0, 3.0, 100.0]).unwrap();
+        // The short vector is padded with a zero and the long one loses its fourth component.
+        assert_eq!(fit.try_search(&[0.0, 0.0, 1.0, 50.0], 2).unwrap(), [("long".to_string(), 3.0), ("short".to_string(), 0.0)]);
+        assert_eq!(fit.search(&[1.0, 1.0], 2).await, [("short".to_string(), 3.0), ("long".to_string(), 1.0)]);
+    }
+}
@@ -1,27 +1,138 @@
-#[cfg(feature = "vector-qdrant")]
-pub mod qdrant_store {
-    use qdrant_client::client::QdrantClient;
-    use qdrant_client::qdrant::{vectors_config::Config, Distance, PointStruct, VectorsConfig, CreateCollection, VectorParams};
+use async_trait::async_trait;
+use qdrant_client::qdrant::{value::Kind, CreateCollectionBuilder, DeleteCollectionBuilder, DeletePointsBuilder, Distance, GetPointsBuilder, PointStruct, PointsIdsList, SearchPointsBuilder, UpsertPointsBuilder, VectorParamsBuilder};
+use anyhow::Context;
+use qdrant_client::{Payload, Qdrant};
 
-    use super::super::vector::VectorStore;
+use super::{ByteHistogramEmbedder, Embedder, VectorStore};
 
-    pub struct QdrantStore {
-        pub client: QdrantClient,
-        pub collection: String,
-        pub dim: usize,
+// Qdrant point ids must be integers or UUIDs, so string ids are hashed and kept in this payload field.
+const ID_FIELD: &str = "forge_id";
+
+pub struct QdrantStore<E: Embedder = ByteHistogramEmbedder> {
+    pub client: Qdrant,
+    pub collection: String,
+    pub embedder: E,
+}
+
+impl<E: Embedder> QdrantStore<E> {
+    pub fn new(client: Qdrant, collection: impl Into<String>, embedder: E) -> Self {
+        Self { client, collection: collection.into(), embedder }
+    }
+
+    /// Creates the collection unless it exists; an existing one is left as it is.
+    pub async fn ensure_collection(&self) -> anyhow::Result<()> {
+        if self.client.collection_exists(self.collection.clone()).await.with_context(|| format!("checking for qdrant collection {}", self.collection))? {
+            return Ok(());
+        }
+        let params = VectorParamsBuilder::new(self.embedder.dim() as u64, Distance::Cosine);
+        match self.client.create_collection(CreateCollectionBuilder::new(self.collection.clone()).vectors_config(params)).await {
+            Ok(_) => Ok(()),
+            // Another client created it after the check.
+            Err(e) if e.to_string().contains("already exists") => Ok(()),
+            Err(e) => Err(e).with_context(|| format!("creating qdrant collection {}", self.collection)),
+        }
+    }
+}
+
+#[async_trait]
+impl<E: Embedder> VectorStore for QdrantStore<E> {
+    async fn upsert(&mut self, id: String, vector: Vec<f32>) -> anyhow::Result<()> {
+        let req = UpsertPointsBuilder::new(self.collection.clone(), vec![to_point(&id, vector)]).wait(true);
+        self.client.upsert_points(req).await.with_context(|| format!("qdrant upsert of '{}' into {}", id, self.collection))?;
+        Ok(())
+    }
+
+    async fn upsert_batch(&mut self, items: Vec<(String, Vec<f32>)>) -> anyhow::Result<()> {
+        if items.is_empty() { return Ok(()); }
+        let count = items.len();
+        let points: Vec<PointStruct> = items.into_iter().map(|(id, vector)| to_point(&id, vector)).collect();
+        let req = UpsertPointsBuilder::new(self.collection.clone(), points).wait(true);
+        self.client.upsert_points(req).await.with_context(|| format!("qdrant batch upsert of {} points into {}", count, self.collection))?;
+        Ok(())
+    }
+
+    async fn search(&self, query: &[f32], k: usize) -> Vec<(String, f32)> { self.search_paged(query, k, 0).await }
+
+    async fn search_paged(&self, query: &[f32], k: usize, offset: usize) -> Vec<(String, f32)> {
+        let req = SearchPointsBuilder::new(self.collection.clone(), query.to_vec(), k as u64).offset(offset as u64).with_payload(true);
+        match self.client.search_points(req).await {
+            Ok(resp) => resp
+                .result
+                .into_iter()
+                .filter_map(|p| match p.payload.get(ID_FIELD).and_then(|v| v.kind.as_ref()) {
+                    Some(Kind::StringValue(id)) => Some((id.clone(), p.score)),
+                    _ => None,
+                })
+                .collect(),
+            Err(e) => {
+                eprintln!("qdrant search in {} failed: {}", self.collection, e);
+                vec![]
+            }
+        }
     }
 
-    impl QdrantStore {
-        pub async fn ensure_collection(&self) -> anyhow::Result<()> {
-            let cfg = VectorsConfig { config: Some(Config::Params(VectorParams { size: self.dim as u64, distance: Distance::Cosine as i32, ..Default::default() })) };
-            let _ = self.client.create_collection(&CreateCollection { collection_name: self.collection.clone(), vectors_config: Some(cfg), ..Default::default() }).await;
-            Ok(())
+    async fn remove(&mut self, id: &str) -> bool {
+        let point = crate::fnv1a(id.as_bytes());
+        let existed = match self.client.get_points(GetPointsBuilder::new(self.collection.clone(), vec![point.into()])).await {
+            Ok(resp) => !resp.result.is_empty(),
+            Err(e) => {
+                eprintln!("qdrant lookup of '{}' in {} failed: {}", id, self.collection, e);
+                return false;
+            }
+        };
+        if !existed { return false; }
+        let req = DeletePointsBuilder::new(self.collection.clone()).points(PointsIdsList::from(vec![point])).wait(true);
+        match self.client.delete_points(req).await {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!("qdrant delete of '{}' from {} failed: {}", id, self.collection, e);
+                false
+            }
+        }
+    }
+
+    async fn clear(&mut self) {
+        // Dropping and recreating the collection is cheaper than deleting every point.
+        if let Err(e) = self.client.delete_collection(DeleteCollectionBuilder::new(self.collection.clone())).await {
+            eprintln!("qdrant delete of collection {} failed: {}", self.collection, e);
+        }
+        if let Err(e) = self.ensure_collection().await {
+            eprintln!("qdrant recreation of collection {} failed: {:#}", self.collection, e);
         }
     }
 
-    impl VectorStore for QdrantStore {
-        fn upsert(&mut self, id: String, vector: Vec<f32>) { let _ = (id, vector); }
-        fn search(&self, _query: &[f32], _k: usize) -> Vec<(String, f32)> { vec![] }
-        fn embed_text(&self, text: &str) -> Vec<f32> { let mut v = vec![0f32; self.dim]; for &b in text.as_bytes() { v[(b as usize) % self.dim] += 1.0; } v }
+    fn embedder(&self) -> &dyn Embedder { &self.embedder }
+}
+
+fn to_point(id: &str, vector: Vec<f32>) -> PointStruct {
+    let mut payload = Payload::new();
+    payload.insert(ID_FIELD, id.to_string());
+    PointStruct::new(crate::fnv1a(id.as_bytes()), vector, payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs against a live server, e.g. FORGE_QDRANT_URL=http://localhost:6334 cargo test -- --ignored.
+    #[tokio::test]
+    #[ignore = "needs FORGE_QDRANT_URL"]
+    async fn upsert_search_remove_round_trip() {
+        let url = std::env::var("FORGE_QDRANT_URL").expect("FORGE_QDRANT_URL");
+        let client = Qdrant::from_url(&url).build().unwrap();
+        let mut store = QdrantStore::new(client, format!("forge-test-{}", std::process::id()), ByteHistogramEmbedder::new(32));
+        store.ensure_collection().await.unwrap();
+        // A second call finds the collection and leaves it be.
+        store.ensure_collection().await.unwrap();
+        for (id, text) in [("a", "graph databases"), ("b", "vector search"), ("c", "rust ownership")] {
+            let v = store.embedder.embed(text);
+            store.upsert(id.into(), v).await.unwrap();
+        }
+        let q = store.embedder.embed("vector search");
+        assert_eq!(store.search(&q, 1).await[0].0, "b");
+        assert_eq!(store.search_paged(&q, 2, 1).await.len(), 2);
+        assert!(store.remove("b").await);
+        assert!(!store.search(&q, 3).await.iter().any(|(id, _)| id == "b"));
+        store.client.delete_collection(DeleteCollectionBuilder::new(store.collection.clone())).await.unwrap();
     }
 }